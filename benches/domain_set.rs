@@ -0,0 +1,250 @@
+//! Memory use and time for holding a large blocklist in a `DomainSet`, against the
+//! `HashSet<Host>` and sorted `Vec<Host>` it replaced.
+//!
+//! Run with `cargo bench --bench domain_set`, which uses a synthetic corpus of 2,000,000 domains.
+//! Without the `--bench` argument (as under `cargo test --all-targets`), a small corpus is used,
+//! just to check the benchmark still runs.
+//!
+//! Memory figures count the bytes requested from the allocator, so they leave out the
+//! allocator's own overhead for each allocation, which falls on every `String` in a
+//! `HashSet<Host>`.
+
+#![warn(clippy::all, clippy::pedantic)]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashSet,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use ahash::RandomState;
+use blocklist_generator::domain_set::DomainSet;
+use humansize::{DECIMAL, format_size};
+use num_format::{Locale, ToFormattedString};
+use url::Host;
+
+const BENCH_DOMAIN_COUNT: usize = 2_000_000;
+const CHECK_DOMAIN_COUNT: usize = 10_000;
+const TLDS: [&str; 8] = ["com", "net", "org", "io", "co.uk", "de", "info", "xyz"];
+
+/// Global allocator which keeps count of bytes currently allocated and the peak since the last
+/// reset.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let result = unsafe { System.alloc(layout) };
+        if !result.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocated() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+fn reset_peak() {
+    PEAK.store(allocated(), Ordering::Relaxed);
+}
+
+fn peak() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// Deterministic xorshift generator, so every run benchmarks the same corpus.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit as u64) as usize
+    }
+}
+
+fn push_label(rng: &mut Rng, name: &mut String) {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-";
+    let length = 3 + rng.below(12);
+    for index in 0..length {
+        // labels cannot start or end with a hyphen
+        let limit = if index == 0 || index == length - 1 {
+            ALPHABET.len() - 1
+        } else {
+            ALPHABET.len()
+        };
+        name.push(char::from(ALPHABET[rng.below(limit)]));
+    }
+}
+
+/// `count` domain names, shaped like blocklist entries: one to three labels under a registrable
+/// domain, with many sharing a parent.
+fn corpus(count: usize) -> Vec<String> {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut parents: Vec<String> = Vec::with_capacity(count / 8);
+    let mut result = Vec::with_capacity(count);
+    while result.len() < count {
+        let mut name = String::new();
+        let parent = if parents.is_empty() || rng.below(4) == 0 {
+            let mut parent = String::new();
+            push_label(&mut rng, &mut parent);
+            parent.push('.');
+            parent.push_str(TLDS[rng.below(TLDS.len())]);
+            parents.push(parent.clone());
+            result.push(parent.clone());
+            parent
+        } else {
+            parents[rng.below(parents.len())].clone()
+        };
+        for _ in 0..=rng.below(3) {
+            push_label(&mut rng, &mut name);
+            name.push('.');
+        }
+        name.push_str(&parent);
+        result.push(name);
+    }
+    result.truncate(count);
+
+    result
+}
+
+/// Timings and memory use for one representation.
+struct Measurement {
+    build: Duration,
+    sort: Duration,
+    lookup: Duration,
+    retained: usize,
+    peak: usize,
+}
+
+fn measure_hash_set(corpus: &[String]) -> Measurement {
+    let baseline = allocated();
+    reset_peak();
+
+    let start = Instant::now();
+    let mut set: HashSet<Host, RandomState> = HashSet::with_hasher(RandomState::new());
+    for name in corpus {
+        set.insert(Host::Domain(name.clone()));
+    }
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    let mut sorted: Vec<&Host> = set.iter().collect();
+    sorted.sort_unstable();
+    black_box(&sorted);
+    let sort = start.elapsed();
+    let retained = allocated() - baseline;
+    drop(sorted);
+
+    let start = Instant::now();
+    let found = corpus
+        .iter()
+        .filter(|val| set.contains(&Host::Domain((*val).clone())))
+        .count();
+    black_box(found);
+    let lookup = start.elapsed();
+
+    let peak = peak() - baseline;
+    drop(set);
+
+    Measurement {
+        build,
+        sort,
+        lookup,
+        retained,
+        peak,
+    }
+}
+
+fn measure_domain_set(corpus: &[String]) -> Measurement {
+    let baseline = allocated();
+    reset_peak();
+
+    let start = Instant::now();
+    let mut set = DomainSet::new();
+    for name in corpus {
+        set.insert(Host::Domain(name.clone()));
+    }
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    set.sort();
+    black_box(set.iter().count());
+    let sort = start.elapsed();
+    let retained = allocated() - baseline;
+
+    let start = Instant::now();
+    let found = corpus
+        .iter()
+        .filter(|val| set.contains(&Host::Domain(val.as_str())))
+        .count();
+    black_box(found);
+    let lookup = start.elapsed();
+
+    let peak = peak() - baseline;
+    drop(set);
+
+    Measurement {
+        build,
+        sort,
+        lookup,
+        retained,
+        peak,
+    }
+}
+
+fn print_row(name: &str, value: &Measurement) {
+    println!(
+        "{name:<16} {:>10.2?} {:>10.2?} {:>10.2?} {:>12} {:>12}",
+        value.build,
+        value.sort,
+        value.lookup,
+        format_size(value.retained, DECIMAL),
+        format_size(value.peak, DECIMAL),
+    );
+}
+
+fn main() {
+    let count = if std::env::args().any(|val| val == "--bench") {
+        BENCH_DOMAIN_COUNT
+    } else {
+        CHECK_DOMAIN_COUNT
+    };
+    let corpus = corpus(count);
+
+    let hash_set = measure_hash_set(&corpus);
+    let domain_set = measure_domain_set(&corpus);
+
+    println!(
+        "{} synthetic domains (sort includes building a sorted Vec for HashSet)\n",
+        count.to_formatted_string(&Locale::en)
+    );
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>12} {:>12}",
+        "", "build", "sort", "lookup", "retained", "peak"
+    );
+    print_row("HashSet<Host>", &hash_set);
+    print_row("DomainSet", &domain_set);
+}
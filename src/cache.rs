@@ -0,0 +1,156 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain_set::DomainSet, error::Error, file_system::read_domain_list_file, metadata::sha256_hex,
+};
+
+const STATE_FILE_NAME: &str = "state.toml";
+
+/// Details of the last good fetch of a source.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CachedSource {
+    /// Distinct domains parsed from the source
+    pub entries: usize,
+
+    /// UTC timestamp in RFC 3339 format
+    pub fetched_at: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheState {
+    sources: BTreeMap<String, CachedSource>,
+}
+
+/// Last good copy of each source, kept between runs so a broken upstream list can be compared
+/// with, or replaced by, what it returned before.
+#[derive(Debug)]
+pub struct SourceCache {
+    directory: PathBuf,
+    state: CacheState,
+}
+
+impl SourceCache {
+    /// Open the cache in `directory`, creating the directory if it does not exist yet.
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, Error> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(|error| {
+            Error::io(
+                format!("Failed to create cache directory `{}`", directory.display()),
+                error,
+            )
+        })?;
+        let state_path = directory.join(STATE_FILE_NAME);
+        let state = match fs::read_to_string(&state_path) {
+            Ok(value) => toml::from_str(&value).map_err(|error| Error::CacheState {
+                message: format!(
+                    "Failed to parse cache state file `{}`.  Delete it to start afresh.",
+                    state_path.display()
+                ),
+                source: Box::new(error),
+            })?,
+            Err(_) => CacheState::default(),
+        };
+
+        Ok(SourceCache { directory, state })
+    }
+
+    fn source_path(&self, url: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.txt", sha256_hex(url.as_bytes())))
+    }
+
+    /// Distinct domain count from the last good fetch of `url`.
+    pub fn previous_entries(&self, url: &str) -> Option<usize> {
+        self.state.sources.get(url).map(|val| val.entries)
+    }
+
+    /// Domains from the last good fetch of `url`, if there is a cached copy.
+    pub fn domains(&self, url: &str) -> Option<DomainSet> {
+        read_domain_list_file(self.source_path(url))
+    }
+
+    /// Keep `domains`, in the order the source listed them, as the last good copy of `url`.  Call
+    /// [`SourceCache::save`] to record the entry count for the next run.
+    pub fn store(&mut self, url: &str, domains: &DomainSet) -> Result<(), Error> {
+        let content = domains.iter().fold(String::new(), |mut acc, val| {
+            let _ = writeln!(acc, "{val}");
+            acc
+        });
+        let source_path = self.source_path(url);
+        fs::write(&source_path, content).map_err(|error| {
+            Error::io(
+                format!("Failed to write cache file `{}`", source_path.display()),
+                error,
+            )
+        })?;
+        self.state.sources.insert(
+            url.to_string(),
+            CachedSource {
+                entries: domains.len(),
+                fetched_at: jiff::Timestamp::now()
+                    .strftime("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let state_path = self.directory.join(STATE_FILE_NAME);
+        let content = toml::to_string(&self.state).map_err(|error| Error::CacheState {
+            message: String::from("Failed to serialise cache state"),
+            source: Box::new(error),
+        })?;
+        fs::write(&state_path, content).map_err(|error| {
+            Error::io(
+                format!(
+                    "Failed to write cache state file `{}`",
+                    state_path.display()
+                ),
+                error,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::TempDir;
+    use url::Host;
+
+    use crate::{cache::SourceCache, domain_set::DomainSet};
+
+    #[test]
+    fn source_cache_keeps_domains_and_entries_between_runs() {
+        // arrange
+        let temp_dir = TempDir::new().unwrap();
+        let url = "https://example.com/domains.txt";
+        let domains: DomainSet = ["ads.example.com", "tracker.example.com"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect();
+        let mut cache = SourceCache::load(temp_dir.path()).unwrap();
+        cache.store(url, &domains).unwrap();
+        cache.save().unwrap();
+
+        // act
+        let outcome = SourceCache::load(temp_dir.path()).unwrap();
+
+        // assert
+        assert_eq!(outcome.previous_entries(url), Some(2));
+        assert_eq!(outcome.domains(url), Some(domains));
+        assert_eq!(
+            outcome.previous_entries("https://example.com/other.txt"),
+            None
+        );
+        assert_eq!(outcome.domains("https://example.com/other.txt"), None);
+    }
+}
@@ -1,18 +1,57 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
-use clap::Parser;
+use blocklist_generator::diff::DiffFormat;
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[clap(author,version,about,long_about=None)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Config file path (default: ./blocklist-generator.toml)
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, global = true)]
     pub config: Option<PathBuf>,
 
     /// (default: 3)
     #[clap(short, long, value_parser)]
     pub max_concurrent_downloads: Option<u32>,
 
+    /// Threads parsing downloaded sources (default: number of CPUs)
+    #[clap(long, value_parser, global = true)]
+    pub parse_workers: Option<usize>,
+
+    /// Largest accepted source size in MiB, after any decompression (default: 256)
+    #[clap(long, value_parser, global = true)]
+    pub max_source_size: Option<usize>,
+
+    /// Lock file held while writing outputs, so two runs cannot write at once (default: the
+    /// config file path, with `.lock` added)
+    #[clap(long, value_parser, global = true)]
+    pub lock_file: Option<PathBuf>,
+
+    /// Write outputs even if they fail the `[guard]` checks.  With `init`, overwrite an existing
+    /// config file
+    #[clap(long, global = true)]
+    pub force: bool,
+
+    /// Report domains added and removed since the previous domain list output
+    #[clap(long, global = true)]
+    pub diff: bool,
+
+    /// Change report format for `--diff` (default: text)
+    #[clap(long, value_enum, global = true)]
+    pub diff_format: Option<ChangeReportFormat>,
+
+    /// Write the `--diff` change report to this file, instead of printing it
+    #[clap(long, value_parser, global = true)]
+    pub diff_output: Option<PathBuf>,
+
+    /// Write run metrics to this file after each successful run, in the Prometheus text format,
+    /// for the node exporter textfile collector
+    #[clap(long, value_parser, global = true)]
+    pub metrics_file: Option<PathBuf>,
+
     /// Generate Markdown documentation for app
     #[arg(long, hide = true)]
     pub markdown_help: bool,
@@ -21,6 +60,114 @@ pub struct Cli {
     verbose: clap_verbosity_flag::Verbosity,
 }
 
+/// Change report formats, as [`DiffFormat`].
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ChangeReportFormat {
+    /// Summary with domains grouped by source
+    #[default]
+    Text,
+
+    /// Summary with domains grouped by source, as JSON
+    Json,
+
+    /// Unified diff of the sorted domain lists
+    Unified,
+}
+
+impl From<ChangeReportFormat> for DiffFormat {
+    fn from(value: ChangeReportFormat) -> Self {
+        match value {
+            ChangeReportFormat::Text => DiffFormat::Text,
+            ChangeReportFormat::Json => DiffFormat::Json,
+            ChangeReportFormat::Unified => DiffFormat::Unified,
+        }
+    }
+}
+
+/// Groups of sources in the `init` catalogue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SourceCategory {
+    Ads,
+    Tracking,
+    Malware,
+    Adult,
+    Gambling,
+}
+
+/// DNS resolvers `init` can add outputs for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Resolver {
+    Bind,
+    Knot,
+    Unbound,
+    AdguardHome,
+    Pihole,
+    Blocky,
+    Coredns,
+    Powerdns,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Report domains added and removed between two domain list files
+    Diff {
+        /// Previous domain list file
+        old: PathBuf,
+
+        /// New domain list file
+        new: PathBuf,
+
+        /// Change report format (default: text)
+        #[clap(short, long, value_enum)]
+        format: Option<ChangeReportFormat>,
+    },
+
+    /// Inspect the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Check the config file, without fetching sources or writing outputs
+    Validate,
+
+    /// Write a commented starter config file, at the `--config` path
+    Init {
+        /// Categories of catalogue sources to include (default: ads, tracking and malware)
+        #[clap(long = "category", value_enum, value_delimiter = ',')]
+        categories: Vec<SourceCategory>,
+
+        /// DNS resolvers to write blocklists for (default: RPZ, Unbound and plain domain list
+        /// outputs)
+        #[clap(long = "resolver", value_enum, value_delimiter = ',')]
+        resolvers: Vec<Resolver>,
+
+        /// List the catalogue sources, without writing a config file
+        #[clap(long)]
+        list: bool,
+    },
+
+    /// Keep running, regenerating the blocklists on the `[schedule]` interval and whenever the
+    /// config changes.  SIGHUP reloads the config and regenerates; SIGTERM stops, once any run in
+    /// progress finishes
+    Serve {
+        /// Seconds between runs, overriding `[schedule] interval`
+        #[clap(long)]
+        interval: Option<u64>,
+
+        /// Address to serve the latest outputs on over HTTP, with `/status`, `/check?domain=` and
+        /// `/metrics` endpoints, such as `0.0.0.0:8080`
+        #[clap(long)]
+        listen: Option<SocketAddr>,
+    },
+
+    /// Fetch sources and write their current SHA-256 hashes to the config file as pins
+    Pin {
+        /// Names or URLs of sources to pin (default: all enabled sources)
+        sources: Vec<String>,
+    },
+}
+
 impl Cli {
     /// Set logging filter level filter from user-supplied verbosity flags.
     pub fn initialise_logging(&self) {
@@ -29,3 +176,14 @@ impl Cli {
             .init();
     }
 }
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the config file
+    Show {
+        /// Print the effective config, with includes merged, environment overrides applied and
+        /// secrets masked
+        #[clap(long)]
+        resolved: bool,
+    },
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use toml::{Table, Value};
+
+use crate::{error::Error, file_system::Config};
+
+/// Top-level key listing glob patterns of other config files to merge in.
+const INCLUDE_KEY: &str = "include";
+
+/// Prefix of environment variables which override config values.
+const ENVIRONMENT_PREFIX: &str = "BLOCKLIST_GENERATOR_";
+
+/// Shown in place of secret values.
+const MASK: &str = "********";
+
+/// Config file which is not valid TOML, or does not match the config format.
+#[derive(Debug, Diagnostic, thiserror::Error)]
+#[error("Failed to parse config file `{path}`: {message}")]
+#[diagnostic(code(config::parse))]
+pub struct ConfigParseError {
+    path: String,
+    message: String,
+    #[source_code]
+    source_code: NamedSource<String>,
+    #[label("{label}")]
+    span: Option<SourceSpan>,
+    label: String,
+}
+
+impl ConfigParseError {
+    fn new(path: &Path, content: String, error: &toml::de::Error) -> Self {
+        let span = error.span();
+        let key = span
+            .as_ref()
+            .and_then(|val| key_path_at(&content, val.start));
+        let label = match key {
+            Some(value) => format!("in `{value}`"),
+            None => String::from("here"),
+        };
+        ConfigParseError {
+            path: path.display().to_string(),
+            message: error.message().trim().to_string(),
+            source_code: NamedSource::new(path.display().to_string(), content),
+            span: span.map(SourceSpan::from),
+            label,
+        }
+    }
+}
+
+fn join_key_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn span_contains(span: Option<std::ops::Range<usize>>, offset: usize) -> bool {
+    span.is_some_and(|val| val.contains(&offset))
+}
+
+fn value_key_path_at(value: &toml_edit::Value, offset: usize, path: &str) -> Option<String> {
+    let nested = match value {
+        toml_edit::Value::Array(array) => array.iter().enumerate().find_map(|(index, element)| {
+            value_key_path_at(element, offset, &format!("{path}[{index}]"))
+        }),
+        toml_edit::Value::InlineTable(table) => table.iter().find_map(|(key, element)| {
+            value_key_path_at(element, offset, &join_key_path(path, key))
+        }),
+        _ => None,
+    };
+
+    nested.or_else(|| span_contains(value.span(), offset).then(|| path.to_string()))
+}
+
+fn table_key_path_at(table: &toml_edit::Table, offset: usize, path: &str) -> Option<String> {
+    let nested = table.iter().find_map(|(key, item)| {
+        let key_path = join_key_path(path, key);
+        let found = match item {
+            toml_edit::Item::Value(value) => value_key_path_at(value, offset, &key_path),
+            toml_edit::Item::Table(child) => table_key_path_at(child, offset, &key_path),
+            toml_edit::Item::ArrayOfTables(array) => {
+                array.iter().enumerate().find_map(|(index, child)| {
+                    table_key_path_at(child, offset, &format!("{key_path}[{index}]"))
+                })
+            }
+            toml_edit::Item::None => None,
+        };
+        let on_key = table
+            .key(key)
+            .is_some_and(|val| span_contains(val.span(), offset));
+
+        found.or_else(|| on_key.then_some(key_path))
+    });
+
+    nested.or_else(|| {
+        (!path.is_empty() && span_contains(table.span(), offset)).then(|| path.to_string())
+    })
+}
+
+/// Dotted path of the most deeply nested key at byte `offset` of TOML `content`, such as
+/// `outputs[1].format`.
+fn key_path_at(content: &str, offset: usize) -> Option<String> {
+    let document = toml_edit::Document::parse(content).ok()?;
+
+    table_key_path_at(document.as_table(), offset, "")
+}
+
+/// Environment variables of the running process, skipping any which are not valid Unicode.
+#[must_use]
+pub fn process_environment() -> BTreeMap<String, String> {
+    env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .collect()
+}
+
+/// Merge `overlay` into `base`.  Tables merge key by key, arrays are appended and any other
+/// value in `overlay` replaces the one in `base`.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, overlay_value) in overlay {
+        match (base.get_mut(&key), overlay_value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (Some(Value::Array(base_array)), Value::Array(overlay_array)) => {
+                base_array.extend(overlay_array);
+            }
+            (_, overlay_value) => {
+                base.insert(key, overlay_value);
+            }
+        }
+    }
+}
+
+/// Parse the config file at `path`, first merging in any files it includes, in path order.
+/// Values in the including file take precedence.  Each file read is added to `read_paths`.
+fn load_table(
+    path: &Path,
+    visited: &mut Vec<PathBuf>,
+    read_paths: &mut Vec<PathBuf>,
+) -> Result<Table, Error> {
+    let read_error = |error| {
+        Error::io(
+            format!("Failed to open or read config file `{}`", path.display()),
+            error,
+        )
+    };
+    let canonical_path = fs::canonicalize(path).map_err(read_error)?;
+    if visited.contains(&canonical_path) {
+        return Err(Error::Config(format!(
+            "Config file `{}` includes itself, directly or through another file",
+            path.display()
+        )));
+    }
+    let content = fs::read_to_string(path).map_err(read_error)?;
+    read_paths.push(path.to_path_buf());
+    // parse as `Config` first, for errors pointing at the offending part of this file
+    let mut table =
+        match toml::from_str::<Config>(&content).and_then(|_| toml::from_str::<Table>(&content)) {
+            Ok(value) => value,
+            Err(error) => return Err(ConfigParseError::new(path, content, &error).into()),
+        };
+    let Some(include_value) = table.remove(INCLUDE_KEY) else {
+        return Ok(table);
+    };
+    let Value::Array(patterns) = include_value else {
+        return Err(Error::Config(format!(
+            "`include` in config file `{}` should be an array of glob patterns",
+            path.display()
+        )));
+    };
+
+    visited.push(canonical_path);
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut result = Table::new();
+    for pattern in patterns {
+        let Value::String(pattern) = pattern else {
+            return Err(Error::Config(format!(
+                "`include` in config file `{}` should be an array of glob patterns",
+                path.display()
+            )));
+        };
+        let full_pattern = directory.join(&pattern);
+        let mut included_paths = glob::glob(&full_pattern.to_string_lossy())
+            .map_err(|error| {
+                Error::Config(format!("Invalid include pattern `{pattern}`: {error}"))
+            })?
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(|error| {
+                Error::io(
+                    format!("Failed to read files matching `{pattern}`"),
+                    error.into_error(),
+                )
+            })?;
+        if included_paths.is_empty() {
+            log::warn!("No config files match include pattern `{pattern}`");
+        }
+        included_paths.sort();
+        for included_path in included_paths {
+            merge_tables(
+                &mut result,
+                load_table(&included_path, visited, read_paths)?,
+            );
+        }
+    }
+    visited.pop();
+    merge_tables(&mut result, table);
+
+    Ok(result)
+}
+
+/// Config key path for an overlay environment variable, so
+/// `BLOCKLIST_GENERATOR_GUARD__MAX_CHANGE_PERCENT` becomes `guard.max_change_percent`.
+fn overlay_key_path(name: &str) -> Option<Vec<String>> {
+    let key = name.strip_prefix(ENVIRONMENT_PREFIX)?;
+    let result: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+    if result.iter().any(String::is_empty) {
+        return None;
+    }
+
+    Some(result)
+}
+
+/// Value set by an overlay environment variable: a TOML value (number, boolean, array or inline
+/// table) if it parses as one, otherwise a string.
+fn overlay_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut val| val.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// Set config values from `BLOCKLIST_GENERATOR_*` variables in `environment`.  These replace
+/// values from config files, rather than merging with them.
+fn apply_environment_overlay(
+    table: &mut Table,
+    environment: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    for (name, raw_value) in environment {
+        let Some(key_path) = overlay_key_path(name) else {
+            continue;
+        };
+        let Some((last_key, parent_keys)) = key_path.split_last() else {
+            continue;
+        };
+        let mut parent = &mut *table;
+        for key in parent_keys {
+            let entry = parent
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            let Value::Table(entry_table) = entry else {
+                return Err(Error::Config(format!(
+                    "Environment variable `{name}` sets a key inside `{key}`, which is not a table"
+                )));
+            };
+            parent = entry_table;
+        }
+        parent.insert(last_key.clone(), overlay_value(raw_value));
+    }
+
+    Ok(())
+}
+
+/// Replace each `${NAME}` in `value` with the value of environment variable `NAME`, or with a
+/// mask when `mask_secrets` is set.  `$${` gives a literal `${`.
+fn interpolate_str(
+    value: &str,
+    environment: &BTreeMap<String, String>,
+    mask_secrets: bool,
+) -> Result<String, Error> {
+    let mut result = String::with_capacity(value.len());
+    let mut remaining = value;
+    while let Some(start) = remaining.find('$') {
+        result.push_str(&remaining[..start]);
+        let rest = &remaining[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            remaining = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                return Err(Error::Config(format!(
+                    "Unclosed `${{` in config value `{value}`"
+                )));
+            };
+            let name = &after[..end];
+            let Some(variable_value) = environment.get(name) else {
+                return Err(Error::Config(format!(
+                    "Environment variable `{name}`, used in config, is not set"
+                )));
+            };
+            result.push_str(if mask_secrets { MASK } else { variable_value });
+            remaining = &after[end + 1..];
+        } else {
+            result.push('$');
+            remaining = &rest[1..];
+        }
+    }
+    result.push_str(remaining);
+
+    Ok(result)
+}
+
+fn interpolate(
+    value: &mut Value,
+    environment: &BTreeMap<String, String>,
+    mask_secrets: bool,
+) -> Result<(), Error> {
+    match value {
+        Value::String(string_value) => {
+            *string_value = interpolate_str(string_value, environment, mask_secrets)?;
+        }
+        Value::Array(array_value) => {
+            for element in array_value {
+                interpolate(element, environment, mask_secrets)?;
+            }
+        }
+        Value::Table(table_value) => {
+            for (_, element) in table_value {
+                interpolate(element, environment, mask_secrets)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Mask request header values in `[[sources]]` entries, which often hold credentials.
+fn mask_headers(table: &mut Table) {
+    let Some(Value::Array(sources)) = table.get_mut("sources") else {
+        return;
+    };
+    for source in sources {
+        if let Value::Table(source_table) = source
+            && let Some(Value::Table(headers)) = source_table.get_mut("headers")
+        {
+            for (_, header_value) in headers {
+                *header_value = Value::String(MASK.to_string());
+            }
+        }
+    }
+}
+
+/// Keys of `[blocklists]` which list source URLs.
+const BLOCKLIST_URL_KEYS: [&str; 2] = ["hosts_file_blocklist_urls", "domain_blocklist_urls"];
+
+/// Source URLs in `table`: from `[[sources]]` entries, then from `[blocklists]`.
+fn source_urls(table: &Table) -> Vec<&str> {
+    let sources = table
+        .get("sources")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|val| val.get("url").and_then(Value::as_str));
+    let blocklists = table.get("blocklists");
+    let blocklist_urls = BLOCKLIST_URL_KEYS
+        .iter()
+        .filter_map(|key| {
+            blocklists
+                .and_then(|val| val.get(key))
+                .and_then(Value::as_array)
+        })
+        .flatten()
+        .map(Value::as_str);
+
+    sources.chain(blocklist_urls).flatten().collect()
+}
+
+/// Config file at `path`, with included files merged in, then values from
+/// `BLOCKLIST_GENERATOR_*` environment variables.
+fn load_with_overlay(path: &Path, environment: &BTreeMap<String, String>) -> Result<Table, Error> {
+    let mut result = load_table(path, &mut Vec::new(), &mut Vec::new())?;
+    apply_environment_overlay(&mut result, environment)?;
+
+    Ok(result)
+}
+
+/// Effective config from the file at `path`: included files merged in, then values from
+/// `BLOCKLIST_GENERATOR_*` environment variables, then `${NAME}` references replaced.  With
+/// `mask_secrets` set, interpolated values and request headers are masked, for display.
+///
+/// # Errors
+///
+/// Returns an error if a config file cannot be read or parsed, includes form a cycle, or an
+/// environment override or reference cannot be applied.
+pub fn resolve_config_table<P: AsRef<Path>>(
+    path: P,
+    environment: &BTreeMap<String, String>,
+    mask_secrets: bool,
+) -> Result<Table, Error> {
+    let mut result = load_with_overlay(path.as_ref(), environment)?;
+    for (_, value) in &mut result {
+        interpolate(value, environment, mask_secrets)?;
+    }
+    if mask_secrets {
+        mask_headers(&mut result);
+    }
+
+    Ok(result)
+}
+
+/// Effective config, as [`resolve_config_table`] gives it without masking, along with each
+/// source URL which references environment variables, as written, keyed by the URL it resolves
+/// to.
+pub(crate) fn resolve_config_table_with_url_templates<P: AsRef<Path>>(
+    path: P,
+    environment: &BTreeMap<String, String>,
+) -> Result<(Table, BTreeMap<String, String>), Error> {
+    let templates = load_with_overlay(path.as_ref(), environment)?;
+    let mut result = templates.clone();
+    for (_, value) in &mut result {
+        interpolate(value, environment, false)?;
+    }
+    // interpolation only changes strings, so URLs are in the same order in both tables
+    let url_templates = source_urls(&result)
+        .into_iter()
+        .zip(source_urls(&templates))
+        .filter(|(url, template)| url != template)
+        .map(|(url, template)| (url.to_string(), template.to_string()))
+        .collect();
+
+    Ok((result, url_templates))
+}
+
+/// Paths of the config file at `path` and each file it includes, directly or through another
+/// file, in the order they are read.
+///
+/// # Errors
+///
+/// Returns an error if a config file cannot be read or parsed, or includes form a cycle.
+pub fn config_file_paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+    let mut result = Vec::new();
+    load_table(path.as_ref(), &mut Vec::new(), &mut result)?;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{
+        Error,
+        config::{interpolate_str, resolve_config_table},
+    };
+
+    #[test]
+    fn interpolate_str_replaces_environment_references() {
+        // arrange
+        let environment = BTreeMap::from([(String::from("TOKEN"), String::from("s3cret"))]);
+
+        // act
+        let outcome = interpolate_str(
+            "https://example.com/list?token=${TOKEN}&price=$5&raw=$${TOKEN}",
+            &environment,
+            false,
+        )
+        .unwrap();
+
+        // assert
+        assert_eq!(
+            outcome,
+            "https://example.com/list?token=s3cret&price=$5&raw=${TOKEN}"
+        );
+        let outcome = interpolate_str("${MISSING}", &environment, false).unwrap_err();
+        assert_eq!(
+            format!("{outcome}"),
+            "Environment variable `MISSING`, used in config, is not set"
+        );
+    }
+
+    #[test]
+    fn resolve_config_table_merges_includes_and_environment() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let _ = temp_dir.child("blocklist-generator.toml").write_str(
+            r#"include = ["sources.d/*.toml"]
+
+[[sources]]
+name = "Private list"
+url = "https://lists.example.com/hosts?token=${LIST_TOKEN}"
+format = "hosts-file"
+headers = { Authorization = "Bearer secret" }
+
+[filters]
+allowed_names = ["example.com"]
+"#,
+        );
+        let _ = temp_dir.child("sources.d/10-ads.toml").write_str(
+            r#"[[sources]]
+name = "OISD big"
+url = "https://big.oisd.nl/domainswild"
+format = "domain-list"
+
+[filters]
+allowed_names = ["another.example.com"]
+"#,
+        );
+        let _ = temp_dir
+            .child("sources.d/20-guard.toml")
+            .write_str("[guard]\nmax_change_percent = 50\n");
+        let environment = BTreeMap::from([
+            (String::from("LIST_TOKEN"), String::from("s3cret")),
+            (
+                String::from("BLOCKLIST_GENERATOR_GUARD__MAX_CHANGE_PERCENT"),
+                String::from("20"),
+            ),
+        ]);
+        let config_path = temp_dir.join("blocklist-generator.toml");
+
+        // act
+        let outcome = resolve_config_table(&config_path, &environment, true).unwrap();
+
+        // assert
+        insta::assert_snapshot!(toml::to_string_pretty(&outcome).unwrap());
+        let outcome = resolve_config_table(&config_path, &environment, false).unwrap();
+        assert_eq!(
+            outcome["sources"][1]["url"].as_str(),
+            Some("https://lists.example.com/hosts?token=s3cret")
+        );
+    }
+
+    #[test]
+    fn resolve_config_table_reports_offending_key() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let _ = temp_dir.child("blocklist-generator.toml").write_str(
+            r#"[[outputs]]
+format = "domain-list"
+
+[[outputs]]
+format = "rpz"
+header = "yes"
+"#,
+        );
+        let config_path = temp_dir.join("blocklist-generator.toml");
+
+        // act
+        let outcome = resolve_config_table(&config_path, &BTreeMap::new(), false).unwrap_err();
+
+        // assert
+        let Error::ConfigParse(outcome) = outcome else {
+            panic!("Expected a config parse error, got: {outcome}");
+        };
+        assert_eq!(outcome.label, "in `outputs[1].header`");
+        assert_eq!(outcome.span.map(|val| val.offset()), Some(72));
+        assert!(outcome.message.starts_with("invalid type: string \"yes\""));
+    }
+}
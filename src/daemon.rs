@@ -0,0 +1,311 @@
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use ahash::RandomState;
+use blocklist_generator::{
+    Pipeline,
+    file_system::{Config, Schedule},
+    metadata::sha256_hex,
+};
+use log::{error, info, warn};
+use tokio::{
+    net::TcpListener,
+    time::{Instant, interval_at, sleep_until},
+};
+
+use crate::{
+    cli::Cli,
+    load_config,
+    lock::OutputLock,
+    lock_file_path, pipeline, print_run_report,
+    server::{self, ServerState},
+};
+
+/// Time between checks of the config for changes, while waiting for the next run.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Signal {
+    Hangup,
+    Terminate,
+}
+
+/// SIGHUP and SIGTERM (or Ctrl-C), as they arrive.
+struct Signals {
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        Ok(Signals {
+            hangup: signal(SignalKind::hangup())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.hangup.recv() => Signal::Hangup,
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = tokio::signal::ctrl_c() => Signal::Terminate,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Signals {
+    #[expect(clippy::unnecessary_wraps)]
+    fn new() -> std::io::Result<Self> {
+        Ok(Signals {})
+    }
+
+    async fn recv(&mut self) -> Signal {
+        let _ = tokio::signal::ctrl_c().await;
+        Signal::Terminate
+    }
+}
+
+/// Digest of the config with includes and environment overrides applied, so a change to an
+/// included file counts as a change too.
+fn config_digest(config: &Config) -> String {
+    sha256_hex(&serde_json::to_vec(config).unwrap_or_default())
+}
+
+/// Watches the config file for changes.
+struct ConfigWatch<'a> {
+    path: &'a Path,
+    digest: String,
+
+    /// Last problem logged, so each problem is only logged once
+    last_error: Option<String>,
+}
+
+impl<'a> ConfigWatch<'a> {
+    fn new(path: &'a Path, config: &Config) -> Self {
+        ConfigWatch {
+            path,
+            digest: config_digest(config),
+            last_error: None,
+        }
+    }
+
+    /// Config loaded from the file, if it differs from the one running.  A config with problems
+    /// is logged and ignored, keeping the running one.
+    fn reload(&mut self) -> Option<Config> {
+        match load_config(self.path) {
+            Ok(config) => {
+                self.last_error = None;
+                let digest = config_digest(&config);
+                if digest == self.digest {
+                    return None;
+                }
+                self.digest = digest;
+
+                Some(config)
+            }
+            Err(report) => {
+                let message = format!("{report:?}");
+                if self.last_error.as_ref() != Some(&message) {
+                    error!(
+                        "Keeping the running config, as the config file has problems:\n{message}"
+                    );
+                    self.last_error = Some(message);
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// Why `serve` stopped waiting for the next scheduled run.
+enum Wake {
+    Scheduled,
+
+    /// Reload requested, with the new config, if it changed
+    Reload(Option<Box<Config>>),
+    Stop,
+}
+
+/// Time until the next run: `interval` seconds, or the `[schedule]` interval, plus a random part
+/// of the `[schedule]` jitter.
+fn next_run_delay(schedule: Option<&Schedule>, interval: Option<u64>) -> Duration {
+    let default_schedule = Schedule::default();
+    let schedule = schedule.unwrap_or(&default_schedule);
+    let interval = interval.map_or_else(|| schedule.interval(), Duration::from_secs);
+    let jitter = schedule.jitter().as_secs();
+    let random = RandomState::new().hash_one(());
+
+    interval + Duration::from_secs(random % jitter.saturating_add(1))
+}
+
+/// Run `pipeline` once, holding the lock, returning any signal received meanwhile.  A failed run
+/// is logged, and the outputs from the last good run are left in place.
+async fn run_once(
+    cli: &Cli,
+    pipeline: &Pipeline,
+    lock_path: &Path,
+    signals: &mut Signals,
+    server: Option<&ServerState>,
+) -> Option<Signal> {
+    let _lock = match OutputLock::try_acquire(lock_path) {
+        Ok(Some(value)) => value,
+        Ok(None) => {
+            warn!(
+                "Skipping this run, as another run holds the lock file `{}`",
+                lock_path.display()
+            );
+            return None;
+        }
+        Err(error) => {
+            error!("Skipping this run: {error:#}");
+            return None;
+        }
+    };
+    let run = pipeline.run();
+    tokio::pin!(run);
+    let mut received: Option<Signal> = None;
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut run => break outcome,
+            signal = signals.recv(), if received != Some(Signal::Terminate) => {
+                info!("Received {signal:?}, which is handled once the current run finishes");
+                received = Some(signal);
+            }
+        }
+    };
+    match outcome {
+        Ok(report) => {
+            if let Err(error) = print_run_report(cli, &report) {
+                error!("{error:#}");
+            }
+            if let Some(value) = server {
+                value.publish(report);
+            }
+        }
+        Err(error) => {
+            error!("Run failed, so the outputs from the last good run are kept: {error}");
+            if let Some(value) = server {
+                value.record_failure(&error);
+            }
+        }
+    }
+
+    received
+}
+
+/// Wait `delay` for the next run, checking the config for changes meanwhile.
+async fn wait(delay: Duration, watch: &mut ConfigWatch<'_>, signals: &mut Signals) -> Wake {
+    info!("Next run in {} seconds", delay.as_secs());
+    let deadline = Instant::now() + delay;
+    let mut config_checks = interval_at(
+        Instant::now() + CONFIG_CHECK_INTERVAL,
+        CONFIG_CHECK_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            () = sleep_until(deadline) => return Wake::Scheduled,
+            _ = config_checks.tick() => {
+                if let Some(config) = watch.reload() {
+                    info!("Config changed, so regenerating now");
+                    return Wake::Reload(Some(Box::new(config)));
+                }
+            }
+            signal = signals.recv() => match signal {
+                Signal::Hangup => {
+                    info!("Received SIGHUP, so reloading the config and regenerating now");
+                    return Wake::Reload(watch.reload().map(Box::new));
+                }
+                Signal::Terminate => return Wake::Stop,
+            },
+        }
+    }
+}
+
+/// Regenerate the blocklists for `config`, loaded from `config_path`, now and then every
+/// `interval` seconds (default: the `[schedule]` interval), and whenever the config changes,
+/// until SIGTERM.  With `listen` set, the outputs of the latest good run are served over HTTP on
+/// that address.
+pub async fn serve(
+    cli: &Cli,
+    config_path: &Path,
+    config: Config,
+    interval: Option<u64>,
+    listen: Option<SocketAddr>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut signals = Signals::new()?;
+    let lock_path = lock_file_path(cli, config_path);
+    let server = match listen {
+        Some(address) => {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|error| format!("Failed to listen on `{address}`: {error}"))?;
+            info!("Serving outputs on http://{address}");
+            let state = Arc::new(ServerState::default());
+            tokio::spawn(server::listen(listener, Arc::clone(&state)));
+            Some(state)
+        }
+        None => None,
+    };
+    let mut watch = ConfigWatch::new(config_path, &config);
+    let mut running = pipeline(cli, config_path, config)?;
+    loop {
+        let wake = match run_once(cli, &running, &lock_path, &mut signals, server.as_deref()).await
+        {
+            Some(Signal::Terminate) => Wake::Stop,
+            Some(Signal::Hangup) => Wake::Reload(watch.reload().map(Box::new)),
+            None => {
+                let delay = next_run_delay(running.config().schedule.as_ref(), interval);
+                wait(delay, &mut watch, &mut signals).await
+            }
+        };
+        match wake {
+            Wake::Scheduled | Wake::Reload(None) => {}
+            Wake::Reload(Some(config)) => match pipeline(cli, config_path, *config) {
+                Ok(value) => running = value,
+                Err(error) => error!("Keeping the running config: {error}"),
+            },
+            Wake::Stop => {
+                info!("Stopping");
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use blocklist_generator::file_system::Schedule;
+
+    use crate::daemon::next_run_delay;
+
+    #[test]
+    fn next_run_delay_adds_at_most_the_jitter_to_the_interval() {
+        // arrange
+        let schedule = Schedule {
+            interval: Some(3_600),
+            jitter: Some(60),
+        };
+
+        // act
+        let outcome: Vec<Duration> = (0..100)
+            .map(|_| next_run_delay(Some(&schedule), None))
+            .collect();
+        let outcome_override = next_run_delay(None, Some(120));
+
+        // assert
+        assert!(
+            outcome
+                .iter()
+                .all(|val| (3_600..=3_660).contains(&val.as_secs()))
+        );
+        assert_eq!(outcome_override, Duration::from_secs(120));
+    }
+}
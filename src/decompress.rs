@@ -0,0 +1,222 @@
+use std::io::Read;
+
+use log::debug;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DecompressError {
+    #[error("Decompressed data is larger than the {limit} byte limit")]
+    SizeLimit { limit: usize },
+
+    #[error("Unable to decompress data: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Compression formats recognised for fetched source bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    fn from_magic_bytes(body: &[u8]) -> Option<Self> {
+        match body {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Compression::Xz),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    fn from_extension(url: &str) -> Option<Self> {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Identify the compression used for `body` fetched from `url`.  Magic bytes decide, since a
+    /// server might already have decoded a `.gz` file using `Content-Encoding`.
+    pub fn detect(url: &str, body: &[u8]) -> Option<Self> {
+        let result = Compression::from_magic_bytes(body);
+        if result.is_none()
+            && let Some(extension_compression) = Compression::from_extension(url)
+        {
+            debug!(
+                "`{url}` has a {extension_compression:?} file extension, but is not compressed.  \
+                Treating it as plain text."
+            );
+        }
+
+        result
+    }
+}
+
+/// Reads a body sent a chunk at a time over a channel.  Reads block until the next chunk
+/// arrives, so only use it on a blocking thread.
+pub struct ChannelReader {
+    receiver: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+
+    /// Bytes of `chunk` already read
+    position: usize,
+}
+
+impl ChannelReader {
+    pub fn new(receiver: tokio::sync::mpsc::Receiver<Vec<u8>>) -> Self {
+        ChannelReader {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            let Some(chunk) = self.receiver.blocking_recv() else {
+                return Ok(0);
+            };
+            self.chunk = chunk;
+            self.position = 0;
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Size of each block read from a decoder and passed on.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Read from `reader` a block at a time, passing each block to `output`, and failing once more
+/// than `limit` bytes come out, so a small, highly compressed body cannot exhaust memory.
+fn read_to_limit<R: Read>(
+    mut reader: R,
+    limit: usize,
+    output: &mut dyn FnMut(&[u8]),
+) -> Result<(), DecompressError> {
+    let mut block = vec![0; BLOCK_SIZE];
+    let mut total: usize = 0;
+    loop {
+        let length = match reader.read(&mut block) {
+            Ok(0) => break,
+            Ok(value) => value,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        };
+        total += length;
+        if total > limit {
+            return Err(DecompressError::SizeLimit { limit });
+        }
+        output(&block[..length]);
+    }
+
+    Ok(())
+}
+
+/// Decompress `body`, passing the output to `output` a block at a time, rather than holding all
+/// of it in memory.
+pub fn decompress<R: Read>(
+    body: R,
+    compression: Compression,
+    limit: usize,
+    output: &mut dyn FnMut(&[u8]),
+) -> Result<(), DecompressError> {
+    match compression {
+        Compression::Gzip => read_to_limit(flate2::read::MultiGzDecoder::new(body), limit, output),
+        Compression::Zstd => read_to_limit(zstd::stream::read::Decoder::new(body)?, limit, output),
+        Compression::Xz => read_to_limit(
+            liblzma::read::XzDecoder::new_multi_decoder(body),
+            limit,
+            output,
+        ),
+        Compression::Bzip2 => read_to_limit(bzip2::read::MultiBzDecoder::new(body), limit, output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::decompress::{Compression, DecompressError, decompress};
+
+    const PLAIN_TEXT: &[u8] = b"# comment\nexample.com\nanother.example.com\n";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detect_identifies_compression_from_magic_bytes() {
+        // arrange
+        let gzip_body = gzip(PLAIN_TEXT);
+        let zstd_body = zstd::encode_all(PLAIN_TEXT, 3).unwrap();
+        let mut xz_encoder = liblzma::write::XzEncoder::new(Vec::new(), 6);
+        xz_encoder.write_all(PLAIN_TEXT).unwrap();
+        let xz_body = xz_encoder.finish().unwrap();
+        let mut bzip2_encoder =
+            bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        bzip2_encoder.write_all(PLAIN_TEXT).unwrap();
+        let bzip2_body = bzip2_encoder.finish().unwrap();
+
+        // act
+        let outcome_gzip = Compression::detect("https://example.com/list", &gzip_body);
+        let outcome_zstd = Compression::detect("https://example.com/list", &zstd_body);
+        let outcome_xz = Compression::detect("https://example.com/list", &xz_body);
+        let outcome_bzip2 = Compression::detect("https://example.com/list", &bzip2_body);
+        let outcome_plain = Compression::detect("https://example.com/list.gz", PLAIN_TEXT);
+
+        // assert
+        assert_eq!(outcome_gzip, Some(Compression::Gzip));
+        assert_eq!(outcome_zstd, Some(Compression::Zstd));
+        assert_eq!(outcome_xz, Some(Compression::Xz));
+        assert_eq!(outcome_bzip2, Some(Compression::Bzip2));
+        assert_eq!(outcome_plain, None);
+    }
+
+    #[test]
+    fn decompress_returns_original_data() {
+        // arrange
+        let body = gzip(PLAIN_TEXT);
+
+        let mut outcome: Vec<u8> = Vec::new();
+
+        // act
+        decompress(&body[..], Compression::Gzip, 1024, &mut |val| {
+            outcome.extend_from_slice(val);
+        })
+        .unwrap();
+
+        // assert
+        assert_eq!(outcome, PLAIN_TEXT);
+    }
+
+    #[test]
+    fn decompress_returns_error_when_output_exceeds_limit() {
+        // arrange
+        let body = zstd::encode_all(&[b'a'; 4096][..], 3).unwrap();
+
+        // act
+        let outcome = decompress(&body[..], Compression::Zstd, 1024, &mut |_| {}).unwrap_err();
+
+        // assert
+        assert!(matches!(
+            outcome,
+            DecompressError::SizeLimit { limit: 1024 }
+        ));
+    }
+}
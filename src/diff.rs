@@ -0,0 +1,301 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs,
+    path::Path,
+};
+
+use ahash::RandomState;
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+use similar::TextDiff;
+use url::Host;
+
+use crate::{domain_set::DomainSet, error::Error, parse::domainlist as parse_domainlist};
+
+/// Change report formats.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum DiffFormat {
+    /// Summary with domains grouped by source
+    #[default]
+    Text,
+
+    /// Summary with domains grouped by source, as JSON
+    Json,
+
+    /// Unified diff of the sorted domain lists
+    Unified,
+}
+
+/// Source which first listed each domain in a run, as an index into the run's sources.
+pub type SourceAttribution = HashMap<Host, usize, RandomState>;
+
+/// Domains added or removed, all listed by the same source.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DiffGroup {
+    /// `None` when the source is not known
+    pub source: Option<String>,
+    pub domains: Vec<String>,
+}
+
+/// Changes between two blocklists.
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub old_count: usize,
+    pub new_count: usize,
+    pub added_count: usize,
+    pub removed_count: usize,
+    pub added: Vec<DiffGroup>,
+    pub removed: Vec<DiffGroup>,
+}
+
+/// Group `domains` by the source which listed them.  Groups follow source order, with domains
+/// from unknown sources last.
+fn group_by_source(
+    domains: Vec<Host<&str>>,
+    attribution: Option<(&SourceAttribution, &[&str])>,
+) -> Vec<DiffGroup> {
+    let mut groups: BTreeMap<Option<usize>, Vec<String>> = BTreeMap::new();
+    for domain in domains {
+        let source_index = attribution.and_then(|(val, _)| val.get(&domain.to_owned()).copied());
+        groups
+            .entry(source_index)
+            .or_default()
+            .push(domain.to_string());
+    }
+    let unknown_group = groups.remove(&None);
+
+    groups
+        .into_iter()
+        .map(|(source_index, domains)| DiffGroup {
+            source: source_index.and_then(|index| {
+                attribution.and_then(|(_, names)| names.get(index).map(ToString::to_string))
+            }),
+            domains,
+        })
+        .chain(unknown_group.map(|domains| DiffGroup {
+            source: None,
+            domains,
+        }))
+        .collect()
+}
+
+impl DiffReport {
+    /// Compare `old` and `new`, both sorted with [`DomainSet::sort`].  `attribution`, with the
+    /// source names it indexes, groups added domains by the source which listed them.
+    #[must_use]
+    pub fn new(
+        old: &DomainSet,
+        new: &DomainSet,
+        attribution: Option<(&SourceAttribution, &[&str])>,
+    ) -> Self {
+        let mut added: Vec<Host<&str>> = Vec::new();
+        let mut removed: Vec<Host<&str>> = Vec::new();
+        let mut old_hosts = old.iter().peekable();
+        let mut new_hosts = new.iter().peekable();
+        loop {
+            match (old_hosts.peek(), new_hosts.peek()) {
+                (Some(old_value), Some(new_value)) => match old_value.cmp(new_value) {
+                    Ordering::Less => removed.extend(old_hosts.next()),
+                    Ordering::Greater => added.extend(new_hosts.next()),
+                    Ordering::Equal => {
+                        old_hosts.next();
+                        new_hosts.next();
+                    }
+                },
+                (Some(_), None) => removed.extend(old_hosts.next()),
+                (None, Some(_)) => added.extend(new_hosts.next()),
+                (None, None) => break,
+            }
+        }
+
+        DiffReport {
+            old_count: old.len(),
+            new_count: new.len(),
+            added_count: added.len(),
+            removed_count: removed.len(),
+            added: group_by_source(added, attribution),
+            removed: group_by_source(removed, None),
+        }
+    }
+
+    fn write_groups(result: &mut String, title: &str, marker: char, groups: &[DiffGroup]) {
+        let count: usize = groups.iter().map(|val| val.domains.len()).sum();
+        let _ = writeln!(
+            result,
+            "\n{title} ({}):",
+            count.to_formatted_string(&Locale::en)
+        );
+        for DiffGroup { source, domains } in groups {
+            let _ = writeln!(
+                result,
+                "  {} ({}):",
+                source.as_deref().unwrap_or("Unknown source"),
+                domains.len().to_formatted_string(&Locale::en)
+            );
+            for domain in domains {
+                let _ = writeln!(result, "    {marker} {domain}");
+            }
+        }
+    }
+
+    /// Plain text summary, suitable for posting to chat.
+    #[must_use]
+    pub fn text(&self) -> String {
+        let mut result = format!(
+            "{} added, {} removed ({} domains, previously {})\n",
+            self.added_count.to_formatted_string(&Locale::en),
+            self.removed_count.to_formatted_string(&Locale::en),
+            self.new_count.to_formatted_string(&Locale::en),
+            self.old_count.to_formatted_string(&Locale::en),
+        );
+        if !self.added.is_empty() {
+            DiffReport::write_groups(&mut result, "Added", '+', &self.added);
+        }
+        if !self.removed.is_empty() {
+            DiffReport::write_groups(&mut result, "Removed", '-', &self.removed);
+        }
+
+        result
+    }
+
+    /// Summary, as JSON.  Its string and count fields always serialise.
+    #[must_use]
+    pub fn json(&self) -> String {
+        let mut result = serde_json::to_string_pretty(self).unwrap_or_default();
+        result.push('\n');
+
+        result
+    }
+}
+
+/// Unified diff of the sorted domain lists `old` and `new`, for attaching to a pull request.
+#[must_use]
+pub fn unified(old_name: &str, new_name: &str, old: &DomainSet, new: &DomainSet) -> String {
+    let to_lines = |domains: &DomainSet| -> String {
+        domains.iter().fold(String::new(), |mut acc, val| {
+            let _ = writeln!(acc, "{val}");
+            acc
+        })
+    };
+    let old_lines = to_lines(old);
+    let new_lines = to_lines(new);
+
+    TextDiff::from_lines(&old_lines, &new_lines)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Sorted domains from a plain list file, such as a previous `domain-list` output.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn read_sorted_domain_list(path: &Path) -> Result<DomainSet, Error> {
+    let content = fs::read_to_string(path).map_err(|error| {
+        Error::io(
+            format!("Failed to open or read domain list `{}`", path.display()),
+            error,
+        )
+    })?;
+    let mut result = DomainSet::new();
+    parse_domainlist(&content, &mut result);
+    result.sort();
+
+    Ok(result)
+}
+
+/// Report changes from the sorted domain list `old` to `new` in `format`.  `attribution`, with
+/// the source names it indexes, groups added domains by source in the text and JSON formats.
+#[must_use]
+pub fn report(
+    format: DiffFormat,
+    (old_name, old): (&str, &DomainSet),
+    (new_name, new): (&str, &DomainSet),
+    attribution: Option<(&SourceAttribution, &[&str])>,
+) -> String {
+    match format {
+        DiffFormat::Text => DiffReport::new(old, new, attribution).text(),
+        DiffFormat::Json => DiffReport::new(old, new, attribution).json(),
+        DiffFormat::Unified => unified(old_name, new_name, old, new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Host;
+
+    use crate::{
+        diff::{DiffGroup, DiffReport, SourceAttribution, unified},
+        domain_set::DomainSet,
+    };
+
+    fn hosts(names: &[&str]) -> DomainSet {
+        let mut result: DomainSet = names.iter().map(|val| Host::parse(val).unwrap()).collect();
+        result.sort();
+
+        result
+    }
+
+    #[test]
+    fn diff_report_groups_added_domains_by_source() {
+        // arrange
+        let old = hosts(&["ads.example.com", "old.example.com", "tracker.example.com"]);
+        let new = hosts(&[
+            "ads.example.com",
+            "malware.example.net",
+            "new.example.com",
+            "tracker.example.com",
+            "unlisted.example.org",
+        ]);
+        let mut attribution = SourceAttribution::default();
+        attribution.insert(Host::parse("new.example.com").unwrap(), 1);
+        attribution.insert(Host::parse("malware.example.net").unwrap(), 0);
+        let source_names = ["https://example.com/malware.txt", "https://example.com/ads"];
+
+        // act
+        let outcome = DiffReport::new(&old, &new, Some((&attribution, &source_names)));
+
+        // assert
+        assert_eq!(outcome.added_count, 3);
+        assert_eq!(outcome.removed_count, 1);
+        assert_eq!(
+            outcome.added,
+            vec![
+                DiffGroup {
+                    source: Some(String::from("https://example.com/malware.txt")),
+                    domains: vec![String::from("malware.example.net")],
+                },
+                DiffGroup {
+                    source: Some(String::from("https://example.com/ads")),
+                    domains: vec![String::from("new.example.com")],
+                },
+                DiffGroup {
+                    source: None,
+                    domains: vec![String::from("unlisted.example.org")],
+                },
+            ]
+        );
+        insta::assert_snapshot!(outcome.text());
+    }
+
+    #[test]
+    fn unified_returns_unified_diff_of_domain_lists() {
+        // arrange
+        let old = hosts(&["ads.example.com", "old.example.com"]);
+        let new = hosts(&["ads.example.com", "new.example.com"]);
+
+        // act
+        let outcome = unified(
+            "old/domain-blocklist.txt",
+            "new/domain-blocklist.txt",
+            &old,
+            &new,
+        );
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+}
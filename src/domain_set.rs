@@ -0,0 +1,573 @@
+//! Compact set of hosts, for holding millions of blocked domains.
+//!
+//! A [`DomainSet`] keeps the names of all its hosts one after another in a single buffer, with a
+//! small fixed-size entry for each host, rather than a separate `String` for every [`Host`].
+//! Entries stay in the order hosts were added until [`DomainSet::sort`] puts them in order, so
+//! there is no need to copy the hosts into a `Vec` to sort them.  [`DomainSet::sort_in`] puts
+//! them in any [`SortOrder`], including back in the order they were added.
+//!
+//! ```
+//! use blocklist_generator::domain_set::DomainSet;
+//! use url::Host;
+//!
+//! let mut set = DomainSet::new();
+//! set.insert(Host::parse("tracker.example.net").unwrap());
+//! set.insert(Host::parse("ads.example.com").unwrap());
+//! set.insert(Host::parse("ads.example.com").unwrap());
+//! set.sort();
+//!
+//! let names: Vec<String> = set.iter().map(|val| val.to_string()).collect();
+//! assert_eq!(names, ["ads.example.com", "tracker.example.net"]);
+//! ```
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use ahash::RandomState;
+use hashbrown::HashTable;
+use serde::{Deserialize, Serialize};
+use url::Host;
+
+/// Order of the hosts in a [`DomainSet`], and of the domains in an output file.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// By name (`a.example.com`, `a.example.net`, `b.example.com`), as sorted [`Host`] values
+    #[default]
+    Lexical,
+
+    /// By name read from the top-level domain down (`com.example.a`), so each domain is next to
+    /// its subdomains (`a.example.com`, `b.example.com`, `a.example.net`)
+    ReverseLabel,
+
+    /// In the order the hosts were added, which is the order sources list them in
+    Source,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Domain,
+    Ipv4,
+    Ipv6,
+
+    /// Left in place by [`DomainSet::remove`], until the next [`DomainSet::sort`]
+    Removed,
+}
+
+/// Where the name of one host is in [`DomainSet::names`].
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    start: u32,
+    length: u16,
+    kind: Kind,
+}
+
+fn entry_name(names: &str, entry: Entry) -> &str {
+    let start = entry.start as usize;
+    &names[start..start + usize::from(entry.length)]
+}
+
+fn entry_host(names: &str, entry: Entry) -> Host<&str> {
+    let name = entry_name(names, entry);
+    match entry.kind {
+        Kind::Ipv4 => Host::Ipv4(name.parse().unwrap_or(Ipv4Addr::UNSPECIFIED)),
+        Kind::Ipv6 => Host::Ipv6(name.parse().unwrap_or(Ipv6Addr::UNSPECIFIED)),
+        Kind::Domain => Host::Domain(name),
+        // a removed host keeps its place among the others, so it needs its original kind back
+        Kind::Removed => match name.parse() {
+            Ok(IpAddr::V4(value)) => Host::Ipv4(value),
+            Ok(IpAddr::V6(value)) => Host::Ipv6(value),
+            Err(_) => Host::Domain(name),
+        },
+    }
+}
+
+/// Domains compared label by label from the top-level domain down.  IP addresses keep their
+/// [`Host`] order, after every domain.
+fn reverse_label_cmp(a: &Host<&str>, b: &Host<&str>) -> Ordering {
+    match (a, b) {
+        (Host::Domain(a_name), Host::Domain(b_name)) => a_name.rsplit('.').cmp(b_name.rsplit('.')),
+        _ => a.cmp(b),
+    }
+}
+
+/// Kind and name of `host`, as stored.
+fn host_key<S: AsRef<str>>(host: &Host<S>) -> (Kind, Cow<'_, str>) {
+    match host {
+        Host::Domain(value) => (Kind::Domain, Cow::Borrowed(value.as_ref())),
+        Host::Ipv4(value) => (Kind::Ipv4, Cow::Owned(value.to_string())),
+        Host::Ipv6(value) => (Kind::Ipv6, Cow::Owned(value.to_string())),
+    }
+}
+
+/// Set of hosts, with the names held in one shared buffer.
+#[derive(Clone, Default)]
+pub struct DomainSet {
+    /// Name of every host added, one after another
+    names: String,
+
+    /// One for each host added, in the order added, or sorted
+    entries: Vec<Entry>,
+
+    /// Positions in `entries`, looked up by name
+    index: HashTable<u32>,
+    hasher: RandomState,
+    len: usize,
+
+    /// Order of `entries`, until another host is added
+    order: Option<SortOrder>,
+}
+
+impl DomainSet {
+    #[must_use]
+    pub fn new() -> Self {
+        DomainSet::default()
+    }
+
+    /// Empty set, with room for `capacity` hosts before it needs to grow its index.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        DomainSet {
+            entries: Vec::with_capacity(capacity),
+            index: HashTable::with_capacity(capacity),
+            ..DomainSet::default()
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn find(&self, name: &str) -> Option<Entry> {
+        let hash = self.hasher.hash_one(name);
+        self.index
+            .find(hash, |&position| {
+                entry_name(&self.names, self.entries[position as usize]) == name
+            })
+            .map(|&position| self.entries[position as usize])
+    }
+
+    /// Add `host`, returning `false` if it was already in the set.  A name longer than 65,535
+    /// bytes, far beyond the 253 byte limit for DNS names, is not added, and neither is any host
+    /// once the names of all the hosts added come to 4 GiB.
+    #[expect(clippy::needless_pass_by_value)]
+    pub fn insert<S: AsRef<str>>(&mut self, host: Host<S>) -> bool {
+        let (kind, name) = host_key(&host);
+        let DomainSet {
+            names,
+            entries,
+            index,
+            hasher,
+            len,
+            order,
+        } = self;
+        let hash = hasher.hash_one(&*name);
+        if index
+            .find(hash, |&position| {
+                entry_name(names, entries[position as usize]) == name
+            })
+            .is_some()
+        {
+            return false;
+        }
+        let Ok(length) = u16::try_from(name.len()) else {
+            log::warn!("Skipping host name longer than 65,535 bytes");
+            return false;
+        };
+        let (Ok(start), Ok(position), Ok(_)) = (
+            u32::try_from(names.len()),
+            u32::try_from(entries.len()),
+            u32::try_from(names.len() + name.len()),
+        ) else {
+            log::warn!("Skipping host, as the set already holds 4 GiB of names");
+            return false;
+        };
+        names.push_str(&name);
+        entries.push(Entry {
+            start,
+            length,
+            kind,
+        });
+        index.insert_unique(hash, position, |&val| {
+            hasher.hash_one(entry_name(names, entries[val as usize]))
+        });
+        *len += 1;
+        *order = None;
+
+        true
+    }
+
+    pub fn contains<S: AsRef<str>>(&self, host: &Host<S>) -> bool {
+        self.find(&host_key(host).1).is_some()
+    }
+
+    /// Remove `host`, returning `false` if it was not in the set.  Its name stays in the shared
+    /// buffer, and is only freed by [`DomainSet::clear`], so a set which has many hosts removed
+    /// and added again keeps growing.
+    pub fn remove<S: AsRef<str>>(&mut self, host: &Host<S>) -> bool {
+        let (_, name) = host_key(host);
+        let DomainSet {
+            names,
+            entries,
+            index,
+            hasher,
+            len,
+            ..
+        } = self;
+        let hash = hasher.hash_one(&*name);
+        let Ok(entry) = index.find_entry(hash, |&position| {
+            entry_name(names, entries[position as usize]) == name
+        }) else {
+            return false;
+        };
+        let (position, _) = entry.remove();
+        entries[position as usize].kind = Kind::Removed;
+        *len -= 1;
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.names.clear();
+        self.entries.clear();
+        self.index.clear();
+        self.len = 0;
+        self.order = None;
+    }
+
+    /// Hosts in the order they were added, or in order after [`DomainSet::sort`].
+    #[must_use]
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            names: &self.names,
+            entries: self.entries.iter(),
+        }
+    }
+
+    /// Put the hosts in [`SortOrder::Lexical`] order (the order of sorted [`Host`] values), for
+    /// [`DomainSet::iter`].  Sorting also drops removed hosts and any spare capacity, so call it
+    /// once all the hosts are in.
+    pub fn sort(&mut self) {
+        self.sort_in(SortOrder::Lexical);
+    }
+
+    /// Put the hosts in `order`, as [`DomainSet::sort`] does.  Does nothing if the hosts are
+    /// already in `order`, with none added since.
+    pub fn sort_in(&mut self, order: SortOrder) {
+        let DomainSet {
+            names,
+            entries,
+            index,
+            hasher,
+            order: current_order,
+            ..
+        } = self;
+        if *current_order == Some(order) {
+            return;
+        }
+        entries.retain(|val| val.kind != Kind::Removed);
+        entries.shrink_to_fit();
+        names.shrink_to_fit();
+        match order {
+            SortOrder::Lexical => {
+                entries.sort_unstable_by(|a, b| entry_host(names, *a).cmp(&entry_host(names, *b)));
+            }
+            SortOrder::ReverseLabel => entries.sort_unstable_by(|a, b| {
+                reverse_label_cmp(&entry_host(names, *a), &entry_host(names, *b))
+            }),
+            // names are added to the end of `names`, so they start in the order added
+            SortOrder::Source => entries.sort_unstable_by_key(|val| val.start),
+        }
+        index.clear();
+        index.shrink_to_fit(|&val| hasher.hash_one(entry_name(names, entries[val as usize])));
+        for (position, entry) in (0_u32..).zip(entries.iter()) {
+            index.insert_unique(
+                hasher.hash_one(entry_name(names, *entry)),
+                position,
+                |&val| hasher.hash_one(entry_name(names, entries[val as usize])),
+            );
+        }
+        *current_order = Some(order);
+    }
+
+    /// Closest parent domain of `host` in the set, not counting top-level domains, so for
+    /// `ads.tracker.example.com`, `tracker.example.com` is checked before `example.com`.
+    pub fn listed_parent<S: AsRef<str>>(&self, host: &Host<S>) -> Option<Host<&str>> {
+        let Host::Domain(domain) = host else {
+            return None;
+        };
+        let mut name = domain.as_ref();
+        while let Some((_, parent)) = name.split_once('.') {
+            if !parent.contains('.') {
+                break;
+            }
+            if let Some(entry) = self.find(parent) {
+                return Some(entry_host(&self.names, entry));
+            }
+            name = parent;
+        }
+
+        None
+    }
+
+    /// Hosts in the set which are subdomains of `domain`, at any depth.  Puts the hosts in
+    /// [`SortOrder::ReverseLabel`] order first, if they are not already, since that keeps each
+    /// domain next to its subdomains, so they can be found with a binary search.
+    pub fn subdomains<'a>(
+        &'a mut self,
+        domain: &'a str,
+    ) -> impl Iterator<Item = Host<&'a str>> + 'a {
+        self.sort_in(SortOrder::ReverseLabel);
+        let DomainSet { names, entries, .. } = self;
+        let names: &'a str = names;
+        let domain_host = Host::Domain(domain);
+        // `domain` itself, if present, comes just before its subdomains
+        let start = entries.partition_point(|val| {
+            reverse_label_cmp(&entry_host(names, *val), &domain_host) != Ordering::Greater
+        });
+        entries[start..]
+            .iter()
+            .take_while(move |val| {
+                matches!(entry_host(names, **val), Host::Domain(name)
+                    if name.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.')))
+            })
+            .filter(|val| val.kind != Kind::Removed)
+            .map(|val| entry_host(names, *val))
+    }
+}
+
+/// Iterator over the hosts in a [`DomainSet`].
+pub struct Iter<'a> {
+    names: &'a str,
+    entries: std::slice::Iter<'a, Entry>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Host<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .by_ref()
+            .find(|val| val.kind != Kind::Removed)
+            .map(|val| entry_host(self.names, *val))
+    }
+}
+
+impl<'a> IntoIterator for &'a DomainSet {
+    type Item = Host<&'a str>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<S: AsRef<str>> Extend<Host<S>> for DomainSet {
+    fn extend<I: IntoIterator<Item = Host<S>>>(&mut self, iter: I) {
+        for host in iter {
+            self.insert(host);
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<Host<S>> for DomainSet {
+    fn from_iter<I: IntoIterator<Item = Host<S>>>(iter: I) -> Self {
+        let mut result = DomainSet::new();
+        result.extend(iter);
+
+        result
+    }
+}
+
+impl PartialEq for DomainSet {
+    /// Sets are equal when they hold the same hosts, in any order.
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|val| other.contains(&val))
+    }
+}
+
+impl Eq for DomainSet {}
+
+impl fmt::Debug for DomainSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Host;
+
+    use crate::domain_set::{DomainSet, SortOrder};
+
+    fn domain_set(names: &[&str]) -> DomainSet {
+        names.iter().map(|val| Host::parse(val).unwrap()).collect()
+    }
+
+    #[test]
+    fn insert_skips_hosts_already_in_set() {
+        // arrange
+        let mut set = domain_set(&["example.com", "127.0.0.1"]); // DevSkim: ignore DS162092 - use of local host IP is in test
+
+        // act
+        let outcome_domain = set.insert(Host::parse("example.com").unwrap());
+        let outcome_ip = set.insert(Host::parse("127.0.0.1").unwrap()); // DevSkim: ignore DS162092 - use of local host IP is in test
+        let outcome_new = set.insert(Host::parse("ads.example.com").unwrap());
+
+        // assert
+        assert!(!outcome_domain);
+        assert!(!outcome_ip);
+        assert!(outcome_new);
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&Host::parse("127.0.0.1").unwrap())); // DevSkim: ignore DS162092 - use of local host IP is in test
+    }
+
+    #[test]
+    fn sort_orders_hosts_as_sorted_host_values_are_ordered() {
+        // arrange
+        let names = [
+            "tracker.example.org",
+            "[::1]",
+            "ads.example.com",
+            "10.0.0.1",
+            "9.9.9.9",
+            "example.com",
+        ];
+        let mut set = domain_set(&names);
+        set.remove(&Host::parse("example.com").unwrap());
+        let mut expected: Vec<Host> = names[..5]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect();
+        expected.sort();
+
+        // act
+        set.sort();
+
+        // assert
+        let outcome: Vec<Host> = set.iter().map(|val| val.to_owned()).collect();
+        assert_eq!(outcome, expected);
+        assert!(set.contains(&Host::parse("9.9.9.9").unwrap()));
+        assert!(!set.contains(&Host::parse("example.com").unwrap()));
+    }
+
+    #[test]
+    fn sort_in_reverse_label_order_keeps_domain_families_together() {
+        // arrange
+        let mut set = domain_set(&[
+            "b.example.com",
+            "10.0.0.1",
+            "a.example.net",
+            "example.com",
+            "a.example.com",
+            "ads.other.com",
+        ]);
+
+        // act
+        set.sort_in(SortOrder::ReverseLabel);
+
+        // assert
+        let outcome: Vec<String> = set.iter().map(|val| val.to_string()).collect();
+        assert_eq!(
+            outcome,
+            [
+                "example.com",
+                "a.example.com",
+                "b.example.com",
+                "ads.other.com",
+                "a.example.net",
+                "10.0.0.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_in_source_order_restores_order_hosts_were_added() {
+        // arrange
+        let names = ["tracker.example.org", "ads.example.com", "example.net"];
+        let mut set = domain_set(&names);
+        set.sort();
+
+        // act
+        set.sort_in(SortOrder::Source);
+
+        // assert
+        let outcome: Vec<String> = set.iter().map(|val| val.to_string()).collect();
+        assert_eq!(outcome, names);
+        assert!(set.contains(&Host::parse("ads.example.com").unwrap()));
+    }
+
+    #[test]
+    fn remove_drops_host_from_set() {
+        // arrange
+        let mut set = domain_set(&["example.com", "ads.example.com"]);
+
+        // act
+        let outcome = set.remove(&Host::parse("example.com").unwrap());
+        let outcome_missing = set.remove(&Host::parse("example.com").unwrap());
+
+        // assert
+        assert!(outcome);
+        assert!(!outcome_missing);
+        assert_eq!(set, domain_set(&["ads.example.com"]));
+        assert!(set.insert(Host::parse("example.com").unwrap()));
+    }
+
+    #[test]
+    fn listed_parent_finds_closest_parent_domain() {
+        // arrange
+        let set = domain_set(&["com", "example.com", "tracker.example.com"]);
+
+        // act
+        let outcome = set.listed_parent(&Host::parse("ads.tracker.example.com").unwrap());
+        let outcome_unlisted = set.listed_parent(&Host::parse("example.net").unwrap());
+        let outcome_tld = set.listed_parent(&Host::parse("other.com").unwrap());
+
+        // assert
+        assert_eq!(outcome, Some(Host::Domain("tracker.example.com")));
+        assert_eq!(outcome_unlisted, None);
+        assert_eq!(outcome_tld, None);
+    }
+
+    #[test]
+    fn subdomains_returns_subdomains_at_any_depth() {
+        // arrange
+        let mut set = domain_set(&[
+            "a.tracker.example.com",
+            "example.com",
+            "ads.example.com",
+            "10.0.0.1",
+            "badexample.com",
+            "example.net",
+            "other.example.com",
+            "ads.example.org",
+        ]);
+        set.remove(&Host::parse("other.example.com").unwrap());
+        set.sort_in(SortOrder::ReverseLabel);
+        set.remove(&Host::parse("10.0.0.1").unwrap());
+
+        // act
+        let outcome: Vec<String> = set
+            .subdomains("example.com")
+            .map(|val| val.to_string())
+            .collect();
+        let outcome_unlisted: Vec<String> = set
+            .subdomains("tracker.example.com")
+            .map(|val| val.to_string())
+            .collect();
+
+        // assert
+        assert_eq!(outcome, ["ads.example.com", "a.tracker.example.com"]);
+        assert_eq!(outcome_unlisted, ["a.tracker.example.com"]);
+    }
+}
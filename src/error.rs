@@ -0,0 +1,76 @@
+use std::io;
+
+use crate::{
+    config::ConfigParseError, fetch::FetchError, guard::GuardError, validate::ConfigValidationError,
+};
+
+/// Errors returned by the library.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing a file failed
+    #[error("{message}")]
+    Io {
+        message: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A config file is not valid TOML, or does not match the config format
+    #[error(transparent)]
+    ConfigParse(Box<ConfigParseError>),
+
+    /// The config parsed, but has values which would not work
+    #[error(transparent)]
+    ConfigValidation(#[from] ConfigValidationError),
+
+    /// A config setting is missing or inconsistent
+    #[error("{0}")]
+    Config(String),
+
+    /// Fetching a source failed
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+
+    /// The new blocklist failed a `[guard]` check, so no outputs were written
+    #[error(transparent)]
+    Guard(#[from] GuardError),
+
+    /// An output hook check rejected a new output, or could not run, so no outputs were replaced
+    #[error("{0}")]
+    Hook(String),
+
+    /// Rendering a user-supplied output template failed
+    #[error("{message}")]
+    Template {
+        message: String,
+        #[source]
+        source: minijinja::Error,
+    },
+
+    /// Rendering a built-in output template failed
+    #[error("Error rendering a built-in output template.")]
+    Render(#[source] askama::Error),
+
+    /// The source cache state could not be read or written
+    #[error("{message}")]
+    CacheState {
+        message: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl Error {
+    pub(crate) fn io(message: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            message: message.into(),
+            source,
+        }
+    }
+}
+
+impl From<ConfigParseError> for Error {
+    fn from(value: ConfigParseError) -> Self {
+        Error::ConfigParse(Box::new(value))
+    }
+}
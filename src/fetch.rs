@@ -1,385 +1,2024 @@
-use std::{collections::HashSet, error::Error};
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
 use ahash::RandomState;
-use futures::{Future, Stream, StreamExt};
-use log::info;
-use url::Host;
+use futures::{
+    Future, Stream, StreamExt,
+    channel::mpsc::{Receiver, Sender, channel},
+};
+use log::{info, trace};
+use minisign_verify::{PublicKey, Signature, StreamVerifier};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use sha2::{Digest, Sha256};
+use tokio::{sync::mpsc, task::JoinHandle};
+use url::Url;
 
 use crate::{
-    Source, SourceType,
-    parse::{domainlist as parse_domainlist, hostfile as parse_hostfile},
+    Source,
+    cache::SourceCache,
+    decompress::{ChannelReader, Compression, DecompressError, decompress},
+    diff::SourceAttribution,
+    domain_set::DomainSet,
+    file_system::{FailureAction, SourceOptions},
+    format::{FormatRegistry, ParseDiagnostic, SourceParser},
+    parse::{LineSplitter, looks_like_html},
 };
 
 #[derive(thiserror::Error, Debug)]
-pub enum AppError {
+pub enum FetchError {
     #[error(
-        "Error fetching blocklist `{url}`: only received part of the file.  The network \
+        "Error fetching blocklist `{name}`: only received part of the file.  The network \
         connection may be unstable."
     )]
-    IncompleteBody { url: String },
+    IncompleteBody { name: String },
 
     #[error(
-        "Error fetching blocklist `{url}`: no response data or incomplete data.  The network \
+        "Error fetching blocklist `{name}`: no response data or incomplete data.  The network \
         connection may be unstable."
     )]
-    FetchBody { url: String },
+    FetchBody { name: String },
 
-    #[error("Error parsing fetched data for blocklist `{url}`.  It might be worth retrying later.")]
-    FetchParse { url: String },
+    #[error(
+        "Error parsing fetched data for blocklist `{name}`.  It might be worth retrying later."
+    )]
+    FetchParse { name: String },
 
     #[error(
-        "Error fetching blocklist `{url}`: error requesting data.  The URL might be invalid, or \
+        "Error fetching blocklist `{name}`: error requesting data.  The URL might be invalid, or \
             there might be a network issue."
     )]
-    FetchRequest { url: String },
+    FetchRequest { name: String },
+
+    #[error(
+        "Error fetching blocklist `{name}`.  Check the URL is correct and the connection is up."
+    )]
+    Fetch { name: String },
+
+    #[error(
+        "Error fetching blocklist `{name}`: the data is larger than the {limit} byte limit, once \
+        decompressed."
+    )]
+    BodyTooLarge { name: String, limit: usize },
+
+    #[error("Error decompressing blocklist `{name}`.  The file might be corrupt or truncated.")]
+    Decompress { name: String },
+
+    #[error(
+        "Error verifying blocklist `{name}`: its SHA-256 hash is `{actual}`, but `{expected}` is \
+        pinned in the config.  The file has changed since it was pinned."
+    )]
+    IntegrityMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Error verifying minisign signature for blocklist `{name}`: {reason}.")]
+    Signature { name: String, reason: String },
+
+    #[error(
+        "Error checking blocklist `{name}`: it looks like an HTML page.  The server might be \
+        returning an error page."
+    )]
+    LooksLikeHtml { name: String },
+
+    #[error("Error parsing blocklist `{name}`: there is no parser for its `{format}` format.")]
+    UnknownFormat { name: String, format: String },
+
+    #[error(
+        "Error checking blocklist `{name}`: it has {entries} entries, fewer than the {min} \
+        minimum."
+    )]
+    TooFewEntries {
+        name: String,
+        entries: usize,
+        min: usize,
+    },
+
+    #[error(
+        "Error checking blocklist `{name}`: it has {entries} entries, more than the {max} maximum."
+    )]
+    TooManyEntries {
+        name: String,
+        entries: usize,
+        max: usize,
+    },
 
     #[error(
-        "Error fetching blocklist `{url}`.  Check the URL is correct and the connection is up."
+        "Error checking blocklist `{name}`: it shrank from {previous} to {entries} entries, by \
+        more than the {limit}% limit."
     )]
-    Fetch { url: String },
+    Shrunk {
+        name: String,
+        previous: usize,
+        entries: usize,
+        limit: u8,
+    },
+
+    #[error("Error starting the HTTP client.  Check the system TLS setup.")]
+    ClientStart(#[source] reqwest::Error),
+
+    #[error("Error starting {threads} parse worker threads.")]
+    ParseWorkers {
+        threads: usize,
+        #[source]
+        source: rayon::ThreadPoolBuildError,
+    },
 }
 
-pub struct Client {
-    client: reqwest::Client,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchStatus {
+    Ok,
+
+    /// Failed, so the last good copy from the cache was used instead
+    Cached,
+    Failed,
 }
 
-impl Default for Client {
-    fn default() -> Self {
-        Client::new(None)
+impl fmt::Display for FetchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchStatus::Ok => write!(f, "ok"),
+            FetchStatus::Cached => write!(f, "cached"),
+            FetchStatus::Failed => write!(f, "failed"),
+        }
     }
 }
 
-impl Client {
-    /// Initialise client optionally supplying the timeout for request.  `None` means no timeout.
-    fn new(timeout: Option<std::time::Duration>) -> Self {
-        if let Some(value) = timeout {
-            Client {
-                client: reqwest::ClientBuilder::new()
-                    .timeout(value)
-                    .build()
-                    .expect("Reqwest builder should be able to initialise itself"),
+/// Outcome of fetching a single source.
+#[derive(Clone, Debug)]
+pub struct SourceReport {
+    pub name: String,
+
+    /// URL as written in the config, with any `${NAME}` references left in place
+    pub url: String,
+
+    /// Distinct domains parsed from the source
+    pub entries: usize,
+
+    /// Lines which could not be parsed
+    pub rejected: usize,
+
+    /// Size of the body, as published (before any decompression)
+    pub bytes: usize,
+
+    /// Time taken to fetch and parse the source
+    pub duration: Duration,
+    pub status: FetchStatus,
+}
+
+/// Check the number of distinct domains parsed from the source `name` against the thresholds in
+/// `options`.  `previous_entries` is the count from the last good fetch, if known.
+fn check_entries(
+    name: &str,
+    entries: usize,
+    options: &SourceOptions,
+    previous_entries: Option<usize>,
+) -> Result<(), FetchError> {
+    if let Some(min) = options.min_entries
+        && entries < min
+    {
+        return Err(FetchError::TooFewEntries {
+            name: name.into(),
+            entries,
+            min,
+        });
+    }
+    if let Some(max) = options.max_entries
+        && entries > max
+    {
+        return Err(FetchError::TooManyEntries {
+            name: name.into(),
+            entries,
+            max,
+        });
+    }
+    if let Some(limit) = options.max_shrink_percent {
+        match previous_entries {
+            Some(previous)
+                if previous.saturating_sub(entries) * 100 > previous * usize::from(limit) =>
+            {
+                return Err(FetchError::Shrunk {
+                    name: name.into(),
+                    previous,
+                    entries,
+                    limit,
+                });
             }
-        } else {
-            Client {
-                client: reqwest::Client::new(),
+            Some(_) => {}
+            None => log::debug!("No previous entry count for {name}, so skipping shrink check"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Record `source_index` as the source of any domain in `domains` not already listed by an
+/// earlier source.
+pub fn attribute(
+    attribution: Option<&mut SourceAttribution>,
+    domains: &DomainSet,
+    source_index: usize,
+) {
+    if let Some(attribution_value) = attribution {
+        for domain in domains {
+            attribution_value
+                .entry(domain.to_owned())
+                .or_insert(source_index);
+        }
+    }
+}
+
+/// Hosts parsed from one source.  Given the shared set other sources are merged into, only hosts
+/// it does not already hold are kept, ready to add once the source passes its checks.
+struct ParsedHosts<'a> {
+    shared: Option<&'a Mutex<DomainSet>>,
+    hosts: DomainSet,
+
+    /// Hashes of the hosts parsed which were already in the shared set, so each is counted once,
+    /// however often the source repeats it
+    shared_hashes: HashSet<u64, RandomState>,
+    hasher: RandomState,
+
+    /// Lines which could not be parsed
+    rejected: usize,
+
+    /// Size of the body, as published
+    bytes: usize,
+}
+
+impl<'a> ParsedHosts<'a> {
+    fn new(shared: Option<&'a Mutex<DomainSet>>) -> Self {
+        ParsedHosts {
+            shared,
+            hosts: DomainSet::new(),
+            shared_hashes: HashSet::default(),
+            hasher: RandomState::new(),
+            rejected: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Move the hosts in `batch` into this set, or count them, if they are already shared.
+    fn extend(&mut self, batch: &mut DomainSet) {
+        match self.shared {
+            Some(shared) => {
+                let shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                for host in batch.iter() {
+                    // the shared set only grows, so a host kept here earlier may be shared now
+                    if !shared.contains(&host) {
+                        self.hosts.insert(host);
+                    } else if !self.hosts.contains(&host) {
+                        self.shared_hashes.insert(self.hasher.hash_one(host));
+                    }
+                }
+            }
+            None => self.hosts.extend(batch.iter()),
+        }
+        batch.clear();
+    }
+
+    /// Distinct hosts parsed, whether or not they were already shared.
+    fn entries(&self) -> usize {
+        self.hosts.len() + self.shared_hashes.len()
+    }
+}
+
+/// Bytes at the start of a body checked for an HTML page, before any of it is parsed.
+const HTML_CHECK_LENGTH: usize = 1024;
+
+/// Whole lines of a body gathered before they are parsed together on the parse pool.  Bodies
+/// larger than this are parsed a block at a time, in parallel.
+const PARSE_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Blocks of a body held at once, waiting for or being parsed, for each parse thread.
+const BLOCKS_WAITING_PER_THREAD: usize = 2;
+
+/// Hosts and diagnostics from parsing one block of a body.
+struct ParsedBlock {
+    hosts: DomainSet,
+    diagnostics: Vec<ParseDiagnostic>,
+
+    /// Lines in the block, for numbering diagnostics in later blocks
+    line_count: usize,
+}
+
+impl ParsedBlock {
+    #[expect(clippy::naive_bytecount)]
+    fn parse(parser: &dyn SourceParser, lines: &[u8]) -> Self {
+        let mut hosts = DomainSet::new();
+        let diagnostics = parser.parse(lines, &mut hosts);
+
+        ParsedBlock {
+            hosts,
+            diagnostics,
+            line_count: lines.iter().filter(|val| **val == b'\n').count(),
+        }
+    }
+}
+
+/// Parses a source body as it arrives.  Whole lines are gathered into blocks, which are parsed
+/// on the parse pool, away from the async runtime, and merged back in the order they appear in
+/// the body.  Only a few blocks per parse thread are held at once: once that many are waiting,
+/// [`BodyParser::push`] waits for one to be parsed, so the download is read no faster than it
+/// can be parsed.
+struct BodyParser<'p, 's> {
+    name: &'p str,
+    pool: Option<&'p ThreadPool>,
+    parser: Arc<dyn SourceParser>,
+    lines: LineSplitter,
+
+    /// Start of the body, held until there is enough of it to check it is not an HTML page
+    start: Option<Vec<u8>>,
+
+    /// Whole lines not yet sent to the parse pool
+    block: Vec<u8>,
+    blocks_sent: usize,
+
+    /// Most blocks sent and not yet merged
+    max_blocks_waiting: usize,
+    parsed_sender: Sender<(usize, ParsedBlock)>,
+    parsed_receiver: Receiver<(usize, ParsedBlock)>,
+
+    /// Blocks parsed ahead of an earlier block, by position in the body
+    parsed: BTreeMap<usize, ParsedBlock>,
+    blocks_merged: usize,
+    hosts: ParsedHosts<'s>,
+
+    /// Lines merged so far, for numbering diagnostics
+    line_count: usize,
+    skipped_lines: usize,
+
+    /// First error, after which the rest of the body is ignored
+    error: Option<FetchError>,
+}
+
+impl<'p, 's> BodyParser<'p, 's> {
+    fn new(
+        name: &'p str,
+        client: &'p Client,
+        parser: Arc<dyn SourceParser>,
+        hosts: ParsedHosts<'s>,
+    ) -> Self {
+        let pool = client.parse_pool.as_ref();
+        let max_blocks_waiting = BLOCKS_WAITING_PER_THREAD
+            * pool.map_or_else(
+                rayon::current_num_threads,
+                rayon::ThreadPool::current_num_threads,
+            );
+        let (parsed_sender, parsed_receiver) = channel(max_blocks_waiting);
+        BodyParser {
+            name,
+            pool,
+            parser,
+            lines: LineSplitter::default(),
+            start: Some(Vec::new()),
+            block: Vec::new(),
+            blocks_sent: 0,
+            max_blocks_waiting,
+            parsed_sender,
+            parsed_receiver,
+            parsed: BTreeMap::new(),
+            blocks_merged: 0,
+            hosts,
+            line_count: 0,
+            skipped_lines: 0,
+            error: None,
+        }
+    }
+
+    /// Parse `data`, the next part of the decompressed body, up to its last whole line.
+    async fn push(&mut self, data: &[u8]) {
+        let Some(mut start) = self.start.take() else {
+            self.split(data).await;
+            return;
+        };
+        start.extend_from_slice(data);
+        if start.len() < HTML_CHECK_LENGTH {
+            self.start = Some(start);
+            return;
+        }
+        self.check_start(&start);
+        self.split(&start).await;
+    }
+
+    fn check_start(&mut self, start: &[u8]) {
+        if looks_like_html(&String::from_utf8_lossy(start)) {
+            self.error = Some(FetchError::LooksLikeHtml {
+                name: self.name.into(),
+            });
+        }
+    }
+
+    async fn split(&mut self, data: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.push(data, &mut |val| self.block.extend_from_slice(val));
+        self.lines = lines;
+        if self.block.len() >= PARSE_BLOCK_SIZE {
+            self.send_block().await;
+        }
+        self.merge_parsed();
+    }
+
+    /// Parse the lines gathered so far on the parse pool, first waiting for a block to be parsed
+    /// if too many are waiting already.
+    async fn send_block(&mut self) {
+        while self.blocks_sent - self.blocks_merged >= self.max_blocks_waiting {
+            self.receive_parsed().await;
+        }
+        let block = std::mem::take(&mut self.block);
+        let index = self.blocks_sent;
+        let parser = Arc::clone(&self.parser);
+        let mut sender = self.parsed_sender.clone();
+        let job = move || {
+            // never full, as no more blocks are sent than the channel holds
+            let _ = sender.try_send((index, ParsedBlock::parse(parser.as_ref(), &block)));
+        };
+        match self.pool {
+            Some(value) => value.spawn(job),
+            None => rayon::spawn(job),
+        }
+        self.blocks_sent += 1;
+    }
+
+    /// Wait for a block to be parsed, then merge it, if it is next.
+    async fn receive_parsed(&mut self) {
+        if let Some((index, block)) = self.parsed_receiver.next().await {
+            self.parsed.insert(index, block);
+        }
+        self.merge_parsed();
+    }
+
+    /// Merge any parsed blocks which follow on from those already merged.
+    fn merge_parsed(&mut self) {
+        while let Ok((index, block)) = self.parsed_receiver.try_recv() {
+            self.parsed.insert(index, block);
+        }
+        while let Some(mut block) = self.parsed.remove(&self.blocks_merged) {
+            for diagnostic in block.diagnostics {
+                trace!(
+                    "{}: line {}: {}",
+                    self.name,
+                    self.line_count + diagnostic.line,
+                    diagnostic.message
+                );
+                self.skipped_lines += 1;
+            }
+            self.line_count += block.line_count;
+            self.hosts.extend(&mut block.hosts);
+            self.blocks_merged += 1;
+        }
+    }
+
+    /// Parse any last line, and wait for every block to be parsed, returning the hosts parsed.
+    async fn finish(mut self) -> Result<ParsedHosts<'s>, FetchError> {
+        if let Some(start) = self.start.take() {
+            self.check_start(&start);
+            self.split(&start).await;
+        }
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.finish(&mut |val| self.block.extend_from_slice(val));
+        if !self.block.is_empty() {
+            self.send_block().await;
+        }
+        while self.blocks_merged < self.blocks_sent {
+            self.receive_parsed().await;
+        }
+        if self.skipped_lines > 0 {
+            log::debug!(
+                "Skipped {} lines of {} which could not be parsed",
+                self.skipped_lines,
+                self.name
+            );
+        }
+        self.hosts.rejected = self.skipped_lines;
+
+        Ok(self.hosts)
+    }
+}
+
+/// Checks a body, as it arrives, against any SHA-256 pin or minisign signature for its source.
+struct BodyVerifier<'a> {
+    name: &'a str,
+
+    /// Pinned hash, and the hash of the body so far
+    sha256: Option<(&'a str, Sha256)>,
+    minisign: Option<StreamVerifier<'a>>,
+}
+
+impl<'a> BodyVerifier<'a> {
+    fn new(
+        name: &'a str,
+        options: &'a SourceOptions,
+        signature: Option<&'a (PublicKey, Signature)>,
+    ) -> Result<Self, FetchError> {
+        let minisign = match signature {
+            Some((public_key, signature_value)) => Some(
+                public_key
+                    .verify_stream(signature_value)
+                    .map_err(|_| BodyVerifier::signature_mismatch(name))?,
+            ),
+            None => None,
+        };
+
+        Ok(BodyVerifier {
+            name,
+            sha256: options
+                .sha256
+                .as_deref()
+                .map(|val| (val.trim(), Sha256::new())),
+            minisign,
+        })
+    }
+
+    fn signature_mismatch(name: &str) -> FetchError {
+        FetchError::Signature {
+            name: name.into(),
+            reason: String::from("the signature does not match the file"),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        if let Some((_, hasher)) = &mut self.sha256 {
+            hasher.update(chunk);
+        }
+        if let Some(verifier) = &mut self.minisign {
+            verifier.update(chunk);
+        }
+    }
+
+    /// Check the whole body, once it has all arrived.
+    fn finish(&mut self) -> Result<(), FetchError> {
+        let name = self.name;
+        if let Some((expected, hasher)) = self.sha256.take() {
+            let actual = format!("{:x}", hasher.finalize());
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(FetchError::IntegrityMismatch {
+                    name: name.into(),
+                    expected: expected.into(),
+                    actual,
+                });
+            }
+            log::debug!("SHA-256 hash matches pin for {name}");
+        }
+        if let Some(verifier) = &mut self.minisign {
+            verifier
+                .finalize()
+                .map_err(|_| BodyVerifier::signature_mismatch(name))?;
+            log::debug!("Verified minisign signature for {name}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Decompression of a body on a blocking thread, away from the async runtime, as it arrives.
+/// Both the compressed chunks and the decompressed blocks pass through bounded channels, so
+/// neither side gets far ahead of the other.
+struct Decompression {
+    /// Compressed chunks, until the whole body has arrived
+    input: Option<mpsc::Sender<Vec<u8>>>,
+    output: mpsc::Receiver<Vec<u8>>,
+    task: JoinHandle<Result<(), DecompressError>>,
+}
+
+impl Decompression {
+    fn start(compression: Compression, limit: usize) -> Self {
+        let (input, input_receiver) = mpsc::channel::<Vec<u8>>(DECOMPRESSION_BLOCKS_WAITING);
+        let (output_sender, output) = mpsc::channel::<Vec<u8>>(DECOMPRESSION_BLOCKS_WAITING);
+        let task = tokio::task::spawn_blocking(move || {
+            decompress(
+                ChannelReader::new(input_receiver),
+                compression,
+                limit,
+                &mut |val| {
+                    let _ = output_sender.blocking_send(val.to_vec());
+                },
+            )
+        });
+
+        Decompression {
+            input: Some(input),
+            output,
+            task,
+        }
+    }
+
+    /// Pass `chunk`, the next part of the compressed body, on to be decompressed, meanwhile
+    /// passing any blocks which come out on to `body_parser`.
+    async fn push(&mut self, chunk: Vec<u8>, body_parser: &mut BodyParser<'_, '_>) {
+        let Some(input) = &self.input else {
+            return;
+        };
+        loop {
+            tokio::select! {
+                permit = input.reserve() => {
+                    // an error means decompression has stopped, and `finish` reports why
+                    if let Ok(value) = permit {
+                        value.send(chunk);
+                    }
+                    return;
+                }
+                Some(block) = self.output.recv() => body_parser.push(&block).await,
+            }
+        }
+    }
+
+    /// Pass on the rest of the decompressed body, once the whole compressed body has arrived.
+    async fn finish(mut self, body_parser: &mut BodyParser<'_, '_>) -> Result<(), DecompressError> {
+        self.input = None;
+        while let Some(block) = self.output.recv().await {
+            body_parser.push(&block).await;
+        }
+
+        self.task
+            .await
+            .map_err(|error| DecompressError::Io(std::io::Error::other(error)))?
+    }
+}
+
+/// Bytes needed to recognise any supported compression format from its magic bytes.
+const MAGIC_BYTES_LENGTH: usize = 6;
+
+/// Compressed chunks, or decompressed blocks, held at once, waiting to be passed on.
+const DECOMPRESSION_BLOCKS_WAITING: usize = 4;
+
+/// How a body arriving in chunks is passed on to its [`BodyParser`].
+enum Encoding {
+    /// Too little has arrived yet to tell whether the body is compressed
+    Unknown(Vec<u8>),
+
+    /// Passed on as it arrives
+    Plain,
+
+    /// Decompressed as it arrives, and passed on a block at a time
+    Compressed(Decompression),
+}
+
+impl Encoding {
+    async fn push(
+        &mut self,
+        name: &str,
+        url: &str,
+        chunk: &[u8],
+        body_parser: &mut BodyParser<'_, '_>,
+        limit: usize,
+    ) {
+        match self {
+            Encoding::Unknown(start) => {
+                start.extend_from_slice(chunk);
+                if start.len() >= MAGIC_BYTES_LENGTH {
+                    self.detect(name, url, body_parser, limit).await;
+                }
+            }
+            Encoding::Plain => body_parser.push(chunk).await,
+            Encoding::Compressed(decompression) => {
+                decompression.push(chunk.to_vec(), body_parser).await;
             }
         }
     }
 
-    fn handle_fetch_error(url: &str, error: &reqwest::Error) -> AppError {
+    async fn detect(
+        &mut self,
+        name: &str,
+        url: &str,
+        body_parser: &mut BodyParser<'_, '_>,
+        limit: usize,
+    ) {
+        let Encoding::Unknown(start) = self else {
+            return;
+        };
+        let start = std::mem::take(start);
+        if let Some(compression) = Compression::detect(url, &start) {
+            log::debug!("Decompressing {compression:?} data from {name}");
+            let mut decompression = Decompression::start(compression, limit);
+            decompression.push(start, body_parser).await;
+            *self = Encoding::Compressed(decompression);
+        } else {
+            body_parser.push(&start).await;
+            *self = Encoding::Plain;
+        }
+    }
+
+    /// Pass on anything held back, once the whole body has arrived.
+    async fn finish(
+        mut self,
+        name: &str,
+        url: &str,
+        limit: usize,
+        body_parser: &mut BodyParser<'_, '_>,
+    ) -> Result<(), FetchError> {
+        self.detect(name, url, body_parser, limit).await;
+        let Encoding::Compressed(decompression) = self else {
+            return Ok(());
+        };
+
+        decompression
+            .finish(body_parser)
+            .await
+            .map_err(|error| match error {
+                DecompressError::SizeLimit { limit } => FetchError::BodyTooLarge {
+                    name: name.into(),
+                    limit,
+                },
+                DecompressError::Io(_) => FetchError::Decompress { name: name.into() },
+            })
+    }
+}
+
+/// Default limit on the size of a fetched source, after any decompression (256 MiB).
+pub const DEFAULT_MAX_BODY_SIZE: usize = 256 * 1024 * 1024;
+
+#[expect(clippy::struct_field_names)]
+pub struct Client {
+    client: reqwest::Client,
+    max_body_size: usize,
+    formats: Arc<FormatRegistry>,
+
+    /// Pool parsing source bodies, or `None` for the global `rayon` pool
+    parse_pool: Option<ThreadPool>,
+}
+
+impl Client {
+    /// Client with an optional timeout for each request.  `None` means no timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP client cannot start, for example when the system TLS setup
+    /// cannot be loaded.
+    pub fn new(timeout: Option<std::time::Duration>) -> Result<Self, FetchError> {
+        let mut builder = reqwest::ClientBuilder::new();
+        if let Some(value) = timeout {
+            builder = builder.timeout(value);
+        }
+
+        Ok(Client {
+            client: builder.build().map_err(FetchError::ClientStart)?,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            formats: Arc::new(FormatRegistry::default()),
+            parse_pool: None,
+        })
+    }
+
+    /// Set the largest source body accepted, in bytes, after any decompression.
+    #[must_use]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Parse sources on a pool of `parse_workers` threads (default: the global `rayon` pool, with
+    /// a thread for each CPU).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operating system cannot start the threads.
+    pub fn with_parse_workers(mut self, parse_workers: usize) -> Result<Self, FetchError> {
+        self.parse_pool = Some(
+            ThreadPoolBuilder::new()
+                .num_threads(parse_workers)
+                .thread_name(|index| format!("parse-{index}"))
+                .build()
+                .map_err(|error| FetchError::ParseWorkers {
+                    threads: parse_workers,
+                    source: error,
+                })?,
+        );
+
+        Ok(self)
+    }
+
+    /// Parse sources with the parsers in `formats`, instead of only the built-in ones.
+    #[must_use]
+    pub fn with_formats(mut self, formats: Arc<FormatRegistry>) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    fn handle_fetch_error(name: &str, error: &reqwest::Error) -> FetchError {
         log::error!("{error}");
         if error.is_body() {
             if let Some(hyper_error) = error.source().unwrap().downcast_ref::<hyper::Error>() {
                 if hyper_error.is_incomplete_message() {
-                    return AppError::IncompleteBody { url: url.into() };
+                    return FetchError::IncompleteBody { name: name.into() };
                 }
             } else {
-                return AppError::FetchBody { url: url.into() };
+                return FetchError::FetchBody { name: name.into() };
             }
         }
         if error.is_request() {
-            return AppError::FetchRequest { url: url.into() };
+            return FetchError::FetchRequest { name: name.into() };
         }
-        AppError::Fetch { url: url.into() }
+        FetchError::Fetch { name: name.into() }
     }
 
-    async fn get_text_body(&self, url: &str) -> Result<String, AppError> {
-        let response = match self.client.get(url).send().await {
+    /// Request `url`, using the timeout and headers for `source`.  `Content-Encoding` is handled
+    /// by `reqwest`.
+    async fn send_request(
+        &self,
+        source: &Source<'_>,
+        url: &str,
+    ) -> Result<reqwest::Response, FetchError> {
+        let Source {
+            name,
+            timeout,
+            headers,
+            ..
+        } = source;
+        let mut request = self.client.get(url);
+        if let Some(timeout_value) = timeout {
+            request = request.timeout(*timeout_value);
+        }
+        for (header_name, header_value) in headers.iter().copied().flatten() {
+            request = request.header(header_name, header_value);
+        }
+        match request.send().await {
             Ok(value) => match value.error_for_status() {
-                Ok(ok_response_value) => ok_response_value,
-                Err(error) => return Err(Client::handle_fetch_error(url, &error)),
+                Ok(ok_response_value) => Ok(ok_response_value),
+                Err(error) => Err(Client::handle_fetch_error(name, &error)),
             },
-            Err(error) => return Err(Client::handle_fetch_error(url, &error)),
+            Err(error) => Err(Client::handle_fetch_error(name, &error)),
+        }
+    }
+
+    /// Next chunk of the body of `response`, for the source `name`, or `None` once it has all
+    /// arrived.  `body_size` counts the bytes so far, which are capped at `max_body_size`.
+    async fn next_chunk(
+        &self,
+        name: &str,
+        response: &mut reqwest::Response,
+        body_size: &mut usize,
+    ) -> Result<Option<Vec<u8>>, FetchError> {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                *body_size += chunk.len();
+                if *body_size > self.max_body_size {
+                    return Err(FetchError::BodyTooLarge {
+                        name: name.into(),
+                        limit: self.max_body_size,
+                    });
+                }
+                Ok(Some(chunk.to_vec()))
+            }
+            Ok(None) => Ok(None),
+            Err(_) => Err(FetchError::FetchParse { name: name.into() }),
+        }
+    }
+
+    /// Fetch the body at `url`, as published, using the timeout and headers for `source`, passing
+    /// each chunk to `output` as it arrives, as [`Client::next_chunk`] does.
+    async fn for_each_chunk(
+        &self,
+        source: &Source<'_>,
+        url: &str,
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), FetchError> {
+        let mut response = self.send_request(source, url).await?;
+        let mut body_size: usize = 0;
+        while let Some(chunk) = self
+            .next_chunk(source.name, &mut response, &mut body_size)
+            .await?
+        {
+            output(&chunk);
+        }
+
+        Ok(())
+    }
+
+    /// Fetch the whole body at `url`, as [`Client::for_each_chunk`] does.
+    async fn get_body(&self, source: &Source<'_>, url: &str) -> Result<Vec<u8>, FetchError> {
+        let mut result: Vec<u8> = Vec::new();
+        self.for_each_chunk(source, url, |chunk| result.extend_from_slice(chunk))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Public key and signature, when `options` sets a minisign public key for `source`.
+    async fn get_signature(
+        &self,
+        source: &Source<'_>,
+        options: &SourceOptions,
+    ) -> Result<Option<(PublicKey, Signature)>, FetchError> {
+        let Source { name, url, .. } = source;
+        let Some(public_key) = &options.minisign_public_key else {
+            return Ok(None);
+        };
+        let signature_error = |reason: &str| FetchError::Signature {
+            name: (*name).into(),
+            reason: reason.into(),
+        };
+        let public_key = PublicKey::from_base64(public_key)
+            .map_err(|_| signature_error("the public key in the config is not valid"))?;
+        let signature_url = match &options.minisign_signature_url {
+            Some(value) => value.clone(),
+            None => format!("{url}.minisig"),
+        };
+        // a signature published elsewhere, such as on a release page, gets none of the headers
+        // for the source, which may hold its credentials
+        let same_origin = match (Url::parse(url), Url::parse(&signature_url)) {
+            (Ok(url_value), Ok(signature_url_value)) => {
+                url_value.origin() == signature_url_value.origin()
+            }
+            _ => false,
+        };
+        let signature_source = Source {
+            source_type: source.source_type.clone(),
+            headers: source.headers.filter(|_| same_origin),
+            ..*source
+        };
+        let signature_body = self
+            .get_body(&signature_source, &signature_url)
+            .await
+            .map_err(|_| signature_error("unable to fetch the signature file"))?;
+        let signature = Signature::decode(&String::from_utf8_lossy(&signature_body))
+            .map_err(|_| signature_error("the signature file is not valid"))?;
+
+        Ok(Some((public_key, signature)))
+    }
+
+    /// SHA-256 hash of the body of `source`, as published, for pinning in the config.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source cannot be fetched.
+    pub async fn body_sha256(&self, source: &Source<'_>) -> Result<String, FetchError> {
+        let mut hasher = Sha256::new();
+        self.for_each_chunk(source, source.url, |chunk| hasher.update(chunk))
+            .await?;
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Fetch `source`, parsing it with the parser registered for its format as the body arrives.
+    /// Without `shared`, every host goes into a new set.  Otherwise, only hosts not already in
+    /// `shared` are kept, ready to add to it once the source passes its checks.  The body is
+    /// checked against any SHA-256 pin or minisign signature before it is accepted.
+    async fn fetch_hosts<'a>(
+        &self,
+        source: &Source<'_>,
+        shared: Option<&'a Mutex<DomainSet>>,
+    ) -> Result<ParsedHosts<'a>, FetchError> {
+        let Source {
+            name,
+            url,
+            source_type,
+            options,
+            ..
+        } = source;
+        let format = source_type.config_name();
+        let Some(parser) = self.formats.shared_parser(format) else {
+            return Err(FetchError::UnknownFormat {
+                name: (*name).into(),
+                format: format.into(),
+            });
         };
+        trace!("Fetching {format} source: {name}");
+        let default_options = SourceOptions::default();
+        let options = options.unwrap_or(&default_options);
+        let signature = self.get_signature(source, options).await?;
+        let mut verifier = BodyVerifier::new(name, options, signature.as_ref())?;
+        let mut body_parser = BodyParser::new(name, self, parser, ParsedHosts::new(shared));
+        let mut encoding = Encoding::Unknown(Vec::new());
+        let mut response = self.send_request(source, url).await?;
+        let mut bytes: usize = 0;
+        // the next chunk is only read once this one is passed on, so a download which arrives
+        // faster than it can be parsed waits, rather than filling memory
+        while let Some(chunk) = self.next_chunk(name, &mut response, &mut bytes).await? {
+            verifier.update(&chunk);
+            encoding
+                .push(name, url, &chunk, &mut body_parser, self.max_body_size)
+                .await;
+        }
+        verifier.finish()?;
+        encoding
+            .finish(name, url, self.max_body_size, &mut body_parser)
+            .await?;
+        let mut result = body_parser.finish().await?;
+        result.bytes = bytes;
+        info!("Fetched {name}.");
+
+        Ok(result)
+    }
+
+    /// Fetch `source` and parse it with the parser registered for its format.
+    #[cfg(test)]
+    pub(crate) async fn fetch_set(&self, source: &Source<'_>) -> Result<DomainSet, FetchError> {
+        Ok(self.fetch_hosts(source, None).await?.hosts)
+    }
+
+    /// Fetch `source`, as [`Client::fetch_hosts`] does, also returning the time taken.
+    async fn timed_fetch_hosts<'a>(
+        &self,
+        source: &Source<'_>,
+        shared: Option<&'a Mutex<DomainSet>>,
+    ) -> (Duration, Result<ParsedHosts<'a>, FetchError>) {
+        let start = Instant::now();
+        let result = self.fetch_hosts(source, shared).await;
+
+        (start.elapsed(), result)
+    }
+
+    fn fetch_futures<'a>(
+        &'a self,
+        sources: &'a [Source],
+    ) -> impl Stream<
+        Item = impl Future<Output = (usize, (Duration, Result<ParsedHosts<'a>, FetchError>))> + 'a,
+    > {
+        futures::stream::iter(sources.iter().enumerate()).map(move |(index, val)| async move {
+            (index, self.timed_fetch_hosts(val, None).await)
+        })
+    }
+
+    /// Fetch and parse each source.  A source which fails to fetch, or fails its entry count
+    /// checks, is logged and skipped (with an empty set), unless it is set to fall back to its
+    /// last good copy in `cache`.  The results are in the same order as `sources`, with a report
+    /// recording the outcome for each one.
+    pub async fn fetch_sets(
+        &self,
+        sources: &[Source<'_>],
+        concurrent_downloads: u32,
+        mut cache: Option<&mut SourceCache>,
+    ) -> Vec<(SourceReport, DomainSet)> {
+        let mut result_sets = self
+            .fetch_futures(sources)
+            .buffer_unordered(usize::try_from(concurrent_downloads).unwrap_or(usize::MAX))
+            .collect::<Vec<(usize, (Duration, Result<ParsedHosts, FetchError>))>>()
+            .await;
+        result_sets.sort_unstable_by_key(|(index, _)| *index);
+
+        let mut result: Vec<(SourceReport, DomainSet)> = Vec::with_capacity(sources.len());
+        let default_options = SourceOptions::default();
+        for (index, (duration, result_set)) in result_sets {
+            let Source {
+                name,
+                display_url,
+                options,
+                ..
+            } = &sources[index];
+            let options = options.unwrap_or(&default_options);
+            let result_set = result_set.and_then(|value| {
+                let previous_entries = cache
+                    .as_deref()
+                    .and_then(|val| val.previous_entries(display_url));
+                check_entries(name, value.hosts.len(), options, previous_entries)?;
+                Ok(value)
+            });
+            let report = |entries: usize, status: FetchStatus| SourceReport {
+                name: name.to_string(),
+                url: display_url.to_string(),
+                entries,
+                rejected: 0,
+                bytes: 0,
+                duration,
+                status,
+            };
+            match result_set {
+                Ok(ParsedHosts {
+                    hosts: value,
+                    rejected,
+                    bytes,
+                    ..
+                }) => {
+                    if let Some(cache_value) = cache.as_deref_mut()
+                        && let Err(error) = cache_value.store(display_url, &value)
+                    {
+                        log::warn!("Unable to cache blocklist {name}: {error:#}");
+                    }
+                    let report = SourceReport {
+                        rejected,
+                        bytes,
+                        ..report(value.len(), FetchStatus::Ok)
+                    };
+                    result.push((report, value));
+                }
+                Err(error) => {
+                    let cached_domains = match options.on_failure.unwrap_or_default() {
+                        FailureAction::UseCache => {
+                            cache.as_deref().and_then(|val| val.domains(display_url))
+                        }
+                        FailureAction::Skip => None,
+                    };
+                    if let Some(value) = cached_domains {
+                        log::warn!("Using last good copy of blocklist: {error}");
+                        result.push((report(value.len(), FetchStatus::Cached), value));
+                    } else {
+                        log::error!("Skipping blocklist: {error}");
+                        result.push((report(0, FetchStatus::Failed), DomainSet::new()));
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Fetch and parse each source, as [`Client::fetch_sets`] does, adding the domains to `set`.
+    /// When `attribution` is given, the first source listing each domain is recorded in it.
+    ///
+    /// Without a cache or attribution, each source is parsed straight into `set` as it arrives,
+    /// with only domains new to `set` held back until the source passes its checks, rather than
+    /// building a full set for every source first.  Either way, sources are added in the order of
+    /// `sources`, so `set` lists domains in source order.
+    pub async fn domainlists(
+        &self,
+        sources: &[Source<'_>],
+        concurrent_downloads: u32,
+        cache: Option<&mut SourceCache>,
+        mut attribution: Option<&mut SourceAttribution>,
+        set: &mut DomainSet,
+    ) -> Vec<SourceReport> {
+        if cache.is_some() || attribution.is_some() {
+            let fetched_sets = self.fetch_sets(sources, concurrent_downloads, cache).await;
+            let mut reports: Vec<SourceReport> = Vec::with_capacity(fetched_sets.len());
+            for (index, (report, value)) in fetched_sets.into_iter().enumerate() {
+                attribute(attribution.as_deref_mut(), &value, index);
+                set.extend(value.iter());
+                reports.push(report);
+            }
+
+            return reports;
+        }
+
+        let shared = Mutex::new(std::mem::take(set));
+        let shared_ref = &shared;
+        let mut fetches = futures::stream::iter(sources.iter().enumerate())
+            .map(move |(index, val)| async move {
+                (index, self.timed_fetch_hosts(val, Some(shared_ref)).await)
+            })
+            .buffer_unordered(usize::try_from(concurrent_downloads).unwrap_or(usize::MAX));
+        let mut reports: Vec<SourceReport> = Vec::with_capacity(sources.len());
+        let default_options = SourceOptions::default();
+
+        // sources are added in config order, whatever order they finish in, so `set` lists
+        // domains in source order
+        let mut finished: BTreeMap<usize, (Duration, Result<ParsedHosts, FetchError>)> =
+            BTreeMap::new();
+        while let Some((index, outcome)) = fetches.next().await {
+            finished.insert(index, outcome);
+            while let Some((duration, outcome)) = finished.remove(&reports.len()) {
+                let Source {
+                    name,
+                    display_url,
+                    options,
+                    ..
+                } = &sources[reports.len()];
+                let options = options.unwrap_or(&default_options);
+                let outcome = outcome.and_then(|value| {
+                    check_entries(name, value.entries(), options, None)?;
+                    Ok(value)
+                });
+                let mut report = SourceReport {
+                    name: name.to_string(),
+                    url: display_url.to_string(),
+                    entries: 0,
+                    rejected: 0,
+                    bytes: 0,
+                    duration,
+                    status: FetchStatus::Failed,
+                };
+                match outcome {
+                    Ok(value) => {
+                        report.entries = value.entries();
+                        report.rejected = value.rejected;
+                        report.bytes = value.bytes;
+                        report.status = FetchStatus::Ok;
+                        shared
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .extend(value.hosts.iter());
+                    }
+                    Err(error) => log::error!("Skipping blocklist: {error}"),
+                }
+                reports.push(report);
+            }
+        }
+        drop(fetches);
+        *set = shared.into_inner().unwrap_or_else(PoisonError::into_inner);
+
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fmt::Write as _, io::Write};
+
+    use crate::{
+        Source, SourceType,
+        cache::SourceCache,
+        domain_set::DomainSet,
+        fetch::{Client, FetchError, FetchStatus},
+        file_system::{FailureAction, SourceOptions},
+    };
+    use assert_fs::TempDir;
+    use url::Host;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path},
+    };
+
+    #[tokio::test]
+    async fn domainlist_contacts_remote_server() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("# comment\n\nexample.com\nanother.example.com\n"),
+            )
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::DomainList))
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(outcome.len(), 2);
+        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
+        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn domainlist_returns_error_if_remote_server_is_unreachable() {
+        // arrange
+        let client = Client::new(Some(std::time::Duration::from_secs(1))).unwrap();
+
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(
+                "https://0.0.0.0/does-not-exist",
+                SourceType::DomainList,
+            ))
+            .await
+            .unwrap_err();
+
+        // assert
+        assert_eq!(
+            format!("{outcome}"),
+            "Error fetching blocklist `https://0.0.0.0/does-not-exist`: error requesting data.  \
+                The URL might be invalid, or there might be a network issue."
+        );
+    }
+
+    #[tokio::test]
+    async fn domainlist_returns_error_if_page_not_found() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("Not Found"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::DomainList))
+            .await
+            .unwrap_err();
+
+        // assert
+        assert_eq!(
+            format!("{outcome}"),
+            format!(
+                "Error fetching blocklist `{mock_remote_uri}`.  Check the URL is correct and the \
+      connection is up."
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn hostsfile_contacts_remote_server() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/hosts", mock_remote_server.uri());
+        Mock::given(path("/hosts"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
+            ))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::HostsFile))
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(outcome.len(), 2);
+        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
+        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn hostsfile_returns_error_when_remote_server_returns_a_server_error() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/hosts", mock_remote_server.uri());
+        Mock::given(path("/hosts"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("Something went wrong!"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::HostsFile))
+            .await
+            .unwrap_err();
+
+        // assert
+        assert_eq!(
+            format!("{outcome}"),
+            format!(
+                "Error fetching blocklist `{mock_remote_uri}`.  Check the URL is correct and the \
+      connection is up."
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_set_contacts_remote_servers() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri_1 = format!("{}/hosts", mock_remote_server.uri());
+        Mock::given(path("/hosts"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
+            ))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let source = Source::new(&mock_remote_uri_1, SourceType::HostsFile);
+
+        // act
+        let outcome = client.fetch_set(&source).await.unwrap();
+
+        // assert
+        assert_eq!(outcome.len(), 2);
+        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
+        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn fetch_set_parses_large_body_in_blocks_keeping_source_order() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domains", mock_remote_server.uri());
+        let names: Vec<String> = (0..150_000)
+            .map(|val| format!("host-{val}.example.com"))
+            .collect();
+        Mock::given(path("/domains"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(names.join("\n")))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap().with_parse_workers(2).unwrap();
+        let source = Source::new(&mock_remote_uri, SourceType::DomainList);
+
+        // act
+        let outcome = client.fetch_set(&source).await.unwrap();
+
+        // assert
+        let outcome_names: Vec<String> = outcome.iter().map(|val| val.to_string()).collect();
+        assert_eq!(outcome_names, names);
+    }
+
+    #[tokio::test]
+    async fn domainlists_compiles_result_set() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri_1 = format!("{}/hosts_a", mock_remote_server.uri());
+        let mock_remote_uri_2 = format!("{}/domainlist", mock_remote_server.uri());
+        let mock_remote_uri_3 = format!("{}/hosts_b", mock_remote_server.uri());
+        Mock::given(path("/hosts_a"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
+            ))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("# comment\nexample.com\nrepeat.example.com\n"),
+            )
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/hosts_b"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "# comment\n\n0.0.0.0 yet.another.example.com\n0.0.0.0\trepeat.example.com\n",
+            ))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let sources = [
+            Source::new(&mock_remote_uri_1, SourceType::HostsFile),
+            Source::new(&mock_remote_uri_2, SourceType::DomainList),
+            Source::new(&mock_remote_uri_3, SourceType::HostsFile),
+        ];
+        let max_concurrent_downloads = 3;
+
+        // act
+        let mut result = DomainSet::new();
+        let outcome = client
+            .domainlists(&sources, max_concurrent_downloads, None, None, &mut result)
+            .await;
+
+        // assert
+        assert_eq!(outcome.len(), 3);
+        assert_eq!(outcome[0].url, mock_remote_uri_1);
+        assert_eq!(outcome[0].entries, 2);
+        assert_eq!(outcome[1].url, mock_remote_uri_2);
+        assert_eq!(outcome[1].entries, 2);
+        assert!(outcome.iter().all(|val| val.status == FetchStatus::Ok));
+        assert_eq!(result.len(), 4);
+        assert!(result.contains(&Host::parse("example.com").unwrap()));
+        assert!(result.contains(&Host::parse("another.example.com").unwrap()));
+        assert!(result.contains(&Host::parse("repeat.example.com").unwrap()));
+        assert!(result.contains(&Host::parse("yet.another.example.com").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn domainlists_reports_body_size_and_rejected_lines() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        let body = "example.com\n-not a domain\nfinal-example.com\n";
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let sources = [Source::new(&mock_remote_uri, SourceType::DomainList)];
+
+        // act
+        let mut result = DomainSet::new();
+        let outcome = client
+            .domainlists(&sources, 1, None, None, &mut result)
+            .await;
 
-        match response.text().await {
-            Ok(value) => Ok(value),
-            Err(_) => Err(AppError::FetchParse { url: url.into() }),
-        }
+        // assert
+        assert_eq!(outcome[0].entries, 2);
+        assert_eq!(outcome[0].rejected, 1);
+        assert_eq!(outcome[0].bytes, body.len());
+        assert_eq!(outcome[0].status, FetchStatus::Ok);
     }
 
-    pub async fn domainlist(&self, url: &str) -> Result<HashSet<Host, RandomState>, AppError> {
-        let mut result = HashSet::<Host, RandomState>::default();
-        log::trace!("Fetching domainlist (stream): {url}");
-        let body = self.get_text_body(url).await?;
-        info!("Fetched {url}.");
-        parse_domainlist(&body, &mut result);
-        Ok(result)
-    }
+    #[tokio::test]
+    async fn domainlists_skips_and_reports_failed_sources() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri_1 = format!("{}/domainlist", mock_remote_server.uri());
+        let mock_remote_uri_2 = format!("{}/missing", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("example.com\n"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/missing"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("Not Found"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let sources = [
+            Source::new(&mock_remote_uri_1, SourceType::DomainList),
+            Source::new(&mock_remote_uri_2, SourceType::DomainList),
+        ];
 
-    pub async fn hostsfile(&self, url: &str) -> Result<HashSet<Host, RandomState>, AppError> {
-        let mut result = HashSet::<Host, RandomState>::default();
-        info!("Fetching domainlist (stream): {url}");
-        let body = self.get_text_body(url).await?;
-        parse_hostfile(&body, &mut result);
+        // act
+        let mut result = DomainSet::new();
+        let outcome = client
+            .domainlists(&sources, 2, None, None, &mut result)
+            .await;
 
-        Ok(result)
+        // assert
+        assert_eq!(result.len(), 1);
+        assert_eq!(outcome[0].status, FetchStatus::Ok);
+        assert_eq!(outcome[1].status, FetchStatus::Failed);
+        assert_eq!(outcome[1].entries, 0);
     }
 
-    pub async fn fetch_set(
-        &self,
-        source: &Source<'_>,
-    ) -> Result<HashSet<Host, RandomState>, AppError> {
-        let Source { url, source_type } = source;
-        match source_type {
-            SourceType::DomainList => self.domainlist(url).await,
-            SourceType::HostsFile => self.hostsfile(url).await,
-        }
-    }
+    #[tokio::test]
+    async fn domainlists_counts_each_shared_domain_once() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri_1 = format!("{}/first", mock_remote_server.uri());
+        let mock_remote_uri_2 = format!("{}/second", mock_remote_server.uri());
+        Mock::given(path("/first"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("example.com\n"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        // repeats of the shared domain more than a parse block apart
+        let filler_count = 100_000;
+        let filler = (0..filler_count).fold(String::new(), |mut acc, val| {
+            let _ = writeln!(acc, "ads-{val:08}.example.net");
+            acc
+        });
+        let body = format!("example.com\n{filler}example.com\n");
+        Mock::given(path("/second"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            max_entries: Some(filler_count + 1),
+            ..Default::default()
+        };
+        let sources = [
+            Source::new(&mock_remote_uri_1, SourceType::DomainList),
+            Source {
+                options: Some(&options),
+                ..Source::new(&mock_remote_uri_2, SourceType::DomainList)
+            },
+        ];
 
-    fn fetch_futures<'a>(
-        &'a self,
-        sources: &'a [Source],
-    ) -> impl Stream<Item = impl Future<Output = Result<HashSet<Host, RandomState>, AppError>> + 'a>
-    {
-        futures::stream::iter(sources).map(move |val| self.fetch_set(val))
+        // act
+        let mut result = DomainSet::new();
+        let outcome = client
+            .domainlists(&sources, 1, None, None, &mut result)
+            .await;
+
+        // assert
+        assert_eq!(outcome[1].status, FetchStatus::Ok);
+        assert_eq!(outcome[1].entries, filler_count + 1);
+        assert_eq!(result.len(), filler_count + 1);
     }
 
-    pub async fn domainlists(
-        &self,
-        sources: &[Source<'_>],
-        concurrent_downloads: u32,
-        set: &mut HashSet<Host, RandomState>,
-    ) -> Result<(), AppError> {
-        let mut result_sets = self
-            .fetch_futures(sources)
-            .buffer_unordered(
-                concurrent_downloads
-                    .try_into()
-                    .expect("max concurrent download should be representable as a usize"),
-            )
-            .collect::<Vec<Result<HashSet<Host, RandomState>, AppError>>>()
+    #[tokio::test]
+    async fn domainlist_decompresses_gzip_file() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist.gz", mock_remote_server.uri());
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder
+            .write_all(b"# comment\n\nexample.com\nanother.example.com\n")
+            .unwrap();
+        Mock::given(path("/domainlist.gz"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(encoder.finish().unwrap()))
+            .expect(1)
+            .mount(&mock_remote_server)
             .await;
-        for result_set in &mut result_sets {
-            let set_values = result_set.as_mut().unwrap().drain();
-            set.extend(set_values);
-        }
-        Ok(())
-    }
-}
+        let client = Client::new(None).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
+        // act
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::DomainList))
+            .await
+            .unwrap();
 
-    use crate::{Source, SourceType, fetch::Client};
-    use ahash::RandomState;
-    use url::Host;
-    use wiremock::{
-        Mock, MockServer, ResponseTemplate,
-        matchers::{method, path},
-    };
+        // assert
+        assert_eq!(outcome.len(), 2);
+        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
+        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+    }
 
     #[tokio::test]
-    async fn domainlist_contacts_remote_server() {
+    async fn domainlist_handles_content_encoding() {
         // arrange
         let mock_remote_server = MockServer::start().await;
         let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        let body = zstd::encode_all(&b"example.com\nanother.example.com\n"[..], 3).unwrap();
         Mock::given(path("/domainlist"))
             .and(method("GET"))
             .respond_with(
                 ResponseTemplate::new(200)
-                    .set_body_string("# comment\n\nexample.com\nanother.example.com\n"),
+                    .insert_header("Content-Encoding", "zstd")
+                    .set_body_bytes(body),
             )
             .expect(1)
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
+        let client = Client::new(None).unwrap();
 
         // act
-        let outcome = client.domainlist(&mock_remote_uri).await.unwrap();
+        let outcome = client
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::DomainList))
+            .await
+            .unwrap();
 
         // assert
         assert_eq!(outcome.len(), 2);
         assert!(outcome.contains(&Host::parse("example.com").unwrap()));
-        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
     }
 
     #[tokio::test]
-    async fn domainlist_returns_error_if_remote_server_is_unreachable() {
+    async fn domainlist_returns_error_when_decompressed_body_exceeds_limit() {
         // arrange
-        let client = Client::new(Some(std::time::Duration::from_secs(1)));
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist.zst", mock_remote_server.uri());
+        let body = zstd::encode_all(&[b'a'; 4096][..], 3).unwrap();
+        Mock::given(path("/domainlist.zst"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap().with_max_body_size(1024);
 
         // act
         let outcome = client
-            .domainlist("https://0.0.0.0/does-not-exist")
+            .fetch_set(&Source::new(&mock_remote_uri, SourceType::DomainList))
             .await
             .unwrap_err();
 
         // assert
         assert_eq!(
             format!("{outcome}"),
-            "Error fetching blocklist `https://0.0.0.0/does-not-exist`: error requesting data.  \
-                The URL might be invalid, or there might be a network issue."
+            format!(
+                "Error fetching blocklist `{mock_remote_uri}`: the data is larger than the 1024 \
+                byte limit, once decompressed."
+            )
+        );
+    }
+
+    /// Sign `body` in minisign's default (pre-hashed) format, returning the public key and
+    /// signature file content.
+    fn minisign(body: &[u8]) -> (String, String) {
+        use base64::{Engine, engine::general_purpose::STANDARD};
+        use blake2::{Blake2b512, Digest};
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let signing_key = SigningKey::from_bytes(&[3; 32]);
+        let key_id = [1_u8, 2, 3, 4, 5, 6, 7, 8];
+        let public_key = [&b"Ed"[..], &key_id, signing_key.verifying_key().as_bytes()].concat();
+        let signature = signing_key.sign(&Blake2b512::digest(body)).to_bytes();
+        let trusted_comment = "timestamp:0";
+        let global_signature = signing_key
+            .sign(&[&signature[..], trusted_comment.as_bytes()].concat())
+            .to_bytes();
+        let signature_file = format!(
+            "untrusted comment: test\n{}\ntrusted comment: {trusted_comment}\n{}\n",
+            STANDARD.encode([&b"ED"[..], &key_id, &signature].concat()),
+            STANDARD.encode(global_signature)
         );
+
+        (STANDARD.encode(public_key), signature_file)
     }
 
     #[tokio::test]
-    async fn domainlist_returns_error_if_page_not_found() {
+    async fn fetch_set_accepts_body_matching_pin() {
         // arrange
         let mock_remote_server = MockServer::start().await;
         let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
         Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(404).set_body_string("Not Found"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("example.com\n"))
             .expect(1)
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            sha256: Some(String::from(
+                "391196688aa55d3321deffa736f8d103b4813470952b748e9c2c9deb17fa60f5",
+            )),
+            ..Default::default()
+        };
+        let source = Source {
+            options: Some(&options),
+            ..Source::new(&mock_remote_uri, SourceType::DomainList)
+        };
 
         // act
-        let outcome = client.domainlist(&mock_remote_uri).await.unwrap_err();
+        let outcome = client.fetch_set(&source).await.unwrap();
 
         // assert
-        assert_eq!(
-            format!("{outcome}"),
-            format!(
-                "Error fetching blocklist `{mock_remote_uri}`.  Check the URL is correct and the \
-      connection is up."
-            )
-        );
+        assert_eq!(outcome.len(), 1);
     }
 
     #[tokio::test]
-    async fn hostsfile_contacts_remote_server() {
+    async fn fetch_set_rejects_body_not_matching_pin() {
         // arrange
         let mock_remote_server = MockServer::start().await;
-        let mock_remote_uri = format!("{}/hosts", mock_remote_server.uri());
-        Mock::given(path("/hosts"))
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(
-                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
-            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("changed.example.com\n"))
             .expect(1)
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            sha256: Some(String::from(
+                "391196688aa55d3321deffa736f8d103b4813470952b748e9c2c9deb17fa60f5",
+            )),
+            ..Default::default()
+        };
+        let source = Source {
+            options: Some(&options),
+            ..Source::new(&mock_remote_uri, SourceType::DomainList)
+        };
 
         // act
-        let outcome = client.hostsfile(&mock_remote_uri).await.unwrap();
+        let outcome = client.fetch_set(&source).await.unwrap_err();
 
         // assert
-        assert_eq!(outcome.len(), 2);
-        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
-        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+        assert!(matches!(outcome, FetchError::IntegrityMismatch { .. }));
     }
 
     #[tokio::test]
-    async fn hostsfile_returns_error_when_remote_server_returns_a_server_error() {
+    async fn fetch_set_checks_minisign_signature() {
         // arrange
+        let body = b"example.com\n";
+        let (public_key, signature_file) = minisign(body);
         let mock_remote_server = MockServer::start().await;
-        let mock_remote_uri = format!("{}/hosts", mock_remote_server.uri());
-        Mock::given(path("/hosts"))
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(500).set_body_string("Something went wrong!"))
-            .expect(1)
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&body[..]))
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/domainlist.minisig"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(signature_file))
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/tampered"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("tampered.example.com\n"))
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            minisign_public_key: Some(public_key.clone()),
+            ..Default::default()
+        };
+        let tampered_options = SourceOptions {
+            minisign_public_key: Some(public_key),
+            minisign_signature_url: Some(format!("{mock_remote_uri}.minisig")),
+            ..Default::default()
+        };
+        let tampered_uri = format!("{}/tampered", mock_remote_server.uri());
 
         // act
-        let outcome = client.hostsfile(&mock_remote_uri).await.unwrap_err();
+        let outcome = client
+            .fetch_set(&Source {
+                options: Some(&options),
+                ..Source::new(&mock_remote_uri, SourceType::DomainList)
+            })
+            .await;
+        let tampered_outcome = client
+            .fetch_set(&Source {
+                options: Some(&tampered_options),
+                ..Source::new(&tampered_uri, SourceType::DomainList)
+            })
+            .await;
 
         // assert
+        assert_eq!(outcome.unwrap().len(), 1);
         assert_eq!(
-            format!("{outcome}"),
+            format!("{}", tampered_outcome.unwrap_err()),
             format!(
-                "Error fetching blocklist `{mock_remote_uri}`.  Check the URL is correct and the \
-      connection is up."
+                "Error verifying minisign signature for blocklist `{tampered_uri}`: the \
+                signature does not match the file."
             )
         );
     }
 
     #[tokio::test]
-    async fn fetch_set_contacts_remote_servers() {
+    async fn fetch_set_only_sends_source_headers_with_signature_on_same_origin() {
         // arrange
+        let body = b"example.com\n";
+        let (public_key, signature_file) = minisign(body);
         let mock_remote_server = MockServer::start().await;
-        let mock_remote_uri_1 = format!("{}/hosts", mock_remote_server.uri());
-        Mock::given(path("/hosts"))
+        let mock_release_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(
-                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
-            ))
+            .and(header("Authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&body[..]))
+            .expect(2)
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/domainlist.minisig"))
+            .and(method("GET"))
+            .and(header("Authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(signature_file.clone()))
             .expect(1)
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
-        let source = Source {
-            url: &mock_remote_uri_1,
-            source_type: SourceType::HostsFile,
+        Mock::given(path("/releases/domainlist.minisig"))
+            .and(method("GET"))
+            .and(|request: &wiremock::Request| !request.headers.contains_key("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(signature_file))
+            .expect(1)
+            .mount(&mock_release_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let headers =
+            BTreeMap::from([(String::from("Authorization"), String::from("Bearer secret"))]);
+        let options = SourceOptions {
+            minisign_public_key: Some(public_key.clone()),
+            ..Default::default()
+        };
+        let release_options = SourceOptions {
+            minisign_public_key: Some(public_key),
+            minisign_signature_url: Some(format!(
+                "{}/releases/domainlist.minisig",
+                mock_release_server.uri()
+            )),
+            ..Default::default()
         };
 
         // act
-        let outcome = client.fetch_set(&source).await.unwrap();
+        let outcome = client
+            .fetch_set(&Source {
+                headers: Some(&headers),
+                options: Some(&options),
+                ..Source::new(&mock_remote_uri, SourceType::DomainList)
+            })
+            .await;
+        let outcome_release = client
+            .fetch_set(&Source {
+                headers: Some(&headers),
+                options: Some(&release_options),
+                ..Source::new(&mock_remote_uri, SourceType::DomainList)
+            })
+            .await;
 
         // assert
-        assert_eq!(outcome.len(), 2);
-        assert!(outcome.contains(&Host::parse("example.com").unwrap()));
-        assert!(outcome.contains(&Host::parse("another.example.com").unwrap()));
+        assert_eq!(outcome.unwrap().len(), 1);
+        assert_eq!(outcome_release.unwrap().len(), 1);
     }
 
     #[tokio::test]
-    async fn domainlists_compiles_result_set() {
+    async fn fetch_set_rejects_html_page() {
         // arrange
         let mock_remote_server = MockServer::start().await;
-        let mock_remote_uri_1 = format!("{}/hosts_a", mock_remote_server.uri());
-        let mock_remote_uri_2 = format!("{}/domainlist", mock_remote_server.uri());
-        let mock_remote_uri_3 = format!("{}/hosts_b", mock_remote_server.uri());
-        Mock::given(path("/hosts_a"))
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(
-                "# comment\n\n0.0.0.0 example.com\n0.0.0.0\tanother.example.com\n",
-            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(
+                    "<!DOCTYPE html>\n<html><body><p>example.com</p></body></html>\n",
+                ),
+            )
             .expect(1)
             .mount(&mock_remote_server)
             .await;
+        let client = Client::new(None).unwrap();
+        let source = Source::new(&mock_remote_uri, SourceType::DomainList);
+
+        // act
+        let outcome = client.fetch_set(&source).await.unwrap_err();
+
+        // assert
+        assert!(matches!(outcome, FetchError::LooksLikeHtml { .. }));
+    }
+
+    #[tokio::test]
+    async fn domainlists_falls_back_to_cache_when_source_shrinks() {
+        // arrange
+        let temp_dir = TempDir::new().unwrap();
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
         Mock::given(path("/domainlist"))
             .and(method("GET"))
             .respond_with(
-                ResponseTemplate::new(200)
-                    .set_body_string("# comment\nexample.com\nrepeat.example.com\n"),
+                ResponseTemplate::new(200).set_body_string(
+                    "ads.example.com\nanalytics.example.com\ntracker.example.com\n",
+                ),
             )
-            .expect(1)
+            .up_to_n_times(1)
             .mount(&mock_remote_server)
             .await;
-        Mock::given(path("/hosts_b"))
+        Mock::given(path("/domainlist"))
             .and(method("GET"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(
-                "# comment\n\n0.0.0.0 yet.another.example.com\n0.0.0.0\trepeat.example.com\n",
-            ))
-            .expect(1)
+            .respond_with(ResponseTemplate::new(200).set_body_string("ads.example.com\n"))
             .mount(&mock_remote_server)
             .await;
-        let client = Client::default();
-        let sources = [
-            Source {
-                url: &mock_remote_uri_1,
-                source_type: SourceType::HostsFile,
-            },
-            Source {
-                url: &mock_remote_uri_2,
-                source_type: SourceType::DomainList,
-            },
-            Source {
-                url: &mock_remote_uri_3,
-                source_type: SourceType::HostsFile,
-            },
-        ];
-        let max_concurrent_downloads = 3;
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            max_shrink_percent: Some(50),
+            on_failure: Some(FailureAction::UseCache),
+            ..Default::default()
+        };
+        let sources = [Source {
+            options: Some(&options),
+            ..Source::new(&mock_remote_uri, SourceType::DomainList)
+        }];
+        let mut cache = SourceCache::load(temp_dir.path()).unwrap();
+        let mut first_result = DomainSet::new();
+        client
+            .domainlists(&sources, 1, Some(&mut cache), None, &mut first_result)
+            .await;
 
         // act
-        let hasher = RandomState::new();
-        let mut result: HashSet<Host, RandomState> = HashSet::with_hasher(hasher);
+        let mut result = DomainSet::new();
         let outcome = client
-            .domainlists(&sources, max_concurrent_downloads, &mut result)
+            .domainlists(&sources, 1, Some(&mut cache), None, &mut result)
             .await;
 
         // assert
-        assert!(outcome.is_ok());
-        assert_eq!(result.len(), 4);
-        assert!(result.contains(&Host::parse("example.com").unwrap()));
-        assert!(result.contains(&Host::parse("another.example.com").unwrap()));
-        assert!(result.contains(&Host::parse("repeat.example.com").unwrap()));
-        assert!(result.contains(&Host::parse("yet.another.example.com").unwrap()));
+        assert_eq!(outcome.len(), 1);
+        assert_eq!(outcome[0].entries, 3);
+        assert_eq!(outcome[0].status, FetchStatus::Cached);
+        assert_eq!(result, first_result);
+    }
+
+    #[tokio::test]
+    async fn domainlists_skips_source_with_too_few_entries() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/domainlist", mock_remote_server.uri());
+        Mock::given(path("/domainlist"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ads.example.com\n"))
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let options = SourceOptions {
+            min_entries: Some(2),
+            on_failure: Some(FailureAction::UseCache),
+            ..Default::default()
+        };
+        let sources = [Source {
+            options: Some(&options),
+            ..Source::new(&mock_remote_uri, SourceType::DomainList)
+        }];
+
+        // act
+        let mut result = DomainSet::new();
+        let outcome = client
+            .domainlists(&sources, 1, None, None, &mut result)
+            .await;
+
+        // assert
+        assert_eq!(outcome[0].entries, 0);
+        assert_eq!(outcome[0].status, FetchStatus::Failed);
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetch_set_sends_source_headers() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        let mock_remote_uri = format!("{}/private", mock_remote_server.uri());
+        Mock::given(path("/private"))
+            .and(method("GET"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("example.com\n"))
+            .expect(1)
+            .mount(&mock_remote_server)
+            .await;
+        let client = Client::new(None).unwrap();
+        let headers =
+            BTreeMap::from([(String::from("Authorization"), String::from("Bearer token"))]);
+        let source = Source {
+            name: "Private list",
+            headers: Some(&headers),
+            ..Source::new(&mock_remote_uri, SourceType::DomainList)
+        };
+
+        // act
+        let outcome = client.fetch_set(&source).await.unwrap();
+
+        // assert
+        assert_eq!(outcome.len(), 1);
     }
 }
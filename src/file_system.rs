@@ -1,64 +1,499 @@
 use std::{
-    collections::HashSet,
-    fs::{self, File},
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::Write as _,
+    fs,
     io::Write,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use ahash::RandomState;
-use anyhow::Context;
-use askama::Template;
-use humansize::{DECIMAL, format_size};
-use log::{error, info};
-use serde::Deserialize;
-use url::Host;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use blake2::{Blake2b512, Digest};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey, pkcs8::DecodePrivateKey};
+use flate2::write::GzEncoder;
+use log::info;
+use serde::{Deserialize, Serialize};
 
-use crate::parse::domainlist as parse_domainlist;
+use crate::{
+    SourceType,
+    config::{process_environment, resolve_config_table_with_url_templates},
+    domain_set::{DomainSet, SortOrder},
+    error::Error,
+    format::{FormatRegistry, OutputContext},
+    hook,
+    metadata::{GenerationMetadata, sha256_hex},
+    parse::domainlist as parse_domainlist,
+};
 
-#[derive(Debug, Deserialize)]
-#[cfg_attr(test, derive(serde::Serialize))]
+/// Sources as two plain lists of URLs.  `[[sources]]` entries can also set a name and options.
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Blocklists {
+    #[serde(default)]
     pub hosts_file_blocklist_urls: Vec<String>,
+    #[serde(default)]
     pub domain_blocklist_urls: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
-#[cfg_attr(test, derive(serde::Serialize))]
+/// A `[[sources]]` entry.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    /// Used in logs and reports (default: source URL)
+    pub name: Option<String>,
+    pub url: String,
+    pub format: SourceType,
+
+    /// For example `ads`, `tracking`, `malware` or `adult`
+    pub category: Option<String>,
+
+    /// Set `false` to leave the source out, without removing it from the config
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Request timeout, in seconds
+    pub timeout: Option<u64>,
+
+    /// Extra HTTP request headers, such as an `Authorization` token for a private source
+    pub headers: Option<BTreeMap<String, String>>,
+
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl SourceConfig {
+    /// Enabled source at `url`, with no name, category or options.
+    pub fn new(url: impl Into<String>, format: SourceType) -> Self {
+        SourceConfig {
+            name: None,
+            url: url.into(),
+            format,
+            category: None,
+            enabled: true,
+            timeout: None,
+            headers: None,
+            options: SourceOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Filters {
     pub allowed_names: Option<Vec<String>>,
-
-    #[cfg_attr(not(test), expect(dead_code))]
     pub blocked_names: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
-#[cfg_attr(test, derive(serde::Serialize))]
+/// Per-source settings.  Set them in a `[[sources]]` entry, or, for sources listed in
+/// `[blocklists]`, in the `source_options` table, keyed by source URL.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SourceOptions {
+    /// Expected SHA-256 hash of the source, as published.  Any other content is rejected.
+    pub sha256: Option<String>,
+
+    /// Base64 minisign public key.  The source is rejected unless it has a valid signature.
+    pub minisign_public_key: Option<String>,
+
+    /// Where to fetch the minisign signature from (default: source URL with `.minisig` added)
+    pub minisign_signature_url: Option<String>,
+
+    /// Fewest distinct domains expected.  A source with fewer is treated as broken.
+    pub min_entries: Option<usize>,
+
+    /// Most distinct domains expected.  A source with more is treated as broken.
+    pub max_entries: Option<usize>,
+
+    /// Largest accepted drop in distinct domains since the last run, as a percentage.  Needs a
+    /// `[cache]` directory to remember the previous count.
+    pub max_shrink_percent: Option<u8>,
+
+    /// What to do when the source fails to fetch, or fails a check (default: skip)
+    pub on_failure: Option<FailureAction>,
+}
+
+/// Action to take for a source which fails to fetch or fails a check.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureAction {
+    /// Leave the source out of this run
+    #[default]
+    Skip,
+
+    /// Use the domains from the last good fetch, kept in the `[cache]` directory
+    UseCache,
+}
+
+/// Directory for keeping the last good copy of each source, and its entry count.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Cache {
+    pub directory: PathBuf,
+}
+
+/// Blocklist file formats the app can write.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Response Policy Zone file for BIND, Knot Resolver and others
+    Rpz,
+
+    /// Unbound `local-zone` config file
+    Unbound,
+
+    /// Plain list of domains, one per line
+    DomainList,
+
+    /// AdGuard Home rules (`||example.com^`)
+    AdguardHome,
+
+    /// Pi-hole gravity-compatible plain list
+    Pihole,
+
+    /// Pi-hole `regex.list`, blocking subdomains of each listed domain
+    PiholeRegex,
+
+    /// Blocky denylist
+    Blocky,
+
+    /// CoreDNS `hosts` plugin file
+    CorednsHosts,
+
+    /// PowerDNS Recursor Lua script with a `preresolve` hook
+    PowerdnsLua,
+
+    /// User-supplied template, rendered at runtime
+    Template,
+
+    /// Format with a writer registered under this name in a
+    /// [`FormatRegistry`](crate::format::FormatRegistry)
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl OutputFormat {
+    /// Name used for the output format in config files.
+    #[must_use]
+    pub fn config_name(&self) -> &str {
+        match self {
+            OutputFormat::Rpz => "rpz",
+            OutputFormat::Unbound => "unbound",
+            OutputFormat::DomainList => "domain-list",
+            OutputFormat::AdguardHome => "adguard-home",
+            OutputFormat::Pihole => "pihole",
+            OutputFormat::PiholeRegex => "pihole-regex",
+            OutputFormat::Blocky => "blocky",
+            OutputFormat::CorednsHosts => "coredns-hosts",
+            OutputFormat::PowerdnsLua => "powerdns-lua",
+            OutputFormat::Template => "template",
+            OutputFormat::Custom(value) => value,
+        }
+    }
+
+    fn default_path(&self) -> PathBuf {
+        let path = match self {
+            OutputFormat::Rpz => "./blocklist.rpz",
+            OutputFormat::Unbound => "./zone-block-general.conf",
+            OutputFormat::DomainList => "./domain-blocklist.txt",
+            OutputFormat::AdguardHome => "./adguard-home-blocklist.txt",
+            OutputFormat::Pihole => "./pihole-gravity.txt",
+            OutputFormat::PiholeRegex => "./regex.list",
+            OutputFormat::Blocky => "./blocky-blocklist.txt",
+            OutputFormat::CorednsHosts => "./coredns-hosts",
+            OutputFormat::PowerdnsLua => "./powerdns-blocklist.lua",
+            OutputFormat::Template => "./blocklist.txt",
+            OutputFormat::Custom(value) => {
+                return PathBuf::from(format!("./{value}-blocklist.txt"));
+            }
+        };
+        PathBuf::from(path)
+    }
+}
+
+/// Compressed copies which can be written alongside an output file.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputCompression {
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputCompression::Gzip => "gz",
+            OutputCompression::Zstd => "zst",
+        }
+    }
+
+    /// Compressed files are built once and downloaded many times, so use a high compression
+    /// level.
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            OutputCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            OutputCompression::Zstd => zstd::encode_all(data, 19),
+        }
+    }
+}
+
+/// Default time a hook may run for before it is stopped.
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Built-in checks of a new output file, made before it replaces the previous one.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookCheck {
+    /// Check an `unbound` output with `unbound-checkconf`
+    UnboundCheckconf,
+
+    /// Check an `rpz` output with `named-checkzone`
+    NamedCheckzone,
+}
+
+/// An `[[outputs.hooks]]` entry, run when the content of the output changes.  Set either
+/// `command`, run once the new file is in place, or `check`, which must pass before the new file
+/// replaces the previous one.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Hook {
+    /// Program and arguments, such as `["unbound-control", "reload"]`.  `{path}` in an argument
+    /// is replaced with the output path.
+    pub command: Option<Vec<String>>,
+
+    pub check: Option<HookCheck>,
+
+    /// Zone name passed to `named-checkzone` (default: `rpz`)
+    pub zone: Option<String>,
+
+    /// Seconds to wait for the hook before stopping it (default: 30)
+    pub timeout: Option<u64>,
+}
+
+impl Hook {
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .map_or(DEFAULT_HOOK_TIMEOUT, Duration::from_secs)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Output {
+    pub format: OutputFormat,
+
+    /// Defaults to a format-specific file name in the working directory
+    pub path: Option<PathBuf>,
+
+    /// AdGuard Home only: add the `$important` modifier to each rule
+    #[serde(default)]
+    pub important: bool,
+
+    /// Template outputs only: path of the Jinja-style template file to render
+    pub template: Option<PathBuf>,
+
+    /// Start the file with a comment header recording the app version, generation time, config
+    /// and source details
+    #[serde(default)]
+    pub header: bool,
+
+    /// Start of each header comment line (default: the format's comment syntax, or `#` for
+    /// template outputs)
+    pub comment_prefix: Option<String>,
+
+    /// Also write compressed copies of the output (`output.gz`, `output.zst`)
+    #[serde(default)]
+    pub compress: Vec<OutputCompression>,
+
+    /// Order of the domains in the file: `lexical`, `reverse-label` or `source`
+    #[serde(default)]
+    pub sort: SortOrder,
+
+    /// Checks to make and commands to run when the content of the file changes
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+}
+
+impl Output {
+    /// Output in `format`, at its default path, with no header or compressed copies.
+    #[must_use]
+    pub fn new(format: OutputFormat) -> Self {
+        Output {
+            format,
+            path: None,
+            important: false,
+            template: None,
+            header: false,
+            comment_prefix: None,
+            compress: Vec::new(),
+            sort: SortOrder::default(),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Template outputs default to the template file name, without its final extension, in the
+    /// working directory (`templates/hosts.txt.j2` gets written to `./hosts.txt`).
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        if let Some(value) = &self.path {
+            return value.clone();
+        }
+        if let (OutputFormat::Template, Some(template)) = (&self.format, &self.template)
+            && let Some(file_stem) = template.file_stem()
+        {
+            return PathBuf::from(".").join(file_stem);
+        }
+        self.format.default_path()
+    }
+}
+
+/// Outputs written when the config file has no `outputs` entries.
+#[must_use]
+pub fn default_outputs() -> Vec<Output> {
+    vec![
+        Output::new(OutputFormat::Rpz),
+        Output::new(OutputFormat::Unbound),
+        Output::new(OutputFormat::DomainList),
+    ]
+}
+
+/// A `[[profiles]]` entry: one of several blocklists built in a run, from the same fetched
+/// sources.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Profile {
+    pub name: String,
+
+    /// Names or URLs of sources to include
+    pub sources: Option<Vec<String>>,
+
+    /// Include `[[sources]]` entries in any of these categories.  With neither `sources` nor
+    /// `categories` set, every source is included.
+    pub categories: Option<Vec<String>>,
+
+    /// Applied after the top-level `[filters]`
+    pub filters: Option<Filters>,
+    pub outputs: Vec<Output>,
+}
+
+/// Whole-run checks made before overwriting existing output files.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Guard {
+    /// Largest accepted change in domain count since the previous output, as a percentage
+    pub max_change_percent: Option<u8>,
+
+    /// Names which must never be blocked, such as your own domains
+    pub canary_names: Option<Vec<String>>,
+
+    /// Previous output to compare against (default: first `domain-list`, `pihole` or `blocky`
+    /// output)
+    pub previous_output: Option<PathBuf>,
+}
+
+/// Files for publishing outputs to downstream hosts.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Distribution {
+    /// Path for a `SHA256SUMS` manifest, listing every file written
+    pub checksums: Option<PathBuf>,
+
+    /// PEM-encoded (PKCS #8) Ed25519 private key.  When set, a detached minisign signature of the
+    /// checksums manifest is written alongside it, with a `.sig` extension added.
+    pub signing_key: Option<PathBuf>,
+}
+
+/// Default time between `serve` runs (a day).
+const DEFAULT_SCHEDULE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// When `serve` regenerates the blocklists.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Schedule {
+    /// Seconds between runs (default: 86,400, once a day)
+    pub interval: Option<u64>,
+
+    /// Most seconds added at random to each interval, so instances started together do not all
+    /// fetch sources at once (default: 0)
+    pub jitter: Option<u64>,
+}
+
+impl Schedule {
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval
+            .map_or(DEFAULT_SCHEDULE_INTERVAL, Duration::from_secs)
+    }
+
+    #[must_use]
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs(self.jitter.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Config {
+    #[serde(default)]
     pub blocklists: Blocklists,
+    pub sources: Option<Vec<SourceConfig>>,
+    pub source_options: Option<BTreeMap<String, SourceOptions>>,
+    pub cache: Option<Cache>,
     pub filters: Option<Filters>,
+    pub outputs: Option<Vec<Output>>,
+    pub profiles: Option<Vec<Profile>>,
+    pub guard: Option<Guard>,
+    pub distribution: Option<Distribution>,
+    pub schedule: Option<Schedule>,
+
+    /// Source URLs which reference environment variables, as written, keyed by the URL they
+    /// resolve to
+    #[serde(skip)]
+    pub url_templates: BTreeMap<String, String>,
 }
 
-pub fn get_config_from_file<P: AsRef<Path>>(config_file_path: P) -> anyhow::Result<Config> {
-    let config_file_content = fs::read_to_string(&config_file_path).with_context(|| {
-        format!(
-            "Failed to open or read config file `{}`",
-            config_file_path.as_ref().display()
-        )
-    })?;
+impl Config {
+    /// `url` as written in the config, with any `${NAME}` references left in place.  Use this
+    /// wherever a source URL leaves the process, so secrets in URLs are not published.
+    #[must_use]
+    pub fn display_url<'a>(&'a self, url: &'a str) -> &'a str {
+        self.url_templates.get(url).map_or(url, String::as_str)
+    }
 
-    match toml::from_str(&config_file_content) {
-        Ok(value) => Ok(value),
-        Err(_) => anyhow::bail!(
-            "Failed to parse config file `{}`.  Check it is valid.",
-            config_file_path.as_ref().display()
-        ),
+    /// Name of `source`, for logs and reports: its `name`, or its URL as written.
+    #[must_use]
+    pub fn source_name<'a>(&'a self, source: &'a SourceConfig) -> &'a str {
+        source
+            .name
+            .as_deref()
+            .unwrap_or_else(|| self.display_url(&source.url))
     }
 }
 
-pub fn get_custom_blocked_names<P: AsRef<Path>>(
-    blocked_names_path: P,
-    set: &mut HashSet<Host, RandomState>,
-) {
+/// Load the config from `config_file_path`, with includes, environment overrides and
+/// `${NAME}` references resolved.
+///
+/// # Errors
+///
+/// Returns an error if a config file cannot be read or parsed, or an environment override or
+/// reference cannot be applied.
+pub fn get_config_from_file<P: AsRef<Path>>(config_file_path: P) -> Result<Config, Error> {
+    let (table, url_templates) =
+        resolve_config_table_with_url_templates(&config_file_path, &process_environment())?;
+
+    // each file was checked as it was read, so errors here come from environment overrides
+    let config: Config = toml::Value::Table(table).try_into().map_err(|error: toml::de::Error| {
+        Error::Config(format!(
+            "Failed to apply `BLOCKLIST_GENERATOR_*` environment overrides to config file `{}`: {}",
+            config_file_path.as_ref().display(),
+            error.message().trim()
+        ))
+    })?;
+
+    Ok(Config {
+        url_templates,
+        ..config
+    })
+}
+
+pub fn get_custom_blocked_names<P: AsRef<Path>>(blocked_names_path: P, set: &mut DomainSet) {
     let blocked_names_display_path = blocked_names_path.as_ref().display().to_string();
     let blocked_names_content = if let Ok(value) = fs::read_to_string(blocked_names_path) {
         Some(value)
@@ -71,94 +506,409 @@ pub fn get_custom_blocked_names<P: AsRef<Path>>(
     }
 }
 
-#[derive(Template)]
-#[template(escape = "none", path = "blocklist.rpz")]
-struct BlocklistRPZTemplate<'a> {
-    domains: &'a str,
+/// Path of the first output written as a plain list of domains, which can be read back in.
+pub fn plain_list_output_path(outputs: &[Output]) -> Option<PathBuf> {
+    outputs
+        .iter()
+        .find(|val| {
+            matches!(
+                val.format,
+                OutputFormat::DomainList | OutputFormat::Pihole | OutputFormat::Blocky
+            )
+        })
+        .map(Output::path)
+}
+
+/// Domains in a plain list file, such as a previous `domain-list` output.  Returns `None` when
+/// the file cannot be read.
+pub fn read_domain_list_file<P: AsRef<Path>>(path: P) -> Option<DomainSet> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut result = DomainSet::new();
+    parse_domainlist(&content, &mut result);
+
+    Some(result)
+}
+
+/// File written beside its final path, ready to move into place with [`StagedFile::commit`], so
+/// readers never see a partly written file.  Dropping it without committing removes it.
+struct StagedFile {
+    temp_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl StagedFile {
+    fn write(content: &[u8], path: &Path) -> Result<Self, Error> {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        fs::write(&temp_path, content).map_err(|error| {
+            Error::io(
+                format!("Failed to write output file `{}`", path.display()),
+                error,
+            )
+        })?;
+
+        Ok(StagedFile {
+            temp_path,
+            path: path.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// Replace the file at the final path, returning the path.
+    fn commit(mut self) -> Result<PathBuf, Error> {
+        fs::rename(&self.temp_path, &self.path).map_err(|error| {
+            Error::io(
+                format!("Failed to replace output file `{}`", self.path.display()),
+                error,
+            )
+        })?;
+        self.committed = true;
+        info!("Wrote data to file: {}", self.path.display());
+
+        Ok(std::mem::take(&mut self.path))
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Write `content` to `output_path`, replacing any file there in one step, so readers never see
+/// a partly written file.
+pub(crate) fn write_to_file<C: AsRef<[u8]>, P: AsRef<Path>>(
+    content: C,
+    output_path: &P,
+) -> Result<(), Error> {
+    StagedFile::write(content.as_ref(), output_path.as_ref())?.commit()?;
+
+    Ok(())
+}
+
+/// Stage a compressed copy of `file_content`, the content of the output at `output_path`, for
+/// each of `compress`.
+fn write_compressed_files(
+    output_path: &Path,
+    file_content: &[u8],
+    compress: &[OutputCompression],
+) -> Result<Vec<StagedFile>, Error> {
+    let mut result: Vec<StagedFile> = Vec::with_capacity(compress.len());
+    for compression in compress {
+        let mut compressed_path = output_path.as_os_str().to_owned();
+        compressed_path.push(".");
+        compressed_path.push(compression.extension());
+        let compressed_content = compression.compress(file_content).map_err(|error| {
+            Error::io(
+                format!("Failed to compress output file `{}`", output_path.display()),
+                error,
+            )
+        })?;
+        result.push(StagedFile::write(
+            &compressed_content,
+            Path::new(&compressed_path),
+        )?);
+    }
+
+    Ok(result)
+}
+
+/// Name to list `file_path` under in a manifest at `manifest_path`: just the file name for files in
+/// the same directory as the manifest, so `sha256sum --check` works there.
+fn manifest_entry_name(file_path: &Path, manifest_path: &Path) -> String {
+    let file_directory = file_path
+        .parent()
+        .and_then(|val| fs::canonicalize(val).ok());
+    let manifest_directory = manifest_path
+        .parent()
+        .and_then(|val| fs::canonicalize(val).ok());
+    match (file_directory, file_path.file_name()) {
+        (Some(file_directory_value), Some(file_name))
+            if Some(&file_directory_value) == manifest_directory.as_ref() =>
+        {
+            file_name.to_string_lossy().into_owned()
+        }
+        _ => file_path.display().to_string(),
+    }
 }
 
-fn domain_to_blocklist_rpz_domain(host: &Host) -> String {
-    let domain = host.to_string();
-    format!("{domain}\tCNAME\t.\n*.{domain}\tCNAME\t.\n")
+/// Untrusted comment line of minisign signatures.
+const MINISIGN_UNTRUSTED_COMMENT: &str = "signature from blocklist-generator secret key";
+
+/// Id for `key` in minisign public keys and signatures.  PKCS #8 keys have no id of their own, so
+/// this is the first 8 bytes of the BLAKE2b-512 hash of the key.
+fn minisign_key_id(key: &VerifyingKey) -> [u8; 8] {
+    let mut result = [0; 8];
+    result.copy_from_slice(&Blake2b512::digest(key.as_bytes())[..8]);
+
+    result
+}
+
+/// `key` as a base64 minisign public key, for checking signatures with `minisign -P`.
+#[must_use]
+pub fn minisign_public_key(key: &VerifyingKey) -> String {
+    STANDARD.encode([&b"Ed"[..], &minisign_key_id(key), key.as_bytes()].concat())
 }
 
-fn domain_to_unbound_local_zone(host: &Host) -> String {
-    let domain = host.to_string();
-    format!("local-zone: \"{domain}\" always_nxdomain\n")
+/// Detached minisign signature of `content`, in minisign's default (pre-hashed) format.
+fn minisign_signature(key: &SigningKey, content: &[u8], trusted_comment: &str) -> String {
+    let signature = key.sign(&Blake2b512::digest(content)).to_bytes();
+    let global_signature = key
+        .sign(&[&signature[..], trusted_comment.as_bytes()].concat())
+        .to_bytes();
+
+    format!(
+        "untrusted comment: {MINISIGN_UNTRUSTED_COMMENT}\n{}\ntrusted comment: \
+        {trusted_comment}\n{}\n",
+        STANDARD.encode(
+            [
+                &b"ED"[..],
+                &minisign_key_id(&key.verifying_key()),
+                &signature
+            ]
+            .concat()
+        ),
+        STANDARD.encode(global_signature)
+    )
 }
 
-fn write_to_file<P: AsRef<Path>>(content: &str, output_path: &P) {
-    let output_display_path = output_path.as_ref().display().to_string();
-    let Ok(mut outfile) = File::create(output_path) else {
-        error!("Unable to create output file");
-        panic!("Error creating output file {output_display_path}")
+/// Write a `SHA256SUMS` manifest of `file_paths`, in `sha256sum` format, and optionally sign it.
+/// Returns the paths written.
+///
+/// # Errors
+///
+/// Returns an error if an output file cannot be read, the signing key is not valid, or a file
+/// cannot be written.
+pub fn write_distribution_files(
+    file_paths: &[PathBuf],
+    distribution: &Distribution,
+) -> Result<Vec<PathBuf>, Error> {
+    let Distribution {
+        checksums,
+        signing_key,
+    } = distribution;
+    let Some(checksums_path) = checksums else {
+        if signing_key.is_some() {
+            return Err(Error::Config(String::from(
+                "`signing_key` is set, but there is no `checksums` manifest path to sign.",
+            )));
+        }
+        return Ok(Vec::new());
     };
-    if outfile.write_all(content.as_bytes()).is_err() {
-        error!("Unable to write to output file {output_display_path}");
-        panic!("Error writing to output file");
+
+    let mut manifest = String::new();
+    for file_path in file_paths {
+        let file_content = fs::read(file_path).map_err(|error| {
+            Error::io(
+                format!(
+                    "Failed to read output file `{}` for checksum",
+                    file_path.display()
+                ),
+                error,
+            )
+        })?;
+        let _ = writeln!(
+            manifest,
+            "{}  {}",
+            sha256_hex(&file_content),
+            manifest_entry_name(file_path, checksums_path)
+        );
+    }
+    write_to_file(&manifest, checksums_path)?;
+    let mut result = vec![checksums_path.clone()];
+
+    if let Some(signing_key_path) = signing_key {
+        let signing_key_pem = fs::read_to_string(signing_key_path).map_err(|error| {
+            Error::io(
+                format!(
+                    "Failed to open or read signing key file `{}`",
+                    signing_key_path.display()
+                ),
+                error,
+            )
+        })?;
+        let Ok(key) = SigningKey::from_pkcs8_pem(&signing_key_pem) else {
+            return Err(Error::Config(format!(
+                "Failed to parse signing key file `{}`.  Check it is a PEM-encoded Ed25519 \
+                private key.",
+                signing_key_path.display()
+            )));
+        };
+        let trusted_comment = format!(
+            "timestamp:{}\tfile:{}",
+            jiff::Timestamp::now().as_second(),
+            checksums_path
+                .file_name()
+                .map_or_else(String::new, |val| val.to_string_lossy().into_owned())
+        );
+        let mut signature_path = checksums_path.as_os_str().to_owned();
+        signature_path.push(".sig");
+        let signature_path = PathBuf::from(signature_path);
+        write_to_file(
+            minisign_signature(&key, manifest.as_bytes(), &trusted_comment),
+            &signature_path,
+        )?;
+        info!(
+            "Signed `{}` with minisign public key {}",
+            checksums_path.display(),
+            minisign_public_key(&key.verifying_key())
+        );
+        result.push(signature_path);
     }
-    info!("Wrote data to file: {output_display_path}");
+
+    Ok(result)
+}
+
+/// Output files written beside their final paths by [`stage_output_files`], with the outputs
+/// whose hook commands run once the files are in place.
+pub(crate) struct StagedOutputs<'a> {
+    files: Vec<StagedFile>,
+    changed_outputs: Vec<(&'a Output, PathBuf)>,
 }
 
-fn print_output_file_metadata<P: AsRef<Path>>(output_path: &P) {
-    if let Ok(value) = fs::metadata(output_path) {
-        let bytes = value.len();
-        let display_bytes = format_size(bytes, DECIMAL);
-        let display_path = output_path.as_ref().display();
-        std::println!("Written {display_bytes} to {display_path}");
+impl StagedOutputs<'_> {
+    /// Move every staged file into place, returning the paths written.
+    pub(crate) fn commit(&mut self) -> Result<Vec<PathBuf>, Error> {
+        self.files
+            .drain(..)
+            .map(StagedFile::commit)
+            .collect::<Result<Vec<PathBuf>, Error>>()
+    }
+
+    /// Run the hook commands for outputs whose content changed, once they are in place.
+    pub(crate) async fn run_hook_commands(self) {
+        for (output, output_path) in self.changed_outputs {
+            hook::run_commands(&output.hooks, &output_path).await;
+        }
     }
 }
 
-pub fn write_domain_blocklist_file(blocklist_domains: &[Host]) {
-    let domains = blocklist_domains
-        .iter()
-        .fold(String::new(), |mut acc, val| {
-            acc.push_str(&val.to_string());
-            acc.push('\n');
-            acc
-        });
-    let output_path = PathBuf::from("./domain-blocklist.txt");
-    write_to_file(&domains, &output_path);
-    print_output_file_metadata(&output_path);
-}
-
-pub fn write_blocklist_rpz_file(blocklist_domains: &[Host]) {
-    let domains = blocklist_domains
-        .iter()
-        .fold(String::new(), |mut acc, val| {
-            acc.push_str(&domain_to_blocklist_rpz_domain(val));
-            acc
-        });
-    let template = BlocklistRPZTemplate { domains: &domains };
-    let file_content = template
-        .render()
-        .expect("Unexpected error rendering template");
-    let output_path = PathBuf::from("./blocklist.rpz");
-    write_to_file(&file_content, &output_path);
-    print_output_file_metadata(&output_path);
-}
-
-pub fn write_unbound_local_zone_file(blocklist_domains: &[Host]) {
-    let output_path = PathBuf::from("./zone-block-general.conf");
-    let file_content = blocklist_domains
-        .iter()
-        .fold(String::new(), |mut acc, val| {
-            acc.push_str(&domain_to_unbound_local_zone(val));
-            acc
-        });
-    write_to_file(&file_content, &output_path);
-    print_output_file_metadata(&output_path);
+/// Write `blocklist_domains` beside each of `outputs`, as [`write_output_files`] does, and make
+/// the hook checks, without replacing any output yet.  Dropping the result removes the files.
+pub(crate) async fn stage_output_files<'a>(
+    blocklist_domains: &mut DomainSet,
+    outputs: &'a [Output],
+    config: &Config,
+    metadata: &GenerationMetadata,
+    formats: &FormatRegistry,
+) -> Result<StagedOutputs<'a>, Error> {
+    let mut result = StagedOutputs {
+        files: Vec::with_capacity(outputs.len()),
+        changed_outputs: Vec::new(),
+    };
+    for output in outputs {
+        let output_path = output.path();
+        let format_name = output.format.config_name();
+        let Some(writer) = formats.writer(format_name) else {
+            return Err(Error::Config(format!(
+                "Output `{}` has format `{format_name}`, but there is no writer for that format.",
+                output_path.display()
+            )));
+        };
+        let context = OutputContext {
+            output,
+            config,
+            metadata,
+        };
+        blocklist_domains.sort_in(output.sort);
+        let mut file_content = writer.write(blocklist_domains, &context)?;
+        let changed = !output.hooks.is_empty()
+            && hook::content_changed(&output_path, &file_content, output.header);
+        if output.header {
+            let comment_prefix = output
+                .comment_prefix
+                .as_deref()
+                .unwrap_or(writer.comment_prefix());
+            let mut header = metadata.header(comment_prefix, &file_content).into_bytes();
+            header.append(&mut file_content);
+            file_content = header;
+        }
+        let staged_file = StagedFile::write(&file_content, &output_path)?;
+        if changed {
+            hook::run_checks(&output.hooks, &staged_file.temp_path, &output_path).await?;
+            result.changed_outputs.push((output, output_path.clone()));
+        } else if !output.hooks.is_empty() {
+            info!(
+                "Content of `{}` is unchanged, so skipping its hooks",
+                output_path.display()
+            );
+        }
+        result.files.push(staged_file);
+        result.files.extend(write_compressed_files(
+            &output_path,
+            &file_content,
+            &output.compress,
+        )?);
+    }
+
+    Ok(result)
+}
+
+/// Write `blocklist_domains` to each of `outputs`, in the output's sort order, with the writer
+/// `formats` has for its format, returning the paths of all files written, including compressed
+/// copies.  No output is replaced until every one has been written, so if any output fails, the
+/// files from the last good run are left in place.  `metadata` goes in the
+/// comment header of outputs with `header` set.
+///
+/// For outputs whose content changed, each hook `check` is made before any file is replaced, and
+/// each hook `command` is run once every file is in place.
+///
+/// # Errors
+///
+/// Returns an error if an output format has no writer, the writer fails, a hook check fails, or
+/// a file cannot be written.
+pub async fn write_output_files(
+    blocklist_domains: &mut DomainSet,
+    outputs: &[Output],
+    config: &Config,
+    metadata: &GenerationMetadata,
+    formats: &FormatRegistry,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut staged_outputs =
+        stage_output_files(blocklist_domains, outputs, config, metadata, formats).await?;
+    let result = staged_outputs.commit()?;
+    staged_outputs.run_hook_commands().await;
+
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
+    use std::{io::Read, path::PathBuf, time::Duration};
 
-    use ahash::RandomState;
     use assert_fs::fixture::{FileWriteStr, PathChild};
+    use ed25519_dalek::{
+        SigningKey,
+        pkcs8::{EncodePrivateKey, spki::der::pem::LineEnding},
+    };
+    use minisign_verify::{PublicKey, Signature};
     use url::Host;
 
-    use crate::file_system::{get_config_from_file, get_custom_blocked_names};
+    use super::write_compressed_files;
+    use crate::{
+        Error,
+        domain_set::{DomainSet, SortOrder},
+        fetch::{FetchStatus, SourceReport},
+        file_system::{
+            Config, Distribution, Hook, Output, OutputCompression, OutputFormat,
+            get_config_from_file, get_custom_blocked_names, minisign_public_key,
+            write_distribution_files, write_output_files,
+        },
+        format::FormatRegistry,
+        metadata::GenerationMetadata,
+    };
+
+    fn sample_blocklist_domains() -> DomainSet {
+        ["ads.example.com", "example.net", "tracker.example.org"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect()
+    }
 
     #[test]
     fn get_config_from_file_successfully_parses_valid_file() {
@@ -201,6 +951,71 @@ allowed_names = [
         insta::assert_json_snapshot!(outcome);
     }
 
+    #[test]
+    fn get_config_from_file_parses_named_sources() {
+        // arrange
+        let config_content = r#"[[sources]]
+name = "OISD big"
+url = "https://big.oisd.nl/domainswild"
+format = "domain-list"
+category = "ads"
+timeout = 30
+min_entries = 100000
+
+[[sources]]
+name = "Private list"
+url = "https://lists.example.com/private/hosts"
+format = "hosts-file"
+category = "malware"
+enabled = false
+headers = { Authorization = "Bearer token" }
+"#;
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str(config_content);
+        let config_path = temp_dir.join("blocklist-generator.toml");
+
+        // act
+        let outcome = get_config_from_file(config_path).unwrap();
+
+        // assert
+        insta::assert_json_snapshot!(outcome);
+    }
+
+    #[test]
+    fn get_config_from_file_parses_profiles() {
+        // arrange
+        let config_content = r#"[[sources]]
+name = "OISD big"
+url = "https://big.oisd.nl/domainswild"
+format = "domain-list"
+category = "ads"
+
+[[profiles]]
+name = "kids"
+categories = ["ads", "adult"]
+filters = { allowed_names = ["example.com"] }
+outputs = [{ format = "domain-list", path = "./kids-blocklist.txt" }]
+
+[[profiles]]
+name = "work"
+sources = ["OISD big"]
+outputs = [{ format = "rpz", path = "./work-blocklist.rpz" }]
+"#;
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str(config_content);
+        let config_path = temp_dir.join("blocklist-generator.toml");
+
+        // act
+        let outcome = get_config_from_file(config_path).unwrap();
+
+        // assert
+        insta::assert_json_snapshot!(outcome.profiles);
+    }
+
     #[test]
     fn get_config_from_file_returns_error_on_config_file_system_error() {
         let temp_dir = assert_fs::TempDir::new().unwrap();
@@ -218,19 +1033,12 @@ allowed_names = [
                 &config_path.display()
             )
         );
-        let mut chain = outcome.chain();
-        assert_eq!(
-            chain.next().map(|val| format!("{val}")),
-            Some(format!(
-                "Failed to open or read config file `{}`",
-                &config_path.display()
-            ))
-        );
+        let source = std::error::Error::source(&outcome);
         assert_eq!(
-            chain.next().map(|val| format!("{val}")),
+            source.map(|val| format!("{val}")),
             Some(String::from("No such file or directory (os error 2)"))
         );
-        assert!(chain.next().is_none());
+        assert!(source.and_then(std::error::Error::source).is_none());
     }
 
     #[test]
@@ -266,19 +1074,12 @@ domain_blocklist_urls = [
         assert_eq!(
             format!("{outcome}"),
             format!(
-                "Failed to parse config file `{}`.  Check it is valid.",
+                "Failed to parse config file `{}`: unclosed table, expected `]`",
                 &config_path.display()
             )
         );
-        let mut chain = outcome.chain();
-        assert_eq!(
-            chain.next().map(|val| format!("{val}")),
-            Some(format!(
-                "Failed to parse config file `{}`.  Check it is valid.",
-                &config_path.display()
-            ))
-        );
-        assert!(chain.next().is_none());
+        assert!(matches!(outcome, Error::ConfigParse(_)));
+        assert!(std::error::Error::source(&outcome).is_none());
     }
 
     #[test]
@@ -292,8 +1093,7 @@ another.example.com
             .child("blocked-names.txt")
             .write_str(config_content);
         let blocked_names_path = temp_dir.join("blocked-names.txt");
-        let hasher = RandomState::new();
-        let mut set: HashSet<Host, RandomState> = HashSet::with_hasher(hasher);
+        let mut set = DomainSet::new();
 
         // act
         get_custom_blocked_names(&blocked_names_path, &mut set);
@@ -310,8 +1110,7 @@ another.example.com
     fn get_custom_blocked_names_does_not_modify_set_when_blocked_names_file_not_found() {
         let temp_dir = assert_fs::TempDir::new().unwrap();
         let blocked_names_path = temp_dir.join("does-not-exist.txt");
-        let hasher = RandomState::new();
-        let mut set: HashSet<Host, RandomState> = HashSet::with_hasher(hasher);
+        let mut set = DomainSet::new();
 
         // act
         get_custom_blocked_names(&blocked_names_path, &mut set);
@@ -319,4 +1118,254 @@ another.example.com
         // assert
         assert!(set.is_empty());
     }
+
+    #[tokio::test]
+    async fn write_output_files_writes_metadata_header_when_requested() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("blocklist.rpz");
+        let metadata = GenerationMetadata {
+            version: "0.1.0",
+            generated_at: String::from("2026-01-01T00:00:00Z"),
+            config_hash: String::from("0123456789abcdef"),
+            profile: None,
+            sources: vec![SourceReport {
+                name: String::from("https://example.com/hosts"),
+                url: String::from("https://example.com/hosts"),
+                entries: 3,
+                rejected: 0,
+                bytes: 0,
+                duration: Duration::ZERO,
+                status: FetchStatus::Ok,
+            }],
+            domain_count: 3,
+        };
+
+        // act
+        let outputs = [Output {
+            path: Some(output_path.clone()),
+            header: true,
+            ..Output::new(OutputFormat::Rpz)
+        }];
+        write_output_files(
+            &mut sample_blocklist_domains(),
+            &outputs,
+            &Config::default(),
+            &metadata,
+            &FormatRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        // assert
+        insta::assert_snapshot!(std::fs::read_to_string(&output_path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn write_output_files_writes_domains_in_output_sort_order() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let lexical_path = temp_dir.join("lexical.txt");
+        let reverse_label_path = temp_dir.join("reverse-label.txt");
+        let mut domains: DomainSet = ["b.example.com", "a.example.net", "a.example.com"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect();
+        let metadata = GenerationMetadata::new(String::new(), None, Vec::new(), 3);
+        let outputs = [
+            Output {
+                path: Some(reverse_label_path.clone()),
+                sort: SortOrder::ReverseLabel,
+                ..Output::new(OutputFormat::DomainList)
+            },
+            Output {
+                path: Some(lexical_path.clone()),
+                ..Output::new(OutputFormat::DomainList)
+            },
+        ];
+
+        // act
+        write_output_files(
+            &mut domains,
+            &outputs,
+            &Config::default(),
+            &metadata,
+            &FormatRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        // assert
+        assert_eq!(
+            std::fs::read_to_string(&reverse_label_path).unwrap(),
+            "a.example.com\nb.example.com\na.example.net\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&lexical_path).unwrap(),
+            "a.example.com\na.example.net\nb.example.com\n"
+        );
+    }
+
+    #[test]
+    fn write_compressed_files_writes_gzip_and_zstd_copies() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("domain-blocklist.txt");
+        let expected_content = b"ads.example.com\nexample.net\ntracker.example.org\n";
+
+        // act
+        let outcome: Vec<PathBuf> = write_compressed_files(
+            &output_path,
+            expected_content,
+            &[OutputCompression::Gzip, OutputCompression::Zstd],
+        )
+        .unwrap()
+        .into_iter()
+        .map(|val| val.commit().unwrap())
+        .collect();
+
+        // assert
+        assert_eq!(
+            outcome,
+            vec![
+                temp_dir.join("domain-blocklist.txt.gz"),
+                temp_dir.join("domain-blocklist.txt.zst")
+            ]
+        );
+        let mut gzip_content: Vec<u8> = Vec::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(&outcome[0]).unwrap())
+            .read_to_end(&mut gzip_content)
+            .unwrap();
+        assert_eq!(gzip_content, expected_content);
+        let zstd_content = zstd::decode_all(std::fs::File::open(&outcome[1]).unwrap()).unwrap();
+        assert_eq!(zstd_content, expected_content);
+    }
+
+    #[tokio::test]
+    async fn write_output_files_keeps_previous_outputs_when_an_output_fails() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("domain-blocklist.txt");
+        std::fs::write(&output_path, "previous.example.com\n").unwrap();
+        let metadata = GenerationMetadata::new(String::new(), None, Vec::new(), 3);
+        let outputs = [
+            Output {
+                path: Some(output_path.clone()),
+                ..Output::new(OutputFormat::DomainList)
+            },
+            Output {
+                path: Some(temp_dir.join("hosts.txt")),
+                ..Output::new(OutputFormat::Template)
+            },
+        ];
+
+        // act
+        let outcome = write_output_files(
+            &mut sample_blocklist_domains(),
+            &outputs,
+            &Config::default(),
+            &metadata,
+            &FormatRegistry::default(),
+        )
+        .await;
+
+        // assert
+        assert!(outcome.is_err());
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "previous.example.com\n"
+        );
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn write_output_files_runs_hook_commands_only_when_content_changes() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("domain-blocklist.txt");
+        let copy_path = temp_dir.join("hook-copy.txt");
+        let metadata = GenerationMetadata::new(String::new(), None, Vec::new(), 3);
+        let outputs = [Output {
+            path: Some(output_path.clone()),
+            header: true,
+            hooks: vec![Hook {
+                command: Some(vec![
+                    String::from("cp"),
+                    String::from("{path}"),
+                    copy_path.display().to_string(),
+                ]),
+                ..Hook::default()
+            }],
+            ..Output::new(OutputFormat::DomainList)
+        }];
+        let write = async || {
+            write_output_files(
+                &mut sample_blocklist_domains(),
+                &outputs,
+                &Config::default(),
+                &metadata,
+                &FormatRegistry::default(),
+            )
+            .await
+            .unwrap();
+        };
+
+        // act
+        write().await;
+        let outcome_first_run = copy_path.exists();
+        std::fs::remove_file(&copy_path).unwrap();
+        write().await;
+        let outcome_unchanged_run = copy_path.exists();
+
+        // assert
+        assert!(outcome_first_run);
+        assert!(!outcome_unchanged_run);
+    }
+
+    #[test]
+    fn write_distribution_files_writes_checksums_and_signature() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("domain-blocklist.txt");
+        std::fs::write(
+            &output_path,
+            "ads.example.com\nexample.net\ntracker.example.org\n",
+        )
+        .unwrap();
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let _ = temp_dir
+            .child("signing-key.pem")
+            .write_str(&signing_key.to_pkcs8_pem(LineEnding::LF).unwrap());
+        let distribution = Distribution {
+            checksums: Some(temp_dir.join("SHA256SUMS")),
+            signing_key: Some(temp_dir.join("signing-key.pem")),
+        };
+
+        // act
+        write_distribution_files(&[output_path], &distribution).unwrap();
+
+        // assert
+        let manifest = std::fs::read_to_string(temp_dir.join("SHA256SUMS")).unwrap();
+        insta::assert_snapshot!(manifest);
+        let signature_file = std::fs::read_to_string(temp_dir.join("SHA256SUMS.sig")).unwrap();
+        let signature = Signature::decode(&signature_file).unwrap();
+        let public_key =
+            PublicKey::from_base64(&minisign_public_key(&signing_key.verifying_key())).unwrap();
+        assert!(
+            public_key
+                .verify(manifest.as_bytes(), &signature, false)
+                .is_ok()
+        );
+        assert!(
+            public_key
+                .verify(b"tampered manifest", &signature, false)
+                .is_err()
+        );
+        assert_eq!(
+            signature_file.lines().next(),
+            Some("untrusted comment: signature from blocklist-generator secret key")
+        );
+        assert!(signature.trusted_comment().starts_with("timestamp:"));
+        assert!(signature.trusted_comment().ends_with("\tfile:SHA256SUMS"));
+    }
 }
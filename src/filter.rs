@@ -1,8 +1,10 @@
 #![deny(missing_docs)]
 
+//! Remove allowed names, and their parent domains, from a blocklist, and add blocked names.
+
 use url::Host;
 
-use crate::file_system::Filters;
+use crate::{domain_set::DomainSet, file_system::Filters};
 
 /// Helper function to find parent domains, up to the second level domain.  For example,
 /// with `some.subdomain.example.com` as input, result should be Some, and a vector of
@@ -32,10 +34,24 @@ fn parent_domains(domain: &Host) -> Option<Vec<Host>> {
 /// Remove any allowlist members found in `blocklist`.  If the allowlist member is a subdomain, any
 /// occurrences of parent domain also get removed (`some.example.com` in allowlist results in
 /// `example.com` being removed from `blocklist`)
-pub fn blocklist(
-    blocklist: &mut std::collections::HashSet<Host, ahash::RandomState>,
-    filters: &Filters,
-) {
+///
+/// ```
+/// use blocklist_generator::{domain_set::DomainSet, file_system::Filters, filter::blocklist};
+/// use url::Host;
+///
+/// let mut set = DomainSet::new();
+/// set.insert(Host::parse("ads.example.com").unwrap());
+/// set.insert(Host::parse("example.com").unwrap());
+/// let filters = Filters {
+///     allowed_names: Some(vec![String::from("www.example.com")]),
+///     blocked_names: None,
+/// };
+/// blocklist(&mut set, &filters);
+///
+/// assert!(!set.contains(&Host::parse("example.com").unwrap()));
+/// assert!(set.contains(&Host::parse("ads.example.com").unwrap()));
+/// ```
+pub fn blocklist(blocklist: &mut DomainSet, filters: &Filters) {
     if let Some(allowed_names_value) = &filters.allowed_names {
         for name in allowed_names_value {
             match Host::parse(name) {
@@ -69,11 +85,44 @@ pub fn blocklist(
     }
 }
 
+/// Add the `blocked_names` in `filters` to `blocklist`, skipping any which are not valid
+/// names.
+///
+/// ```
+/// use blocklist_generator::{domain_set::DomainSet, file_system::Filters, filter::add_blocked_names};
+/// use url::Host;
+///
+/// let mut set = DomainSet::new();
+/// let filters = Filters {
+///     allowed_names: None,
+///     blocked_names: Some(vec![String::from("ads.example.com")]),
+/// };
+/// add_blocked_names(&mut set, &filters);
+///
+/// assert!(set.contains(&Host::parse("ads.example.com").unwrap()));
+/// ```
+pub fn add_blocked_names(blocklist: &mut DomainSet, filters: &Filters) {
+    for name in filters.blocked_names.iter().flatten() {
+        match Host::parse(name) {
+            Ok(value) => {
+                blocklist.insert(value);
+            }
+            Err(_) => {
+                log::error!("Ignoring blocked_names element: `{name}`.  Check it is valid.");
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use url::Host;
 
-    use crate::{file_system::Filters, filter::blocklist};
+    use crate::{
+        domain_set::DomainSet,
+        file_system::Filters,
+        filter::{add_blocked_names, blocklist},
+    };
 
     use super::parent_domains;
 
@@ -89,9 +138,7 @@ mod tests {
             blocked_names: None,
         };
 
-        let hasher = ahash::RandomState::new();
-        let mut set: std::collections::HashSet<Host, ahash::RandomState> =
-            std::collections::HashSet::with_hasher(hasher);
+        let mut set = DomainSet::new();
         set.insert(Host::parse("example.com").unwrap());
         set.insert(Host::parse("another.example.com").unwrap());
         set.insert(Host::parse("127.0.0.1").unwrap()); // DevSkim: ignore DS162092 - use of local host IP is in test
@@ -121,9 +168,7 @@ mod tests {
             blocked_names: None,
         };
 
-        let hasher = ahash::RandomState::new();
-        let mut set: std::collections::HashSet<Host, ahash::RandomState> =
-            std::collections::HashSet::with_hasher(hasher);
+        let mut set = DomainSet::new();
         set.insert(Host::parse("example.com").unwrap());
         set.insert(Host::parse("another.example.com").unwrap());
         set.insert(Host::parse("127.0.0.1").unwrap()); // DevSkim: ignore DS162092 - use of local host IP is in test
@@ -154,9 +199,7 @@ mod tests {
             blocked_names: None,
         };
 
-        let hasher = ahash::RandomState::new();
-        let mut set: std::collections::HashSet<Host, ahash::RandomState> =
-            std::collections::HashSet::with_hasher(hasher);
+        let mut set = DomainSet::new();
         set.insert(Host::parse("example.com").unwrap());
         set.insert(Host::parse("some.example.com").unwrap());
         set.insert(Host::parse("127.0.0.1").unwrap()); // DevSkim: ignore DS162092 - use of local host IP is in test
@@ -174,6 +217,29 @@ mod tests {
         assert!(set.contains(&Host::parse("some.example.com").unwrap(),));
     }
 
+    #[test]
+    fn add_blocked_names_adds_valid_names() {
+        // arrange
+        let filters = Filters {
+            allowed_names: None,
+            blocked_names: Some(vec![
+                String::from("ads.example.com"),
+                String::from("example.net"),
+                String::from("not a domain"),
+            ]),
+        };
+        let mut set = DomainSet::new();
+        set.insert(Host::parse("example.net").unwrap());
+
+        // act
+        add_blocked_names(&mut set, &filters);
+
+        // assert
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&Host::parse("ads.example.com").unwrap()));
+        assert!(set.contains(&Host::parse("example.net").unwrap()));
+    }
+
     #[test]
     fn parent_domains_returns_none_for_tld() {
         // arrange
@@ -0,0 +1,190 @@
+//! Source parsers and output writers, looked up by format name.
+//!
+//! Every built-in format is registered in [`FormatRegistry::default`].  Add a format by
+//! implementing [`SourceParser`] or [`OutputWriter`] and registering it under the name used for
+//! `format` in the config:
+//!
+//! ```
+//! use blocklist_generator::{
+//!     domain_set::DomainSet,
+//!     format::{FormatRegistry, ParseDiagnostic, SourceParser},
+//! };
+//! use url::Host;
+//!
+//! /// `address=/example.com/0.0.0.0` lines from a dnsmasq config
+//! struct DnsmasqParser;
+//!
+//! impl SourceParser for DnsmasqParser {
+//!     fn parse(&self, body: &[u8], hosts: &mut DomainSet) -> Vec<ParseDiagnostic> {
+//!         let body = String::from_utf8_lossy(body);
+//!         for line in body.lines() {
+//!             if let Some(domain) = line.strip_prefix("address=/").and_then(|val| val.split('/').next())
+//!                 && let Ok(host) = Host::parse(domain)
+//!             {
+//!                 hosts.insert(host);
+//!             }
+//!         }
+//!         Vec::new()
+//!     }
+//! }
+//!
+//! let formats = FormatRegistry::default().with_parser("dnsmasq", DnsmasqParser);
+//! let mut hosts = DomainSet::new();
+//! formats
+//!     .parser("dnsmasq")
+//!     .unwrap()
+//!     .parse(b"address=/ads.example.com/0.0.0.0\n", &mut hosts);
+//!
+//! assert!(hosts.contains(&Host::parse("ads.example.com").unwrap()));
+//! ```
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use crate::{
+    domain_set::DomainSet,
+    error::Error,
+    file_system::{Config, Output},
+    metadata::GenerationMetadata,
+    output::{
+        AdguardHomeWriter, CorednsHostsWriter, DomainListWriter, PiholeRegexWriter,
+        PowerdnsLuaWriter, RpzWriter, TemplateWriter, UnboundWriter,
+    },
+    parse::{DomainListParser, HostsFileParser},
+};
+
+/// Line in a source which could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    /// Line number, starting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Reads the hosts listed in a fetched source.
+pub trait SourceParser: Send + Sync {
+    /// Add the hosts listed in `body`, already decompressed, to `hosts`.  Returns a diagnostic
+    /// for each line which is not blank or a comment, but could not be parsed, numbered from the
+    /// start of `body`.
+    ///
+    /// Sources are parsed as they arrive, so `body` is a run of whole lines, and `parse` is
+    /// called once for each run in the source.
+    fn parse(&self, body: &[u8], hosts: &mut DomainSet) -> Vec<ParseDiagnostic>;
+}
+
+/// Everything an [`OutputWriter`] might need, besides the domains.
+pub struct OutputContext<'a> {
+    /// Output entry being written, with any format-specific settings
+    pub output: &'a Output,
+    pub config: &'a Config,
+    pub metadata: &'a GenerationMetadata,
+}
+
+/// Renders a blocklist in a resolver's format.
+pub trait OutputWriter: Send + Sync {
+    /// Start of each line of the metadata comment header, unless the output sets
+    /// `comment_prefix`.
+    fn comment_prefix(&self) -> &'static str {
+        "#"
+    }
+
+    /// File content for `domains`, already in the output's sort order, without any metadata
+    /// header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the output settings are missing something the format needs, or the
+    /// content cannot be rendered.
+    fn write(&self, domains: &DomainSet, context: &OutputContext<'_>) -> Result<Vec<u8>, Error>;
+}
+
+/// Source parsers and output writers, keyed by the format name used in the config.
+#[derive(Clone)]
+pub struct FormatRegistry {
+    parsers: BTreeMap<String, Arc<dyn SourceParser>>,
+    writers: BTreeMap<String, Arc<dyn OutputWriter>>,
+}
+
+impl Default for FormatRegistry {
+    /// Registry with every built-in format.
+    fn default() -> Self {
+        FormatRegistry::empty()
+            .with_parser("domain-list", DomainListParser)
+            .with_parser("hosts-file", HostsFileParser)
+            .with_writer("rpz", RpzWriter)
+            .with_writer("unbound", UnboundWriter)
+            .with_writer("domain-list", DomainListWriter)
+            .with_writer("adguard-home", AdguardHomeWriter)
+            .with_writer("pihole", DomainListWriter)
+            .with_writer("pihole-regex", PiholeRegexWriter)
+            .with_writer("blocky", DomainListWriter)
+            .with_writer("coredns-hosts", CorednsHostsWriter)
+            .with_writer("powerdns-lua", PowerdnsLuaWriter)
+            .with_writer("template", TemplateWriter)
+    }
+}
+
+impl FormatRegistry {
+    /// Registry with no formats, not even the built-in ones.
+    #[must_use]
+    pub fn empty() -> Self {
+        FormatRegistry {
+            parsers: BTreeMap::new(),
+            writers: BTreeMap::new(),
+        }
+    }
+
+    /// Register `parser` for sources with `format = "<name>"`, replacing any parser already
+    /// registered under `name`.
+    #[must_use]
+    pub fn with_parser(
+        mut self,
+        name: impl Into<String>,
+        parser: impl SourceParser + 'static,
+    ) -> Self {
+        self.parsers.insert(name.into(), Arc::new(parser));
+        self
+    }
+
+    /// Register `writer` for outputs with `format = "<name>"`, replacing any writer already
+    /// registered under `name`.
+    #[must_use]
+    pub fn with_writer(
+        mut self,
+        name: impl Into<String>,
+        writer: impl OutputWriter + 'static,
+    ) -> Self {
+        self.writers.insert(name.into(), Arc::new(writer));
+        self
+    }
+
+    #[must_use]
+    pub fn parser(&self, name: &str) -> Option<&dyn SourceParser> {
+        self.parsers.get(name).map(AsRef::as_ref)
+    }
+
+    /// Parser for `name`, for handing to parse worker threads.
+    pub(crate) fn shared_parser(&self, name: &str) -> Option<Arc<dyn SourceParser>> {
+        self.parsers.get(name).map(Arc::clone)
+    }
+
+    #[must_use]
+    pub fn writer(&self, name: &str) -> Option<&dyn OutputWriter> {
+        self.writers.get(name).map(AsRef::as_ref)
+    }
+
+    /// Source format names, in order.
+    pub fn parser_names(&self) -> impl Iterator<Item = &str> {
+        self.parsers.keys().map(String::as_str)
+    }
+
+    /// Output format names, in order.
+    pub fn writer_names(&self) -> impl Iterator<Item = &str> {
+        self.writers.keys().map(String::as_str)
+    }
+}
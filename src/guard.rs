@@ -0,0 +1,150 @@
+use url::Host;
+
+use crate::{domain_set::DomainSet, file_system::Guard};
+
+#[derive(thiserror::Error, Debug)]
+pub enum GuardError {
+    #[error(
+        "The new blocklist blocks canary names which must never be blocked: {names}.  Check the \
+        sources and filters, or use `--force` to write it anyway."
+    )]
+    Canary { names: String },
+
+    #[error(
+        "The new blocklist has {current} domains, against {previous} previously, which is a \
+        change of more than the {limit}% limit.  Check the sources, or use `--force` to write it \
+        anyway."
+    )]
+    Change {
+        previous: usize,
+        current: usize,
+        limit: u8,
+    },
+}
+
+/// `canary_name` is blocked if it, or any parent domain, is in `domains`, since most resolvers
+/// block subdomains of a blocked name too.
+fn is_blocked(canary_name: &str, domains: &DomainSet) -> bool {
+    let canary_name = canary_name.trim_end_matches('.').to_ascii_lowercase();
+    let host = Host::Domain(canary_name.as_str());
+
+    domains.contains(&host) || domains.listed_parent(&host).is_some()
+}
+
+/// Check the new `blocklist_domains` against the `guard` settings before any output is
+/// overwritten.  `previous_domains` is the set from the previous output, if there is one.
+///
+/// # Errors
+///
+/// Returns an error for the first check the new blocklist fails.
+pub fn check(
+    guard: &Guard,
+    blocklist_domains: &DomainSet,
+    previous_domains: Option<&DomainSet>,
+) -> Result<(), GuardError> {
+    if let Some(canary_names) = &guard.canary_names {
+        let blocked_canary_names: Vec<&str> = canary_names
+            .iter()
+            .filter(|val| is_blocked(val, blocklist_domains))
+            .map(String::as_str)
+            .collect();
+        if !blocked_canary_names.is_empty() {
+            return Err(GuardError::Canary {
+                names: blocked_canary_names.join(", "),
+            });
+        }
+    }
+
+    if let Some(limit) = guard.max_change_percent {
+        match previous_domains {
+            Some(value) if !value.is_empty() => {
+                let previous = value.len();
+                let current = blocklist_domains.len();
+                if previous.abs_diff(current) * 100 > previous * usize::from(limit) {
+                    return Err(GuardError::Change {
+                        previous,
+                        current,
+                        limit,
+                    });
+                }
+            }
+            _ => log::warn!(
+                "No previous output to compare against, so skipping the `max_change_percent` check"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Host;
+
+    use crate::{
+        domain_set::DomainSet,
+        file_system::Guard,
+        guard::{GuardError, check},
+    };
+
+    fn hosts(names: &[&str]) -> DomainSet {
+        names.iter().map(|val| Host::parse(val).unwrap()).collect()
+    }
+
+    #[test]
+    fn check_rejects_blocklist_blocking_canary_name() {
+        // arrange
+        let guard = Guard {
+            max_change_percent: None,
+            canary_names: Some(vec![
+                String::from("www.example.com"),
+                String::from("example.org"),
+            ]),
+            previous_output: None,
+        };
+        let blocklist_domains = hosts(&["ads.example.net", "example.com"]);
+
+        // act
+        let outcome = check(&guard, &blocklist_domains, None).unwrap_err();
+
+        // assert
+        assert_eq!(
+            format!("{outcome}"),
+            "The new blocklist blocks canary names which must never be blocked: \
+            www.example.com.  Check the sources and filters, or use `--force` to write it anyway."
+        );
+    }
+
+    #[test]
+    fn check_rejects_large_change_in_domain_count() {
+        // arrange
+        let guard = Guard {
+            max_change_percent: Some(50),
+            canary_names: None,
+            previous_output: None,
+        };
+        let previous_domains = hosts(&[
+            "a.example.com",
+            "b.example.com",
+            "c.example.com",
+            "d.example.com",
+        ]);
+        let small_change = hosts(&["a.example.com", "b.example.com"]);
+        let large_change = hosts(&["a.example.com"]);
+
+        // act
+        let outcome_small_change = check(&guard, &small_change, Some(&previous_domains));
+        let outcome_large_change = check(&guard, &large_change, Some(&previous_domains));
+
+        // assert
+        assert!(outcome_small_change.is_ok());
+        assert!(matches!(
+            outcome_large_change,
+            Err(GuardError::Change {
+                previous: 4,
+                current: 1,
+                limit: 50
+            })
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! Checks and commands run when the content of an output changes.
+
+use std::{fs, path::Path, process::ExitStatus, process::Stdio, time::Duration};
+
+use log::{error, info};
+use tokio::process::Command;
+
+use crate::{
+    error::Error,
+    file_system::{Hook, HookCheck},
+    metadata::sha256_hex,
+};
+
+/// Zone name passed to `named-checkzone`, unless the hook sets one.
+const DEFAULT_CHECK_ZONE: &str = "rpz";
+
+/// Exit status and output of a hook program which finished.
+struct HookOutput {
+    status: ExitStatus,
+
+    /// Standard output, followed by standard error
+    output: String,
+}
+
+impl HookOutput {
+    fn log(&self, program: &str) {
+        for line in self.output.lines() {
+            info!("{program}: {line}");
+        }
+    }
+}
+
+/// Run `program` with `args`, stopping it if it has not finished after `timeout`.
+async fn run(program: &str, args: &[String], timeout: Duration) -> Result<HookOutput, String> {
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| format!("Failed to start `{program}`: {error}"))?;
+
+    // both pipes are read as the program runs, so it cannot block on a full pipe, and dropping
+    // the child on timeout stops the program
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => return Err(format!("Failed to wait for `{program}`: {error}")),
+        Err(_) => {
+            return Err(format!(
+                "`{program}` did not finish within {timeout:?}, so was stopped"
+            ));
+        }
+    };
+    let mut result = String::from_utf8_lossy(&output.stdout).into_owned();
+    result.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Ok(HookOutput {
+        status: output.status,
+        output: result,
+    })
+}
+
+/// Whether `body`, the new content for the output at `path` (without any header), differs from
+/// the file there now.  Headers record the generation time, so they differ on every run;
+/// for outputs with a header, the `Content SHA-256` recorded in the existing header is compared
+/// instead.
+pub(crate) fn content_changed(path: &Path, body: &[u8], header: bool) -> bool {
+    let Ok(existing) = fs::read(path) else {
+        return true;
+    };
+    if header {
+        let digest_line = format!(" Content SHA-256: {}", sha256_hex(body));
+        return !existing
+            .split(|val| *val == b'\n')
+            .any(|line| line.ends_with(digest_line.as_bytes()));
+    }
+
+    existing != body
+}
+
+/// Unbound only accepts `local-zone` lines inside a `server:` clause, so the new output is
+/// checked through a minimal config which includes it.
+async fn check_unbound(hook: &Hook, staged_path: &Path) -> Result<HookOutput, String> {
+    let staged_path = fs::canonicalize(staged_path)
+        .map_err(|error| format!("Failed to find `{}`: {error}", staged_path.display()))?;
+    let mut config_path = staged_path.as_os_str().to_owned();
+    config_path.push(".checkconf");
+    let config = format!(
+        "server:\n\tchroot: \"\"\n\tinclude: \"{}\"\n",
+        staged_path.display()
+    );
+    fs::write(&config_path, config).map_err(|error| {
+        format!(
+            "Failed to write `unbound-checkconf` config `{}`: {error}",
+            config_path.display()
+        )
+    })?;
+    let result = run(
+        "unbound-checkconf",
+        &[config_path.to_string_lossy().into_owned()],
+        hook.timeout(),
+    )
+    .await;
+    let _ = fs::remove_file(&config_path);
+
+    result
+}
+
+/// Make each built-in check in `hooks` on `staged_path`, the new file for the output at
+/// `output_path`, logging the output of each.
+///
+/// # Errors
+///
+/// Returns an error if a check fails, or cannot be run.
+pub(crate) async fn run_checks(
+    hooks: &[Hook],
+    staged_path: &Path,
+    output_path: &Path,
+) -> Result<(), Error> {
+    for hook in hooks {
+        let Some(check) = hook.check else {
+            continue;
+        };
+        let (program, outcome) = match check {
+            HookCheck::UnboundCheckconf => {
+                ("unbound-checkconf", check_unbound(hook, staged_path).await)
+            }
+            HookCheck::NamedCheckzone => {
+                let zone = hook.zone.as_deref().unwrap_or(DEFAULT_CHECK_ZONE);
+                let args = [
+                    String::from(zone),
+                    staged_path.to_string_lossy().into_owned(),
+                ];
+                (
+                    "named-checkzone",
+                    run("named-checkzone", &args, hook.timeout()).await,
+                )
+            }
+        };
+        match outcome {
+            Ok(value) if value.status.success() => {
+                value.log(program);
+                info!("`{program}` passed the new `{}`", output_path.display());
+            }
+            Ok(value) => {
+                return Err(Error::Hook(format!(
+                    "`{program}` rejected the new `{}` ({}), so no outputs were replaced:\n{}",
+                    output_path.display(),
+                    value.status,
+                    value.output.trim_end()
+                )));
+            }
+            Err(message) => {
+                return Err(Error::Hook(format!(
+                    "{message}, so no outputs were replaced"
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run each command in `hooks` for the output at `output_path`, which is already in place,
+/// logging the output of each.  A command which fails is logged, but does not fail the run.
+pub(crate) async fn run_commands(hooks: &[Hook], output_path: &Path) {
+    let path = output_path.to_string_lossy();
+    for hook in hooks {
+        let Some((program, args)) = hook.command.as_deref().and_then(<[String]>::split_first)
+        else {
+            continue;
+        };
+        let args: Vec<String> = args
+            .iter()
+            .map(|val| val.replace("{path}", &path))
+            .collect();
+        match run(program, &args, hook.timeout()).await {
+            Ok(value) if value.status.success() => {
+                value.log(program);
+                info!("Ran `{program}` for `{path}`");
+            }
+            Ok(value) => error!(
+                "`{program}` failed for `{path}` ({}):\n{}",
+                value.status,
+                value.output.trim_end()
+            ),
+            Err(message) => error!("{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{
+        hook::{content_changed, run},
+        metadata::sha256_hex,
+    };
+
+    #[test]
+    fn content_changed_compares_the_recorded_digest_for_outputs_with_a_header() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let body = b"example.com\n";
+        let _ = temp_dir.child("with-header.txt").write_str(&format!(
+            "# Generated at: 2026-01-01T00:00:00Z\n# Content SHA-256: {}\nexample.com\n",
+            sha256_hex(body)
+        ));
+        let _ = temp_dir.child("plain.txt").write_str("example.com\n");
+
+        // act
+        let outcome_header = content_changed(&temp_dir.join("with-header.txt"), body, true);
+        let outcome_plain = content_changed(&temp_dir.join("plain.txt"), body, false);
+        let outcome_new_body =
+            content_changed(&temp_dir.join("plain.txt"), b"example.net\n", false);
+        let outcome_missing = content_changed(&temp_dir.join("missing.txt"), body, false);
+
+        // assert
+        assert!(!outcome_header);
+        assert!(!outcome_plain);
+        assert!(outcome_new_body);
+        assert!(outcome_missing);
+    }
+
+    #[tokio::test]
+    async fn run_stops_a_program_which_does_not_finish_within_the_timeout() {
+        // arrange
+        let args = [String::from("5")];
+
+        // act
+        let outcome = run("sleep", &args, Duration::from_millis(100)).await;
+
+        // assert
+        let Err(message) = outcome else {
+            panic!("Expected the program to be stopped");
+        };
+        assert_eq!(
+            message,
+            "`sleep` did not finish within 100ms, so was stopped"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use askama::Template;
+
+use blocklist_generator::SourceType;
+
+use crate::cli::{Resolver, SourceCategory};
+
+/// Well-known source, offered by `init`.
+pub struct CatalogueSource {
+    pub name: &'static str,
+    pub url: &'static str,
+    pub format: SourceType,
+    pub category: SourceCategory,
+}
+
+pub const CATALOGUE: &[CatalogueSource] = &[
+    CatalogueSource {
+        name: "OISD big",
+        url: "https://big.oisd.nl/domainswild",
+        format: SourceType::DomainList,
+        category: SourceCategory::Ads,
+    },
+    CatalogueSource {
+        name: "AdGuard DNS",
+        url: "https://v.firebog.net/hosts/AdguardDNS.txt",
+        format: SourceType::DomainList,
+        category: SourceCategory::Ads,
+    },
+    CatalogueSource {
+        name: "Disconnect ads",
+        url: "https://s3.amazonaws.com/lists.disconnect.me/simple_ad.txt",
+        format: SourceType::DomainList,
+        category: SourceCategory::Ads,
+    },
+    CatalogueSource {
+        name: "Peter Lowe's ad servers",
+        url: "https://pgl.yoyo.org/adservers/serverlist.php?hostformat=hosts;showintro=0",
+        format: SourceType::HostsFile,
+        category: SourceCategory::Ads,
+    },
+    CatalogueSource {
+        name: "EasyPrivacy",
+        url: "https://v.firebog.net/hosts/Easyprivacy.txt",
+        format: SourceType::DomainList,
+        category: SourceCategory::Tracking,
+    },
+    CatalogueSource {
+        name: "Disconnect tracking",
+        url: "https://s3.amazonaws.com/lists.disconnect.me/simple_tracking.txt",
+        format: SourceType::DomainList,
+        category: SourceCategory::Tracking,
+    },
+    CatalogueSource {
+        name: "NoTrack trackers",
+        url: "https://quidsup.net/notrack/blocklist.php?download=trackersdomains",
+        format: SourceType::DomainList,
+        category: SourceCategory::Tracking,
+    },
+    CatalogueSource {
+        name: "NoTrack malware",
+        url: "https://quidsup.net/notrack/blocklist.php?download=malwaredomains",
+        format: SourceType::DomainList,
+        category: SourceCategory::Malware,
+    },
+    CatalogueSource {
+        name: "URLhaus",
+        url: "https://urlhaus.abuse.ch/downloads/hostfile/",
+        format: SourceType::HostsFile,
+        category: SourceCategory::Malware,
+    },
+    CatalogueSource {
+        name: "Phishing Army",
+        url: "https://phishing.army/download/phishing_army_blocklist.txt",
+        format: SourceType::DomainList,
+        category: SourceCategory::Malware,
+    },
+    CatalogueSource {
+        name: "StevenBlack adult",
+        url: "https://raw.githubusercontent.com/StevenBlack/hosts/master/alternates/porn-only/hosts",
+        format: SourceType::HostsFile,
+        category: SourceCategory::Adult,
+    },
+    CatalogueSource {
+        name: "StevenBlack gambling",
+        url: "https://raw.githubusercontent.com/StevenBlack/hosts/master/alternates/gambling-only/hosts",
+        format: SourceType::HostsFile,
+        category: SourceCategory::Gambling,
+    },
+];
+
+/// Categories included when none are chosen.
+const DEFAULT_CATEGORIES: &[SourceCategory] = &[
+    SourceCategory::Ads,
+    SourceCategory::Tracking,
+    SourceCategory::Malware,
+];
+
+impl SourceCategory {
+    /// Name used for the category in config files.
+    pub fn config_name(self) -> &'static str {
+        match self {
+            SourceCategory::Ads => "ads",
+            SourceCategory::Tracking => "tracking",
+            SourceCategory::Malware => "malware",
+            SourceCategory::Adult => "adult",
+            SourceCategory::Gambling => "gambling",
+        }
+    }
+}
+
+impl Resolver {
+    /// Output format config name, and a description of the resolver, for its `[[outputs]]` entry.
+    fn output(self) -> (&'static str, &'static str) {
+        match self {
+            Resolver::Bind => ("rpz", "BIND response policy zone"),
+            Resolver::Knot => ("rpz", "Knot Resolver response policy zone"),
+            Resolver::Unbound => ("unbound", "Unbound `local-zone` config"),
+            Resolver::AdguardHome => ("adguard-home", "AdGuard Home rules"),
+            Resolver::Pihole => ("pihole", "Pi-hole gravity adlist"),
+            Resolver::Blocky => ("blocky", "Blocky denylist"),
+            Resolver::Coredns => ("coredns-hosts", "CoreDNS `hosts` plugin file"),
+            Resolver::Powerdns => ("powerdns-lua", "PowerDNS Recursor Lua script"),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(escape = "none", path = "starter-config.toml")]
+struct StarterConfigTemplate<'a> {
+    sources: &'a str,
+    outputs: &'a str,
+}
+
+/// Commented starter config, with catalogue sources in `categories` and outputs for
+/// `resolvers`.  Defaults apply to either when empty.
+pub fn starter_config(categories: &[SourceCategory], resolvers: &[Resolver]) -> String {
+    let categories = if categories.is_empty() {
+        DEFAULT_CATEGORIES
+    } else {
+        categories
+    };
+    let sources = CATALOGUE
+        .iter()
+        .filter(|val| categories.contains(&val.category))
+        .fold(String::new(), |mut acc, val| {
+            let _ = writeln!(
+                acc,
+                "[[sources]]\nname = \"{}\"\nurl = \"{}\"\nformat = \"{}\"\ncategory = \"{}\"\n",
+                val.name,
+                val.url,
+                val.format.config_name(),
+                val.category.config_name()
+            );
+            acc
+        });
+
+    let mut output_formats: Vec<&str> = Vec::new();
+    let mut outputs = String::from(
+        "# Output files to write, each at a format-specific path unless `path` is set.\n",
+    );
+    for resolver in resolvers {
+        let (format, description) = resolver.output();
+        if output_formats.contains(&format) {
+            continue;
+        }
+        output_formats.push(format);
+        let _ = writeln!(
+            outputs,
+            "# {description}\n[[outputs]]\nformat = \"{format}\"\n"
+        );
+    }
+    if output_formats.is_empty() {
+        outputs.push_str(
+            "# Without any `[[outputs]]` entries, the RPZ, Unbound and domain list files\n\
+            # are written to the working directory.\n\
+            # [[outputs]]\n\
+            # format = \"domain-list\"\n\
+            # path = \"./domain-blocklist.txt\"\n\n",
+        );
+    }
+
+    StarterConfigTemplate {
+        sources: &sources,
+        outputs: &outputs,
+    }
+    .render()
+    .expect("Starter config template should render")
+}
+
+/// Catalogue sources, one per line, for `init --list`.
+pub fn catalogue_listing() -> String {
+    CATALOGUE.iter().fold(String::new(), |mut acc, val| {
+        let _ = writeln!(
+            acc,
+            "{:<9} {:<24} {}",
+            val.category.config_name(),
+            val.name,
+            val.url
+        );
+        acc
+    })
+}
+
+/// Write `content` to `config_path`.  An existing file is only replaced with `force` set.
+pub fn write_starter_config(config_path: &Path, content: &str, force: bool) -> anyhow::Result<()> {
+    let mut options = OpenOptions::new();
+    if force {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    let mut file = match options.open(config_path) {
+        Ok(value) => value,
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => anyhow::bail!(
+            "Config file `{}` already exists.  Use `--force` to overwrite it.",
+            config_path.display()
+        ),
+        Err(error) => {
+            return Err(error).with_context(|| {
+                format!("Failed to create config file `{}`", config_path.display())
+            });
+        }
+    };
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write config file `{}`", config_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use blocklist_generator::file_system::Config;
+
+    use crate::{
+        cli::{Resolver, SourceCategory},
+        init::{starter_config, write_starter_config},
+    };
+
+    #[test]
+    fn starter_config_includes_chosen_sources_and_outputs() {
+        // arrange
+        let categories = [SourceCategory::Malware];
+        let resolvers = [Resolver::Bind, Resolver::Knot, Resolver::Pihole];
+
+        // act
+        let outcome = starter_config(&categories, &resolvers);
+
+        // assert
+        let config: Config = toml::from_str(&outcome).unwrap();
+        assert_eq!(config.sources.map(|val| val.len()), Some(3));
+        assert_eq!(config.outputs.map(|val| val.len()), Some(2));
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn write_starter_config_only_overwrites_with_force() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str("[blocklists]\n");
+        let config_path = temp_dir.join("blocklist-generator.toml");
+
+        // act
+        let outcome = write_starter_config(&config_path, "[filters]\n", false).unwrap_err();
+
+        // assert
+        assert_eq!(
+            format!("{outcome}"),
+            format!(
+                "Config file `{}` already exists.  Use `--force` to overwrite it.",
+                config_path.display()
+            )
+        );
+        assert_eq!(
+            std::fs::read_to_string(&config_path).unwrap(),
+            "[blocklists]\n"
+        );
+        write_starter_config(&config_path, "[filters]\n", true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&config_path).unwrap(),
+            "[filters]\n"
+        );
+    }
+}
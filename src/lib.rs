@@ -0,0 +1,180 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+//! Build DNS blocklists from published host and domain lists.
+//!
+//! A [`Pipeline`] fetches each source, parses it, removes allowed names and writes the result in
+//! the formats your resolvers use:
+//!
+//! ```no_run
+//! use blocklist_generator::{
+//!     Pipeline, SourceType,
+//!     file_system::{Filters, Output, OutputFormat},
+//! };
+//!
+//! # async fn run() -> Result<(), blocklist_generator::Error> {
+//! let report = Pipeline::builder()
+//!     .source("https://v.firebog.net/hosts/AdguardDNS.txt", SourceType::DomainList)
+//!     .filters(Filters {
+//!         allowed_names: Some(vec![String::from("example.com")]),
+//!         blocked_names: None,
+//!     })
+//!     .output(Output::new(OutputFormat::Unbound))
+//!     .build()?
+//!     .run()
+//!     .await?;
+//! println!("{} domains", report.builds[0].domain_count);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The parsing and filtering steps are also available on their own, in [`parse`] and
+//! [`filter`].
+
+mod cache;
+pub mod config;
+mod decompress;
+pub mod diff;
+pub mod domain_set;
+mod error;
+pub mod fetch;
+pub mod file_system;
+pub mod filter;
+pub mod format;
+pub mod guard;
+mod hook;
+pub mod metadata;
+pub mod metrics;
+mod output;
+pub mod parse;
+mod pipeline;
+mod template;
+pub mod validate;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::file_system::{Blocklists, Config, SourceOptions};
+pub use crate::{
+    error::Error,
+    pipeline::{BuildReport, Pipeline, PipelineBuilder, RunReport, profile_file_path},
+};
+
+/// Format of a source blocklist.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceType {
+    /// One domain per line
+    DomainList,
+
+    /// Hosts file, mapping each domain to a sinkhole address such as `0.0.0.0`
+    HostsFile,
+
+    /// Format with a parser registered under this name in a [`format::FormatRegistry`]
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl SourceType {
+    /// Name used for the source format in config files.
+    #[must_use]
+    pub fn config_name(&self) -> &str {
+        match self {
+            SourceType::DomainList => "domain-list",
+            SourceType::HostsFile => "hosts-file",
+            SourceType::Custom(value) => value,
+        }
+    }
+}
+
+/// A source to fetch, borrowing its settings from the config.
+#[derive(Debug)]
+pub struct Source<'a> {
+    /// Used in logs and reports.  Defaults to the URL, as written.
+    pub name: &'a str,
+    pub url: &'a str,
+
+    /// URL as written in the config, with any `${NAME}` references left in place.  Reports,
+    /// outputs and the cache use this, rather than `url`, so secrets are not published.
+    pub display_url: &'a str,
+    pub source_type: SourceType,
+    pub category: Option<&'a str>,
+    pub timeout: Option<Duration>,
+    pub headers: Option<&'a BTreeMap<String, String>>,
+    pub options: Option<&'a SourceOptions>,
+}
+
+impl<'a> Source<'a> {
+    /// Source at `url`, named by its URL, with default options.
+    #[must_use]
+    pub fn new(url: &'a str, source_type: SourceType) -> Self {
+        Source {
+            name: url,
+            url,
+            display_url: url,
+            source_type,
+            category: None,
+            timeout: None,
+            headers: None,
+            options: None,
+        }
+    }
+}
+
+/// Enabled sources from `[[sources]]` entries, followed by any in `[blocklists]`.
+#[must_use]
+pub fn sources_from_config(config: &Config) -> Vec<Source<'_>> {
+    let mut result: Vec<Source> = Vec::new();
+    let Config {
+        blocklists:
+            Blocklists {
+                hosts_file_blocklist_urls,
+                domain_blocklist_urls,
+            },
+        sources,
+        source_options,
+        ..
+    } = config;
+    let options = |url: &str| {
+        source_options.as_ref().and_then(|source_options_value| {
+            source_options_value
+                .get(config.display_url(url))
+                .or_else(|| source_options_value.get(url))
+        })
+    };
+
+    for val in sources.iter().flatten() {
+        let name = config.source_name(val);
+        if !val.enabled {
+            info!("Skipping disabled source {name}");
+            continue;
+        }
+        result.push(Source {
+            name,
+            url: &val.url,
+            display_url: config.display_url(&val.url),
+            source_type: val.format.clone(),
+            category: val.category.as_deref(),
+            timeout: val.timeout.map(Duration::from_secs),
+            headers: val.headers.as_ref(),
+            options: Some(&val.options),
+        });
+    }
+    for (urls, source_type) in [
+        (hosts_file_blocklist_urls, SourceType::HostsFile),
+        (domain_blocklist_urls, SourceType::DomainList),
+    ] {
+        for val in urls {
+            let display_url = config.display_url(val);
+            result.push(Source {
+                name: display_url,
+                display_url,
+                options: options(val),
+                ..Source::new(val, source_type.clone())
+            });
+        }
+    }
+
+    result
+}
@@ -0,0 +1,54 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::Path,
+};
+
+use anyhow::Context;
+
+/// Exclusive lock on a file, held while a run writes outputs, so two runs cannot write the same
+/// outputs at once.  The lock is released when this is dropped, or if the process exits.
+pub struct OutputLock {
+    _file: File,
+}
+
+impl OutputLock {
+    /// Lock the file at `path`, creating it if needed.  Returns `None` if another run holds the
+    /// lock.
+    pub fn try_acquire(path: &Path) -> anyhow::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open lock file `{}`", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(OutputLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(error)) => {
+                Err(error).with_context(|| format!("Failed to lock file `{}`", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::OutputLock;
+
+    #[test]
+    fn try_acquire_returns_none_while_another_lock_is_held() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let lock_path = temp_dir.join("blocklist-generator.toml.lock");
+        let lock = OutputLock::try_acquire(&lock_path).unwrap();
+
+        // act
+        let outcome_held = OutputLock::try_acquire(&lock_path).unwrap();
+        drop(lock);
+        let outcome_released = OutputLock::try_acquire(&lock_path).unwrap();
+
+        // assert
+        assert!(outcome_held.is_none());
+        assert!(outcome_released.is_some());
+    }
+}
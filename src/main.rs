@@ -1,62 +1,183 @@
 #![warn(clippy::all, clippy::pedantic)]
 
 mod cli;
-mod fetch;
-mod file_system;
-mod filter;
-mod parse;
+mod daemon;
+mod init;
+mod lock;
+mod pin;
+mod server;
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use ahash::RandomState;
+use anyhow::Context;
+use blocklist_generator::{
+    BuildReport, Error, Pipeline, RunReport,
+    config::{process_environment, resolve_config_table},
+    diff::{read_sorted_domain_list, report as diff_report},
+    fetch::{Client as FetchClient, FetchError},
+    file_system::{Config, get_config_from_file},
+    format::FormatRegistry,
+    metadata::config_file_hash,
+    metrics::{run_metrics, write_textfile as write_metrics_textfile},
+    profile_file_path,
+    validate::check as check_config,
+};
 use clap::Parser;
-use filter::blocklist as filter_blocklist;
-use log::warn;
+use humansize::{DECIMAL, format_size};
 use num_format::{Locale, ToFormattedString};
-use url::Host;
 
 use crate::{
-    cli::Cli,
-    fetch::Client as FetchClient,
-    file_system::{
-        Blocklists, Config, get_config_from_file, get_custom_blocked_names,
-        write_blocklist_rpz_file, write_domain_blocklist_file, write_unbound_local_zone_file,
-    },
+    cli::{Cli, Command, ConfigCommand},
+    daemon::serve,
+    init::{catalogue_listing, starter_config, write_starter_config},
+    lock::OutputLock,
+    pin::pin_sources,
 };
 
-#[derive(Debug)]
-enum SourceType {
-    DomainList,
-    HostsFile,
+fn print_output_file_metadata(output_path: &Path) {
+    if let Ok(value) = fs::metadata(output_path) {
+        let display_bytes = format_size(value.len(), DECIMAL);
+        println!("Written {display_bytes} to {}", output_path.display());
+    }
 }
 
-#[derive(Debug)]
-struct Source<'a> {
-    url: &'a str,
-    source_type: SourceType,
+fn write_change_report(output_path: Option<&Path>, content: &str) -> anyhow::Result<()> {
+    if let Some(value) = output_path {
+        return fs::write(value, content)
+            .with_context(|| format!("Failed to write change report `{}`", value.display()));
+    }
+    print!("{content}");
+
+    Ok(())
 }
 
-fn sources_from_blocklists(blocklists: &Blocklists) -> Vec<Source<'_>> {
-    let mut result: Vec<Source> = Vec::new();
-    let Blocklists {
-        hosts_file_blocklist_urls,
-        domain_blocklist_urls,
-    } = blocklists;
+/// Print what a run wrote, write or print any change reports, and write any metrics file.
+fn print_run_report(cli: &Cli, run_report: &RunReport) -> anyhow::Result<()> {
+    for build in &run_report.builds {
+        let BuildReport {
+            profile,
+            domain_count,
+            written_paths,
+            change_report,
+            ..
+        } = build;
+        for path in written_paths {
+            print_output_file_metadata(path);
+        }
+        if let Some(change_report_value) = change_report {
+            let diff_output = cli
+                .diff_output
+                .as_deref()
+                .map(|val| profile_file_path(val, profile.as_deref()));
+            write_change_report(diff_output.as_deref(), change_report_value)?;
+        }
 
-    for val in hosts_file_blocklist_urls {
-        result.push(Source {
-            url: val,
-            source_type: SourceType::HostsFile,
-        });
+        let result_count = domain_count.to_formatted_string(&Locale::en);
+        match profile {
+            Some(profile_value) => println!("{profile_value}: {result_count} results"),
+            None => println!("{result_count} results"),
+        }
+    }
+    for path in &run_report.distribution_paths {
+        print_output_file_metadata(path);
     }
-    for val in domain_blocklist_urls {
-        result.push(Source {
-            url: val,
-            source_type: SourceType::DomainList,
-        });
+    if let Some(value) = &cli.metrics_file {
+        write_metrics_textfile(value, &run_metrics(run_report, jiff::Timestamp::now()))?;
+    }
+
+    Ok(())
+}
+
+/// Peak resident set size of this process, in bytes, where the platform reports it (Linux).
+fn peak_memory_use() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kibibytes = status
+        .lines()
+        .find_map(|val| val.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kibibytes * 1024)
+}
+
+fn fetch_client(cli: &Cli) -> Result<FetchClient, FetchError> {
+    let mut result = FetchClient::new(None)?;
+    if let Some(value) = cli.max_source_size {
+        result = result.with_max_body_size(value.saturating_mul(1_048_576));
+    }
+    if let Some(value) = cli.parse_workers {
+        result = result.with_parse_workers(value)?;
+    }
+
+    Ok(result)
+}
+
+/// Pipeline running `config`, loaded from `config_path`, with the options set on the command
+/// line.
+fn pipeline(cli: &Cli, config_path: &Path, config: Config) -> Result<Pipeline, Error> {
+    let mut builder = Pipeline::from_config(config)
+        .client(fetch_client(cli)?)
+        .blocked_names_file("blocked-names.txt")
+        .config_hash(config_file_hash(config_path)?)
+        .force(cli.force);
+    if let Some(value) = cli.max_concurrent_downloads {
+        builder = builder.max_concurrent_downloads(value);
+    }
+    if cli.diff {
+        builder = builder.change_report(cli.diff_format.unwrap_or_default().into());
+    }
+
+    builder.build()
+}
+
+fn lock_file_path(cli: &Cli, config_path: &Path) -> PathBuf {
+    if let Some(value) = &cli.lock_file {
+        return value.clone();
     }
+    let mut result = config_path.as_os_str().to_owned();
+    result.push(".lock");
 
-    result
+    PathBuf::from(result)
+}
+
+async fn generate(
+    cli: &Cli,
+    config_path: &Path,
+    config: Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lock_path = lock_file_path(cli, config_path);
+    let Some(_lock) = OutputLock::try_acquire(&lock_path)? else {
+        return Err(format!(
+            "Another run holds the lock file `{}`.  Try again once it finishes.",
+            lock_path.display()
+        )
+        .into());
+    };
+    let run_report = pipeline(cli, config_path, config)?.run().await?;
+    print_run_report(cli, &run_report)?;
+    if let Some(value) = peak_memory_use() {
+        log::info!("Peak memory use: {}", format_size(value, DECIMAL));
+    }
+
+    Ok(())
+}
+
+/// Load and check the config, with any problems as diagnostics, showing the offending part of
+/// the config file where there is one.
+fn load_config(config_path: &Path) -> miette::Result<Config> {
+    let config = get_config_from_file(config_path).map_err(|error| match error {
+        Error::ConfigParse(value) => miette::Report::new(*value),
+        other => miette::Report::msg(format!("{:#}", anyhow::Error::new(other))),
+    })?;
+    check_config(config_path, &config, &FormatRegistry::default())?;
+
+    Ok(config)
 }
 
 #[tokio::main]
@@ -70,38 +191,90 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if let Some(Command::Diff { old, new, format }) = &cli.command {
+        let old_domains = read_sorted_domain_list(old)?;
+        let new_domains = read_sorted_domain_list(new)?;
+        print!(
+            "{}",
+            diff_report(
+                format.unwrap_or_default().into(),
+                (&old.display().to_string(), &old_domains),
+                (&new.display().to_string(), &new_domains),
+                None,
+            )
+        );
+
+        return Ok(());
+    }
+
     let default_config_path = PathBuf::from("blocklist-generator.toml");
     let config_path = match &cli.config {
         Some(value) => value,
         None => &default_config_path,
     };
-    let concurrent_downloads = &cli.max_concurrent_downloads.unwrap_or(3);
+    if let Some(Command::Init {
+        categories,
+        resolvers,
+        list,
+    }) = &cli.command
+    {
+        if *list {
+            print!("{}", catalogue_listing());
+        } else {
+            write_starter_config(
+                config_path,
+                &starter_config(categories, resolvers),
+                cli.force,
+            )?;
+            println!("Wrote starter config to `{}`", config_path.display());
+        }
 
-    let Config {
-        blocklists,
-        filters,
-    } = get_config_from_file(config_path)?;
-    let sources = sources_from_blocklists(&blocklists);
+        return Ok(());
+    }
 
-    let fetch_client = FetchClient::default();
-    let hasher = RandomState::new();
-    let mut set: HashSet<Host, RandomState> = HashSet::with_capacity_and_hasher(524_288, hasher);
-    fetch_client
-        .domainlists(&sources, *concurrent_downloads, &mut set)
-        .await?;
+    if let Some(Command::Config {
+        command: ConfigCommand::Show { resolved },
+    }) = &cli.command
+    {
+        let content = if *resolved {
+            toml::to_string_pretty(&resolve_config_table(
+                config_path,
+                &process_environment(),
+                true,
+            )?)?
+        } else {
+            fs::read_to_string(config_path).with_context(|| {
+                format!(
+                    "Failed to open or read config file `{}`",
+                    config_path.display()
+                )
+            })?
+        };
+        print!("{content}");
 
-    if let Some(filters_value) = filters {
-        filter_blocklist(&mut set, &filters_value);
+        return Ok(());
     }
-    get_custom_blocked_names("blocked-names.txt", &mut set);
 
-    let mut result: Vec<Host> = set.into_iter().collect();
-    result.sort();
+    let config = match load_config(config_path) {
+        Ok(value) => value,
+        Err(report) => {
+            eprintln!("{report:?}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(Command::Validate) = &cli.command {
+        println!("Config file `{}` is valid", config_path.display());
+
+        return Ok(());
+    }
+    if let Some(Command::Pin { sources }) = &cli.command {
+        pin_sources(config_path, &config, sources, &fetch_client(cli)?).await?;
 
-    write_blocklist_rpz_file(&result);
-    write_unbound_local_zone_file(&result);
-    write_domain_blocklist_file(&result);
+        return Ok(());
+    }
+    if let Some(Command::Serve { interval, listen }) = &cli.command {
+        return serve(cli, config_path, config, *interval, *listen).await;
+    }
 
-    println!("{} results", result.len().to_formatted_string(&Locale::en));
-    Ok(())
+    generate(cli, config_path, config).await
 }
@@ -0,0 +1,225 @@
+use std::{fmt::Write, fs, path::Path};
+
+use num_format::{Locale, ToFormattedString};
+use sha2::{Digest, Sha256};
+
+use crate::{config::config_file_paths, error::Error, fetch::SourceReport};
+
+/// Details of a generation run, written as a comment header at the top of output files.
+#[derive(Debug)]
+pub struct GenerationMetadata {
+    pub version: &'static str,
+
+    /// UTC timestamp in RFC 3339 format
+    pub generated_at: String,
+    pub config_hash: String,
+
+    /// Name of the `[[profiles]]` entry built, if any
+    pub profile: Option<String>,
+    pub sources: Vec<SourceReport>,
+    pub domain_count: usize,
+}
+
+impl GenerationMetadata {
+    #[must_use]
+    pub fn new(
+        config_hash: String,
+        profile: Option<String>,
+        sources: Vec<SourceReport>,
+        domain_count: usize,
+    ) -> Self {
+        GenerationMetadata {
+            version: env!("CARGO_PKG_VERSION"),
+            generated_at: jiff::Timestamp::now()
+                .strftime("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            config_hash,
+            profile,
+            sources,
+            domain_count,
+        }
+    }
+
+    /// Comment header for an output file, with each line starting `comment_prefix`.
+    /// `file_content` is the rest of the output file, and its checksum is included in the header.
+    #[must_use]
+    pub fn header(&self, comment_prefix: &str, file_content: &[u8]) -> String {
+        let GenerationMetadata {
+            version,
+            generated_at,
+            config_hash,
+            profile,
+            sources,
+            domain_count,
+        } = self;
+        let mut result = format!(
+            "{comment_prefix} Generated by blocklist-generator {version}\n\
+            {comment_prefix} Generated at: {generated_at}\n\
+            {comment_prefix} Config SHA-256: {config_hash}\n"
+        );
+        if let Some(profile_value) = profile {
+            let _ = writeln!(result, "{comment_prefix} Profile: {profile_value}");
+        }
+        for SourceReport {
+            name,
+            url,
+            entries,
+            status,
+            ..
+        } in sources
+        {
+            let entries = entries.to_formatted_string(&Locale::en);
+            let _ = if name == url {
+                writeln!(
+                    result,
+                    "{comment_prefix} Source: {url} ({entries} entries, {status})"
+                )
+            } else {
+                writeln!(
+                    result,
+                    "{comment_prefix} Source: {name} <{url}> ({entries} entries, {status})"
+                )
+            };
+        }
+        let _ = write!(
+            result,
+            "{comment_prefix} Domains: {}\n\
+            {comment_prefix} Content SHA-256: {}\n",
+            domain_count.to_formatted_string(&Locale::en),
+            sha256_hex(file_content)
+        );
+
+        result
+    }
+}
+
+#[must_use]
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// SHA-256 hash of the config file at `config_file_path`, for output headers.  When it includes
+/// other files, this is the hash of the SHA-256 hashes of every file read, one per line in the
+/// order they are read, so a change to any of them changes it.
+///
+/// # Errors
+///
+/// Returns an error if a config file cannot be read or parsed, or includes form a cycle.
+pub fn config_file_hash<P: AsRef<Path>>(config_file_path: P) -> Result<String, Error> {
+    let mut file_hashes = String::new();
+    let config_file_paths = config_file_paths(config_file_path)?;
+    for path in &config_file_paths {
+        let config_file_content = fs::read(path).map_err(|error| {
+            Error::io(
+                format!("Failed to open or read config file `{}`", path.display()),
+                error,
+            )
+        })?;
+        let _ = writeln!(file_hashes, "{}", sha256_hex(&config_file_content));
+    }
+
+    if config_file_paths.len() == 1 {
+        Ok(file_hashes.trim_end().to_string())
+    } else {
+        Ok(sha256_hex(file_hashes.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{
+        fetch::{FetchStatus, SourceReport},
+        metadata::{GenerationMetadata, config_file_hash, sha256_hex},
+    };
+
+    #[test]
+    fn sha256_hex_returns_expected_digest() {
+        // act
+        let outcome = sha256_hex(b"example.com\n");
+
+        // assert
+        assert_eq!(
+            outcome,
+            "391196688aa55d3321deffa736f8d103b4813470952b748e9c2c9deb17fa60f5"
+        );
+    }
+
+    #[test]
+    fn header_includes_run_details() {
+        // arrange
+        let metadata = GenerationMetadata {
+            version: "0.1.0",
+            generated_at: String::from("2026-01-01T00:00:00Z"),
+            config_hash: sha256_hex(b"[blocklists]\n"),
+            profile: Some(String::from("kids")),
+            sources: vec![
+                SourceReport {
+                    name: String::from("StevenBlack hosts"),
+                    url: String::from("https://example.com/hosts"),
+                    entries: 12_345,
+                    rejected: 0,
+                    bytes: 0,
+                    duration: Duration::ZERO,
+                    status: FetchStatus::Ok,
+                },
+                SourceReport {
+                    name: String::from("https://example.com/domains.txt"),
+                    url: String::from("https://example.com/domains.txt"),
+                    entries: 0,
+                    rejected: 0,
+                    bytes: 0,
+                    duration: Duration::ZERO,
+                    status: FetchStatus::Failed,
+                },
+            ],
+            domain_count: 12_345,
+        };
+
+        // act
+        let outcome = metadata.header("#", b"example.com\n");
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn config_file_hash_covers_included_files() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let config_path = temp_dir.join("blocklist-generator.toml");
+        let _ = temp_dir.child("single.toml").write_str("[blocklists]\n");
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str("include = [\"sources.d/*.toml\"]\n");
+        let _ = temp_dir
+            .child("sources.d/10-ads.toml")
+            .write_str("[guard]\nmax_change_percent = 50\n");
+        let first_hash = config_file_hash(&config_path).unwrap();
+
+        // act
+        let _ = temp_dir
+            .child("sources.d/10-ads.toml")
+            .write_str("[guard]\nmax_change_percent = 10\n");
+        let outcome = config_file_hash(&config_path).unwrap();
+        let outcome_single = config_file_hash(temp_dir.join("single.toml")).unwrap();
+
+        // assert
+        assert_ne!(outcome, first_hash);
+        assert_eq!(
+            outcome,
+            sha256_hex(
+                format!(
+                    "{}\n{}\n",
+                    sha256_hex(b"include = [\"sources.d/*.toml\"]\n"),
+                    sha256_hex(b"[guard]\nmax_change_percent = 10\n")
+                )
+                .as_bytes()
+            )
+        );
+        assert_eq!(outcome_single, sha256_hex(b"[blocklists]\n"));
+    }
+}
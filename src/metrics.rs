@@ -0,0 +1,328 @@
+//! Run metrics in the Prometheus text format, for scraping from `/metrics` in `serve` mode, or
+//! for the node exporter textfile collector after a one-shot run.
+
+use std::{collections::BTreeMap, fmt::Display, fmt::Write as _, path::Path};
+
+use crate::{
+    RunReport,
+    error::Error,
+    fetch::{FetchStatus, SourceReport},
+    file_system::write_to_file,
+};
+
+const FETCH_STATUSES: [FetchStatus; 3] =
+    [FetchStatus::Ok, FetchStatus::Cached, FetchStatus::Failed];
+
+/// `value`, escaped for use as a label value.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// One metric family: its `HELP` and `TYPE` lines, then a line for each sample.
+struct Family<'a> {
+    result: &'a mut String,
+    name: &'static str,
+}
+
+impl<'a> Family<'a> {
+    fn new(result: &'a mut String, name: &'static str, kind: &str, help: &str) -> Self {
+        let _ = writeln!(result, "# HELP {name} {help}");
+        let _ = writeln!(result, "# TYPE {name} {kind}");
+
+        Family { result, name }
+    }
+
+    fn sample(&mut self, labels: &[(&str, Option<&str>)], value: impl Display) {
+        let labels: Vec<String> = labels
+            .iter()
+            .filter_map(|(name, value)| {
+                value.map(|val| format!("{name}=\"{}\"", escape_label_value(val)))
+            })
+            .collect();
+        if labels.is_empty() {
+            let _ = writeln!(self.result, "{} {value}", self.name);
+        } else {
+            let _ = writeln!(self.result, "{}{{{}}} {value}", self.name, labels.join(","));
+        }
+    }
+}
+
+/// Report for each source in `run_report`, once each, though several profiles may use it.
+fn source_reports(run_report: &RunReport) -> Vec<&SourceReport> {
+    let mut result: Vec<&SourceReport> = Vec::new();
+    for source in run_report.builds.iter().flat_map(|val| val.sources.iter()) {
+        if !result.iter().any(|val| val.url == source.url) {
+            result.push(source);
+        }
+    }
+
+    result
+}
+
+/// Gauges for `run_report`, from a run which finished at `finished_at`: per-source fetch
+/// duration, body size, status, and entries parsed and rejected, then domains removed by the
+/// allowlist and domain counts, for each blocklist and output.
+///
+/// ```
+/// use blocklist_generator::{RunReport, metrics::run_metrics};
+///
+/// let run_report = RunReport {
+///     builds: Vec::new(),
+///     distribution_paths: Vec::new(),
+/// };
+/// let metrics = run_metrics(&run_report, jiff::Timestamp::from_second(1_767_225_600).unwrap());
+///
+/// assert!(metrics.ends_with("blocklist_generator_last_success_timestamp_seconds 1767225600\n"));
+/// ```
+#[must_use]
+pub fn run_metrics(run_report: &RunReport, finished_at: jiff::Timestamp) -> String {
+    let mut result = String::new();
+    let sources = source_reports(run_report);
+
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_source_fetch_duration_seconds",
+        "gauge",
+        "Time taken to fetch and parse the source.",
+    );
+    for source in &sources {
+        family.sample(
+            &[("source", Some(&source.name))],
+            source.duration.as_secs_f64(),
+        );
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_source_bytes",
+        "gauge",
+        "Size of the source body, as published.",
+    );
+    for source in &sources {
+        family.sample(&[("source", Some(&source.name))], source.bytes);
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_source_status",
+        "gauge",
+        "Outcome of fetching the source: 1 for the status it had, 0 for the others.",
+    );
+    for source in &sources {
+        for status in FETCH_STATUSES {
+            family.sample(
+                &[
+                    ("source", Some(&source.name)),
+                    ("status", Some(&status.to_string())),
+                ],
+                u8::from(source.status == status),
+            );
+        }
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_source_entries",
+        "gauge",
+        "Distinct domains parsed from the source.",
+    );
+    for source in &sources {
+        family.sample(&[("source", Some(&source.name))], source.entries);
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_source_rejected_lines",
+        "gauge",
+        "Lines of the source which could not be parsed.",
+    );
+    for source in &sources {
+        family.sample(&[("source", Some(&source.name))], source.rejected);
+    }
+
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_allowlist_removals",
+        "gauge",
+        "Domains removed from the blocklist by allowed names.",
+    );
+    for build in &run_report.builds {
+        family.sample(
+            &[("profile", build.profile.as_deref())],
+            build.allowlist_removals,
+        );
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_output_domains",
+        "gauge",
+        "Domains written to the output.",
+    );
+    for build in &run_report.builds {
+        for path in &build.output_paths {
+            family.sample(
+                &[
+                    ("profile", build.profile.as_deref()),
+                    ("output", Some(&path.display().to_string())),
+                ],
+                build.domain_count,
+            );
+        }
+    }
+    let mut family = Family::new(
+        &mut result,
+        "blocklist_generator_last_success_timestamp_seconds",
+        "gauge",
+        "Time the last successful run finished, in seconds since the Unix epoch.",
+    );
+    family.sample(&[], finished_at.as_second());
+
+    result
+}
+
+/// Counts kept across the runs of a long-running process, such as `serve`.
+#[derive(Debug, Default)]
+pub struct RunCounters {
+    successes: u64,
+    failures: u64,
+
+    /// Source fetches, by source name and status
+    fetches: BTreeMap<(String, String), u64>,
+}
+
+impl RunCounters {
+    /// Count a successful run, and the fetch of each of its sources.
+    pub fn record_success(&mut self, run_report: &RunReport) {
+        self.successes += 1;
+        for source in source_reports(run_report) {
+            *self
+                .fetches
+                .entry((source.name.clone(), source.status.to_string()))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /// Counters, in the Prometheus text format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut result = String::new();
+        let mut family = Family::new(
+            &mut result,
+            "blocklist_generator_runs_total",
+            "counter",
+            "Runs since the process started, by outcome.",
+        );
+        family.sample(&[("outcome", Some("success"))], self.successes);
+        family.sample(&[("outcome", Some("failure"))], self.failures);
+        let mut family = Family::new(
+            &mut result,
+            "blocklist_generator_source_fetches_total",
+            "counter",
+            "Source fetches since the process started, by status.",
+        );
+        for ((source, status), count) in &self.fetches {
+            family.sample(&[("source", Some(source)), ("status", Some(status))], count);
+        }
+
+        result
+    }
+}
+
+/// Write `metrics` to `path` for the node exporter textfile collector, replacing any previous
+/// file in one step, so the collector never reads a partly written file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn write_textfile<P: AsRef<Path>>(path: P, metrics: &str) -> Result<(), Error> {
+    write_to_file(metrics, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use url::Host;
+
+    use crate::{
+        BuildReport, RunReport,
+        domain_set::DomainSet,
+        fetch::{FetchStatus, SourceReport},
+        metrics::{RunCounters, run_metrics},
+    };
+
+    fn sample_run_report() -> RunReport {
+        let domains: DomainSet = ["ads.example.com", "example.net"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect();
+        let sources = vec![
+            SourceReport {
+                name: String::from("Example \"ads\""),
+                url: String::from("https://example.com/ads.txt"),
+                entries: 2,
+                rejected: 1,
+                bytes: 1_024,
+                duration: Duration::from_millis(1_500),
+                status: FetchStatus::Ok,
+            },
+            SourceReport {
+                name: String::from("https://example.com/hosts"),
+                url: String::from("https://example.com/hosts"),
+                entries: 0,
+                rejected: 0,
+                bytes: 0,
+                duration: Duration::from_millis(250),
+                status: FetchStatus::Failed,
+            },
+        ];
+        RunReport {
+            builds: vec![BuildReport {
+                profile: Some(String::from("kids")),
+                domain_count: domains.len(),
+                domains,
+                allowlist_removals: 3,
+                sources,
+                output_paths: vec![PathBuf::from("./blocklist.rpz")],
+                written_paths: vec![
+                    PathBuf::from("./blocklist.rpz"),
+                    PathBuf::from("./blocklist.rpz.gz"),
+                ],
+                change_report: None,
+            }],
+            distribution_paths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn run_metrics_reports_sources_builds_and_outputs() {
+        // arrange
+        let run_report = sample_run_report();
+        let finished_at = jiff::Timestamp::from_second(1_767_225_600).unwrap();
+
+        // act
+        let outcome = run_metrics(&run_report, finished_at);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn run_counters_count_runs_and_source_fetches() {
+        // arrange
+        let run_report = sample_run_report();
+        let mut counters = RunCounters::default();
+
+        // act
+        counters.record_success(&run_report);
+        counters.record_success(&run_report);
+        counters.record_failure();
+        let outcome = counters.render();
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+}
@@ -0,0 +1,398 @@
+//! Built-in output formats.
+
+use std::fs;
+
+use askama::Template;
+use url::Host;
+
+use crate::{
+    domain_set::DomainSet,
+    error::Error,
+    format::{OutputContext, OutputWriter},
+    template::{TemplateContext, render as render_template},
+};
+
+#[derive(Template)]
+#[template(escape = "none", path = "blocklist.rpz")]
+struct BlocklistRPZTemplate<'a> {
+    domains: &'a str,
+}
+
+fn domain_to_blocklist_rpz_domain(host: &Host<&str>) -> String {
+    let domain = host.to_string();
+    format!("{domain}\tCNAME\t.\n*.{domain}\tCNAME\t.\n")
+}
+
+fn domain_to_unbound_local_zone(host: &Host<&str>) -> String {
+    let domain = host.to_string();
+    format!("local-zone: \"{domain}\" always_nxdomain\n")
+}
+
+fn domain_to_adguard_home_rule(host: &Host<&str>, important: bool) -> String {
+    let domain = host.to_string();
+    if important {
+        format!("||{domain}^$important\n")
+    } else {
+        format!("||{domain}^\n")
+    }
+}
+
+/// Pi-hole regex matching the domain itself and any of its subdomains, equivalent to the `*.`
+/// wildcard entries in the RPZ output.
+fn domain_to_pihole_regex(host: &Host<&str>) -> String {
+    let escaped_domain = host.to_string().replace('.', "\\.");
+    format!("(\\.|^){escaped_domain}$\n")
+}
+
+fn domain_to_coredns_hosts_entry(host: &Host<&str>) -> String {
+    let domain = host.to_string();
+    format!("0.0.0.0 {domain}\n:: {domain}\n")
+}
+
+/// Number of domains added to the PowerDNS Recursor blocklist by each generated Lua function.
+/// LuaJIT caps the constants in a single function at 65,536, so a single table literal with every
+/// domain fails to load for larger lists.
+const POWERDNS_LUA_CHUNK_SIZE: usize = 10_000;
+
+#[derive(Template)]
+#[template(escape = "none", path = "powerdns-blocklist.lua")]
+struct PowerdnsLuaTemplate<'a> {
+    domain_chunks: &'a str,
+}
+
+fn domains_to_powerdns_lua_chunk(hosts: &[Host<&str>]) -> String {
+    let domains = hosts.iter().fold(String::new(), |mut acc, val| {
+        acc.push_str("      \"");
+        acc.push_str(&val.to_string());
+        acc.push_str("\",\n");
+        acc
+    });
+    format!(
+        "do\n  local function add()\n    blocklist:add({{\n{domains}    }})\n  end\n  add()\nend\n"
+    )
+}
+
+/// Response Policy Zone file for BIND, Knot Resolver and others.
+pub struct RpzWriter;
+
+impl OutputWriter for RpzWriter {
+    fn comment_prefix(&self) -> &'static str {
+        ";"
+    }
+
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let domains = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&domain_to_blocklist_rpz_domain(&val));
+            acc
+        });
+        let template = BlocklistRPZTemplate { domains: &domains };
+        let file_content = template.render().map_err(Error::Render)?;
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// Unbound `local-zone` config file.
+pub struct UnboundWriter;
+
+impl OutputWriter for UnboundWriter {
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let file_content = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&domain_to_unbound_local_zone(&val));
+            acc
+        });
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// Plain list of domains, one per line.  Pi-hole gravity and Blocky both accept this list.
+/// Pi-hole entries do not block subdomains (add a `pihole-regex` output for those), while Blocky
+/// entries do.
+pub struct DomainListWriter;
+
+impl OutputWriter for DomainListWriter {
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let file_content = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&val.to_string());
+            acc.push('\n');
+            acc
+        });
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// AdGuard Home rules, with the `$important` modifier when the output sets `important`.
+pub struct AdguardHomeWriter;
+
+impl OutputWriter for AdguardHomeWriter {
+    fn comment_prefix(&self) -> &'static str {
+        "!"
+    }
+
+    fn write(&self, domains: &DomainSet, context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let important = context.output.important;
+        let file_content = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&domain_to_adguard_home_rule(&val, important));
+            acc
+        });
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// Pi-hole `regex.list`, blocking subdomains of each listed domain.
+pub struct PiholeRegexWriter;
+
+impl OutputWriter for PiholeRegexWriter {
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let file_content = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&domain_to_pihole_regex(&val));
+            acc
+        });
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// CoreDNS `hosts` plugin file.  Entries only match the exact name, so subdomains are not
+/// blocked.
+pub struct CorednsHostsWriter;
+
+impl OutputWriter for CorednsHostsWriter {
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let file_content = domains.iter().fold(String::new(), |mut acc, val| {
+            acc.push_str(&domain_to_coredns_hosts_entry(&val));
+            acc
+        });
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// Lua script for the PowerDNS Recursor `lua-dns-script` setting.  Its `preresolve` hook answers
+/// `NXDOMAIN` for listed domains and their subdomains.
+pub struct PowerdnsLuaWriter;
+
+impl OutputWriter for PowerdnsLuaWriter {
+    fn comment_prefix(&self) -> &'static str {
+        "--"
+    }
+
+    fn write(&self, domains: &DomainSet, _context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let mut domain_chunks = String::new();
+        let mut chunk: Vec<Host<&str>> = Vec::with_capacity(POWERDNS_LUA_CHUNK_SIZE);
+        for host in domains {
+            chunk.push(host);
+            if chunk.len() == POWERDNS_LUA_CHUNK_SIZE {
+                domain_chunks.push_str(&domains_to_powerdns_lua_chunk(&chunk));
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            domain_chunks.push_str(&domains_to_powerdns_lua_chunk(&chunk));
+        }
+        let template = PowerdnsLuaTemplate {
+            domain_chunks: &domain_chunks,
+        };
+        let file_content = template.render().map_err(Error::Render)?;
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+/// Renders the user-supplied template set in the output's `template` field.  The template gets
+/// the domains, their count, a UTC generation timestamp, the sources and config values (see
+/// `TemplateContext`).
+pub struct TemplateWriter;
+
+impl OutputWriter for TemplateWriter {
+    fn write(&self, domains: &DomainSet, context: &OutputContext<'_>) -> Result<Vec<u8>, Error> {
+        let OutputContext {
+            output,
+            config,
+            metadata,
+        } = context;
+        let Some(template_path) = &output.template else {
+            return Err(Error::Config(format!(
+                "Template output `{}` has no `template` path set in the config file.",
+                output.path().display()
+            )));
+        };
+        let template_display_path = template_path.display().to_string();
+        let template_source = fs::read_to_string(template_path).map_err(|error| {
+            Error::io(
+                format!("Failed to open or read template file `{template_display_path}`"),
+                error,
+            )
+        })?;
+        let template_context = TemplateContext::new(domains, config, metadata.generated_at.clone());
+        let file_content =
+            render_template(&template_display_path, &template_source, &template_context).map_err(
+                |error| Error::Template {
+                    message: format!("Failed to render template file `{template_display_path}`"),
+                    source: error,
+                },
+            )?;
+
+        Ok(file_content.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Host;
+
+    use crate::{
+        domain_set::DomainSet,
+        file_system::{Config, Output, OutputFormat},
+        format::{FormatRegistry, OutputContext},
+        metadata::GenerationMetadata,
+    };
+
+    fn sample_blocklist_domains() -> DomainSet {
+        ["ads.example.com", "example.net", "tracker.example.org"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect()
+    }
+
+    /// Content the registered writer for `output` produces for the sample domains.
+    fn written_content(output: &Output) -> String {
+        let config = Config::default();
+        let metadata = GenerationMetadata::new(String::new(), None, Vec::new(), 3);
+        let context = OutputContext {
+            output,
+            config: &config,
+            metadata: &metadata,
+        };
+        let formats = FormatRegistry::default();
+        let writer = formats.writer(output.format.config_name()).unwrap();
+
+        String::from_utf8(writer.write(&sample_blocklist_domains(), &context).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rpz_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::Rpz);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn unbound_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::Unbound);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn domain_list_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::DomainList);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn adguard_home_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::AdguardHome);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn adguard_home_writer_adds_important_modifier_when_requested() {
+        // arrange
+        let output = Output {
+            important: true,
+            ..Output::new(OutputFormat::AdguardHome)
+        };
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn pihole_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::Pihole);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn pihole_regex_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::PiholeRegex);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn blocky_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::Blocky);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn coredns_hosts_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::CorednsHosts);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn powerdns_lua_writer_writes_expected_content() {
+        // arrange
+        let output = Output::new(OutputFormat::PowerdnsLua);
+
+        // act
+        let outcome = written_content(&output);
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+}
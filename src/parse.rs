@@ -1,4 +1,3 @@
-use log::trace;
 use nom::{
     IResult, Parser,
     branch::alt,
@@ -10,6 +9,11 @@ use nom::{
 };
 use url::Host;
 
+use crate::{
+    domain_set::DomainSet,
+    format::{ParseDiagnostic, SourceParser},
+};
+
 fn is_digit(c: char) -> bool {
     c.is_ascii_digit()
 }
@@ -66,34 +70,136 @@ fn parse_hostfile_line(input: &str) -> Option<&str> {
     Some(hostname)
 }
 
-pub fn domainlist(file_body: &str, set: &mut std::collections::HashSet<Host, ahash::RandomState>) {
-    for line in file_body.lines() {
-        if let Some(value) = parse_domainlist_line(line) {
-            if let Ok(host_value) = Host::parse(value) {
-                set.insert(host_value);
-            } else {
-                trace!("Unable to parse hostname in line `{value}`");
+/// Check whether `file_body` looks like an HTML page, such as an error or login page served in
+/// place of a blocklist.
+#[must_use]
+pub fn looks_like_html(file_body: &str) -> bool {
+    let lowercase_start = file_body
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(1024)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if ["<!doctype html", "<html", "<head", "<body"]
+        .iter()
+        .any(|val| lowercase_start.starts_with(val))
+    {
+        return true;
+    }
+
+    // an XML declaration or comment might come before the `<html>` tag
+    (lowercase_start.starts_with("<?xml") || lowercase_start.starts_with("<!--"))
+        && lowercase_start.contains("<html")
+}
+
+/// Splits a body arriving in chunks into runs of whole lines, holding back the start of any line
+/// which continues into the next chunk.
+#[derive(Default)]
+pub(crate) struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Pass each run of whole lines completed by `chunk`, newlines included, to `output`.
+    pub(crate) fn push(&mut self, mut chunk: &[u8], output: &mut dyn FnMut(&[u8])) {
+        if !self.partial.is_empty() {
+            let Some(end) = chunk.iter().position(|val| *val == b'\n') else {
+                self.partial.extend_from_slice(chunk);
+                return;
+            };
+            self.partial.extend_from_slice(&chunk[..=end]);
+            output(&self.partial);
+            self.partial.clear();
+            chunk = &chunk[end + 1..];
+        }
+        match chunk.iter().rposition(|val| *val == b'\n') {
+            Some(end) => {
+                output(&chunk[..=end]);
+                self.partial.extend_from_slice(&chunk[end + 1..]);
             }
-        } else if !line.is_empty() && line.trim_start()[0..1] != *"#" {
-            trace!("Unable to parse `{line}`");
+            None => self.partial.extend_from_slice(chunk),
+        }
+    }
+
+    /// Pass any last line, without a trailing newline, to `output`.
+    pub(crate) fn finish(&mut self, output: &mut dyn FnMut(&[u8])) {
+        if !self.partial.is_empty() {
+            output(&self.partial);
+            self.partial.clear();
         }
     }
 }
 
-pub fn hostfile(file_body: &str, set: &mut std::collections::HashSet<Host, ahash::RandomState>) {
-    for line in file_body.lines() {
-        log::trace!("Parsing hostfile line: `{line}`");
-        if let Some(value) = parse_hostfile_line(line) {
-            if let Ok(host_value) = Host::parse(value) {
+/// Add the host found by `parse_line` in each line of `file_body` to `set`, skipping blank and
+/// comment lines.  Returns a diagnostic for each other line without a valid host.
+fn parse_lines(
+    file_body: &str,
+    set: &mut DomainSet,
+    parse_line: fn(&str) -> Option<&str>,
+) -> Vec<ParseDiagnostic> {
+    let mut result = Vec::new();
+    for (index, line) in file_body.lines().enumerate() {
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+        let message = match parse_line(line).map(|val| (val, Host::parse(val))) {
+            Some((_, Ok(host_value))) => {
                 set.insert(host_value);
-            } else {
-                trace!("Unable to parse hostname in line `{value}`");
+                continue;
             }
-        } else if line.trim_start().starts_with('#') {
-            log::trace!("Ignoring hostfile comment line: `{line}`");
-        } else if !line.trim().is_empty() {
-            log::warn!("Unable to parse hostfile line: `{line}`");
-        }
+            Some((value, Err(_))) => format!("unable to parse hostname `{value}`"),
+            None => format!("unable to parse `{line}`"),
+        };
+        result.push(ParseDiagnostic {
+            line: index + 1,
+            message,
+        });
+    }
+
+    result
+}
+
+/// Add each domain in `file_body`, a list with one domain per line, to `set`.  Comment lines,
+/// starting `#`, are skipped.  Returns a diagnostic for each other line which is not a valid
+/// domain.
+///
+/// ```
+/// use blocklist_generator::{domain_set::DomainSet, parse::domainlist};
+/// use url::Host;
+///
+/// let mut set = DomainSet::new();
+/// domainlist("# ads\nads.example.com\ntracker.example.net\n", &mut set);
+///
+/// assert_eq!(set.len(), 2);
+/// assert!(set.contains(&Host::parse("ads.example.com").unwrap()));
+/// ```
+pub fn domainlist(file_body: &str, set: &mut DomainSet) -> Vec<ParseDiagnostic> {
+    parse_lines(file_body, set, parse_domainlist_line)
+}
+
+/// Add each host name in `file_body`, in hosts file format, to `set`.  Lines which cannot be
+/// parsed are skipped, as for [`domainlist`].
+pub fn hostfile(file_body: &str, set: &mut DomainSet) -> Vec<ParseDiagnostic> {
+    parse_lines(file_body, set, parse_hostfile_line)
+}
+
+/// Parser for the `domain-list` source format, using [`domainlist`].
+pub struct DomainListParser;
+
+impl SourceParser for DomainListParser {
+    fn parse(&self, body: &[u8], hosts: &mut DomainSet) -> Vec<ParseDiagnostic> {
+        domainlist(&String::from_utf8_lossy(body), hosts)
+    }
+}
+
+/// Parser for the `hosts-file` source format, using [`hostfile`].
+pub struct HostsFileParser;
+
+impl SourceParser for HostsFileParser {
+    fn parse(&self, body: &[u8], hosts: &mut DomainSet) -> Vec<ParseDiagnostic> {
+        hostfile(&String::from_utf8_lossy(body), hosts)
     }
 }
 
@@ -104,7 +210,11 @@ mod tests {
     use url::Host;
 
     use super::{parse_hostfile_line, parse_hostname, parse_ipv4_address, parse_ipv4_octet};
-    use crate::parse::{domainlist, hostfile, parse_domainlist_line};
+    use crate::{
+        domain_set::DomainSet,
+        format::ParseDiagnostic,
+        parse::{LineSplitter, domainlist, hostfile, looks_like_html, parse_domainlist_line},
+    };
 
     #[test]
     fn parse_ip4_octet_parses_valid_ipv4_octet() {
@@ -380,9 +490,7 @@ another-example.com # some annotation
 # more annotation
 subdomain-which-is-too-long-012345679012345678901234567890123456.com
 final-example.com";
-        let hasher = ahash::RandomState::new();
-        let mut hash_set: std::collections::HashSet<Host, ahash::RandomState> =
-            std::collections::HashSet::with_hasher(hasher);
+        let mut hash_set = DomainSet::new();
 
         // act
         domainlist(input, &mut hash_set);
@@ -394,14 +502,65 @@ final-example.com";
         assert!(hash_set.contains(&Host::parse("final-example.com").unwrap()));
     }
 
+    #[test]
+    fn domainlist_reports_lines_it_cannot_parse() {
+        // arrange
+        let input = "example.com\n   \n# comment\n-not a domain\nfinal-example.com\n";
+        let mut hash_set = DomainSet::new();
+
+        // act
+        let outcome = domainlist(input, &mut hash_set);
+
+        // assert
+        assert_eq!(hash_set.len(), 2);
+        assert_eq!(
+            outcome,
+            vec![ParseDiagnostic {
+                line: 4,
+                message: String::from("unable to parse `-not a domain`"),
+            }]
+        );
+    }
+
+    #[test]
+    fn line_splitter_joins_lines_split_across_chunks() {
+        // arrange
+        let chunks: [&[u8]; 5] = [
+            b"exam",
+            b"ple.com\nanother.ex",
+            b"ample",
+            b".com\nthird.example.com\nfourth.example.com\n",
+            b"last.example.com",
+        ];
+        let mut splitter = LineSplitter::default();
+        let mut outcome: Vec<String> = Vec::new();
+
+        // act
+        for chunk in chunks {
+            splitter.push(chunk, &mut |val| {
+                outcome.push(String::from_utf8_lossy(val).into_owned());
+            });
+        }
+        splitter.finish(&mut |val| outcome.push(String::from_utf8_lossy(val).into_owned()));
+
+        // assert
+        assert_eq!(
+            outcome,
+            vec![
+                "example.com\n",
+                "another.example.com\n",
+                "third.example.com\nfourth.example.com\n",
+                "last.example.com",
+            ]
+        );
+    }
+
     #[test]
     fn hostfile_successfully_parses_valid_input() {
         // arrange
         let _ = env_logger::builder().is_test(true).try_init();
         let input = "127.0.0.1\texample.com\n0.0.0.0 another-example.com # some annotation\n\n# more annotation\n0.0.0.0\t\tsubdomain-which-is-too-long-012345679012345678901234567890123456.com\n0.0.0.0\tfinal-example.com";
-        let hasher = ahash::RandomState::new();
-        let mut hash_set: std::collections::HashSet<Host, ahash::RandomState> =
-            std::collections::HashSet::with_hasher(hasher);
+        let mut hash_set = DomainSet::new();
 
         // act
         hostfile(input, &mut hash_set);
@@ -412,4 +571,23 @@ final-example.com";
         assert!(hash_set.contains(&Host::parse("another-example.com").unwrap()));
         assert!(hash_set.contains(&Host::parse("final-example.com").unwrap()));
     }
+
+    #[test]
+    fn looks_like_html_detects_html_pages() {
+        // arrange
+        let html_page =
+            "\n<!DOCTYPE html>\n<html><head><title>502 Bad Gateway</title></head></html>";
+        let xhtml_page = "<?xml version=\"1.0\"?>\n<html xmlns=\"http://www.w3.org/1999/xhtml\">";
+        let domain_list = "# <html> in a comment\nexample.com\n";
+
+        // act
+        let outcome_doctype = looks_like_html(html_page);
+        let outcome_xml_declaration = looks_like_html(xhtml_page);
+        let outcome_domain_list = looks_like_html(domain_list);
+
+        // assert
+        assert!(outcome_doctype);
+        assert!(outcome_xml_declaration);
+        assert!(!outcome_domain_list);
+    }
 }
@@ -0,0 +1,251 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, bail};
+use blocklist_generator::{
+    Source, config::config_file_paths, fetch::Client as FetchClient, file_system::Config,
+    sources_from_config,
+};
+use toml_edit::{DocumentMut, Item, Table, value};
+
+/// Add a `sha256` pin to the `[[sources]]` entry with `url`.  Returns `false` when there is no
+/// such entry.
+fn set_sources_entry_hash(document: &mut DocumentMut, url: &str, hash: &str) -> bool {
+    let Some(sources) = document
+        .get_mut("sources")
+        .and_then(Item::as_array_of_tables_mut)
+    else {
+        return false;
+    };
+    let Some(source) = sources
+        .iter_mut()
+        .find(|val| val.get("url").and_then(Item::as_str) == Some(url))
+    else {
+        return false;
+    };
+    source.insert("sha256", value(hash));
+
+    true
+}
+
+/// Whether `document` lists `url` in `[blocklists]`.
+fn lists_blocklist_url(document: &DocumentMut, url: &str) -> bool {
+    let Some(blocklists) = document.get("blocklists").and_then(Item::as_table_like) else {
+        return false;
+    };
+
+    ["hosts_file_blocklist_urls", "domain_blocklist_urls"]
+        .iter()
+        .filter_map(|key| blocklists.get(key).and_then(Item::as_array))
+        .flatten()
+        .any(|val| val.as_str() == Some(url))
+}
+
+/// Add a `sha256` pin for `url` under `[source_options."<url>"]`.
+fn set_source_options_hash(
+    document: &mut DocumentMut,
+    url: &str,
+    hash: &str,
+) -> anyhow::Result<()> {
+    let source_options = document
+        .entry("source_options")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_like_mut()
+        .context("`source_options` in the config file is not a table")?;
+    let Some(options) = source_options
+        .entry(url)
+        .or_insert(Item::Table(Table::new()))
+        .as_table_like_mut()
+    else {
+        bail!("`source_options` entry for `{url}` in the config file is not a table");
+    };
+    options.insert("sha256", value(hash));
+
+    Ok(())
+}
+
+/// Add a `sha256` pin to `documents`, the config file followed by the files it includes, for
+/// each `(url, hash)` pair, with `url` as written in the config.  Each pin goes in the file
+/// which declares the source: in its `[[sources]]` entry, or, for sources listed in
+/// `[blocklists]`, under `[source_options."<url>"]`.  Pins for sources no file declares, such as
+/// those added by environment overrides, go in the config file.  Returns whether each document
+/// changed.
+pub fn set_source_hashes(
+    documents: &mut [DocumentMut],
+    hashes: &[(&str, String)],
+) -> anyhow::Result<Vec<bool>> {
+    let mut result = vec![false; documents.len()];
+    for (url, hash) in hashes {
+        if let Some(index) = documents
+            .iter_mut()
+            .position(|val| set_sources_entry_hash(val, url, hash))
+        {
+            result[index] = true;
+            continue;
+        }
+        let index = documents
+            .iter()
+            .position(|val| lists_blocklist_url(val, url))
+            .unwrap_or_default();
+        let Some(document) = documents.get_mut(index) else {
+            bail!("No config file to pin `{url}` in");
+        };
+        set_source_options_hash(document, url, hash)?;
+        result[index] = true;
+    }
+
+    Ok(result)
+}
+
+/// Fetch the current body of each source named in `selection` (by name or URL), or every
+/// enabled source when `selection` is empty, and write its SHA-256 hash into the config file at
+/// `config_path`, or the included file which declares the source.
+pub async fn pin_sources<P: AsRef<Path>>(
+    config_path: P,
+    config: &Config,
+    selection: &[String],
+    fetch_client: &FetchClient,
+) -> anyhow::Result<()> {
+    let sources = sources_from_config(config);
+    let is_selected = |source: &Source, val: &str| {
+        source.name == val || source.url == val || source.display_url == val
+    };
+    for val in selection {
+        if !sources.iter().any(|source| is_selected(source, val)) {
+            bail!("`{val}` is not an enabled source in the config file");
+        }
+    }
+    let selected_sources = sources.iter().filter(|source| {
+        selection.is_empty() || selection.iter().any(|val| is_selected(source, val))
+    });
+
+    let mut hashes: Vec<(&str, String)> = Vec::new();
+    for source in selected_sources {
+        let hash = fetch_client
+            .body_sha256(source)
+            .await
+            .with_context(|| format!("Unable to pin `{}`", source.name))?;
+        println!("Pinned {}: {hash}", source.name);
+        // match the URL as written, so `${NAME}` references are kept and secrets stay out of
+        // the file
+        hashes.push((source.display_url, hash));
+    }
+
+    let config_paths = config_file_paths(config_path.as_ref())?;
+    let mut documents: Vec<DocumentMut> = Vec::with_capacity(config_paths.len());
+    for path in &config_paths {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to open or read config file `{}`", path.display()))?;
+        documents.push(content.parse().with_context(|| {
+            format!(
+                "Failed to parse config file `{}`.  Check it is valid.",
+                path.display()
+            )
+        })?);
+    }
+    let changed = set_source_hashes(&mut documents, &hashes)?;
+    for ((path, document), changed) in config_paths.iter().zip(&documents).zip(changed) {
+        if changed {
+            fs::write(path, document.to_string())
+                .with_context(|| format!("Failed to write config file `{}`", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use toml_edit::DocumentMut;
+
+    use crate::pin::set_source_hashes;
+
+    #[test]
+    fn set_source_hashes_adds_and_updates_pins() {
+        // arrange
+        let config_content = r#"[blocklists]
+hosts_file_blocklist_urls = []
+domain_blocklist_urls = [
+  # vendored snapshot
+  "https://example.com/domains.txt",
+  "https://example.com/other.txt",
+]
+
+[source_options."https://example.com/other.txt"]
+sha256 = "outdated"
+minisign_public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+
+[[sources]]
+name = "Vendored snapshot"
+url = "https://example.com/snapshot.txt"
+format = "domain-list"
+"#;
+        let hashes = [
+            (
+                "https://example.com/domains.txt",
+                String::from("391196688aa55d3321deffa736f8d103b4813470952b748e9c2c9deb17fa60f5"),
+            ),
+            (
+                "https://example.com/snapshot.txt",
+                String::from("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"),
+            ),
+            (
+                "https://example.com/other.txt",
+                String::from("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            ),
+        ];
+
+        let mut documents = [config_content.parse::<DocumentMut>().unwrap()];
+
+        // act
+        let outcome = set_source_hashes(&mut documents, &hashes).unwrap();
+
+        // assert
+        assert_eq!(outcome, [true]);
+        insta::assert_snapshot!(documents[0].to_string());
+    }
+
+    #[test]
+    fn set_source_hashes_pins_sources_in_the_file_declaring_them() {
+        // arrange
+        let config_content = r#"include = ["sources.d/*.toml"]
+
+[blocklists]
+domain_blocklist_urls = []
+hosts_file_blocklist_urls = []
+"#;
+        let included_content = r#"[blocklists]
+domain_blocklist_urls = ["https://example.com/domains.txt?key=${LIST_KEY}"]
+
+[[sources]]
+name = "Private list"
+url = "https://lists.example.com/hosts?token=${LIST_TOKEN}"
+format = "hosts-file"
+"#;
+        let mut documents = [
+            config_content.parse::<DocumentMut>().unwrap(),
+            included_content.parse::<DocumentMut>().unwrap(),
+        ];
+        let hashes = [
+            (
+                "https://lists.example.com/hosts?token=${LIST_TOKEN}",
+                String::from("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"),
+            ),
+            (
+                "https://example.com/domains.txt?key=${LIST_KEY}",
+                String::from("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            ),
+        ];
+
+        // act
+        let outcome = set_source_hashes(&mut documents, &hashes).unwrap();
+
+        // assert
+        assert_eq!(outcome, [false, true]);
+        assert_eq!(documents[0].to_string(), config_content);
+        insta::assert_snapshot!(documents[1].to_string());
+    }
+}
@@ -0,0 +1,672 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::warn;
+
+use crate::{
+    Source, SourceType,
+    cache::SourceCache,
+    diff::{DiffFormat, SourceAttribution, report as diff_report},
+    domain_set::DomainSet,
+    error::Error,
+    fetch::{Client, SourceReport, attribute},
+    file_system::{
+        Config, Filters, Output, Profile, SourceConfig, StagedOutputs, default_outputs,
+        get_custom_blocked_names, plain_list_output_path, read_domain_list_file,
+        stage_output_files, write_distribution_files,
+    },
+    filter::{add_blocked_names, blocklist as filter_blocklist},
+    format::FormatRegistry,
+    guard::check as check_guard,
+    metadata::{GenerationMetadata, sha256_hex},
+    sources_from_config,
+};
+
+/// Concurrent source downloads, unless set with
+/// [`PipelineBuilder::max_concurrent_downloads`].
+const DEFAULT_CONCURRENT_DOWNLOADS: u32 = 3;
+
+/// Blocklist built by a run, for the whole config or for one of its `[[profiles]]`.
+#[derive(Debug)]
+pub struct BuildReport {
+    /// Name of the profile built, if any
+    pub profile: Option<String>,
+    pub domain_count: usize,
+
+    /// Domains in the blocklist
+    pub domains: DomainSet,
+
+    /// Domains removed from the blocklist by `allowed_names`
+    pub allowlist_removals: usize,
+
+    /// Outcome of fetching each source the blocklist was built from
+    pub sources: Vec<SourceReport>,
+
+    /// Paths of the outputs, without compressed copies
+    pub output_paths: Vec<PathBuf>,
+
+    /// Output files written, including compressed copies
+    pub written_paths: Vec<PathBuf>,
+
+    /// Changes since the previous output, when a change report was requested and there is a
+    /// previous output to compare with
+    pub change_report: Option<String>,
+}
+
+/// Everything written by a [`Pipeline::run`].
+#[derive(Debug)]
+pub struct RunReport {
+    pub builds: Vec<BuildReport>,
+
+    /// Checksums manifest and signature, when `[distribution]` is set
+    pub distribution_paths: Vec<PathBuf>,
+}
+
+/// One blocklist to build in a run.
+struct Build<'a> {
+    profile: Option<&'a str>,
+    filters: Vec<&'a Filters>,
+    outputs: &'a [Output],
+    set: DomainSet,
+    attribution: Option<SourceAttribution>,
+    source_reports: Vec<SourceReport>,
+}
+
+/// Blocklist which passed its checks, with its outputs staged, ready to replace the previous ones.
+struct StagedBuild<'a> {
+    report: BuildReport,
+    outputs: StagedOutputs<'a>,
+}
+
+/// Replace the outputs of every build in `staged_builds`, then run their hook commands.
+async fn commit_builds(staged_builds: Vec<StagedBuild<'_>>) -> Result<Vec<BuildReport>, Error> {
+    let mut result: Vec<BuildReport> = Vec::with_capacity(staged_builds.len());
+    let mut staged_outputs: Vec<StagedOutputs> = Vec::with_capacity(staged_builds.len());
+    for StagedBuild {
+        mut report,
+        mut outputs,
+    } in staged_builds
+    {
+        report.written_paths = outputs.commit()?;
+        result.push(report);
+        staged_outputs.push(outputs);
+    }
+    for outputs in staged_outputs {
+        outputs.run_hook_commands().await;
+    }
+
+    Ok(result)
+}
+
+/// `path` with `-<profile>` added to the file stem, so each profile gets its own file.
+#[must_use]
+pub fn profile_file_path(path: &Path, profile: Option<&str>) -> PathBuf {
+    let Some(profile_value) = profile else {
+        return path.to_path_buf();
+    };
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!("-{profile_value}"));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    path.with_file_name(file_name)
+}
+
+/// Whether `profile` includes `source`.
+fn profile_selects(profile: &Profile, source: &Source) -> bool {
+    let Profile {
+        sources,
+        categories,
+        ..
+    } = profile;
+    if sources.is_none() && categories.is_none() {
+        return true;
+    }
+    let selected_by_name = sources
+        .iter()
+        .flatten()
+        .any(|val| val == source.name || val == source.url);
+    let selected_by_category = source
+        .category
+        .is_some_and(|category| categories.iter().flatten().any(|val| val == category));
+
+    selected_by_name || selected_by_category
+}
+
+/// Change report comparing the new `domains` with the `previous_domains` read from
+/// `previous_output`, with added domains grouped by the source which listed them.
+fn change_report(
+    format: DiffFormat,
+    previous_output: Option<&Path>,
+    previous_domains: Option<DomainSet>,
+    (attribution, sources): (&SourceAttribution, &[Source]),
+    domains: &DomainSet,
+) -> Option<String> {
+    let (Some(previous_output_path), Some(mut previous)) = (previous_output, previous_domains)
+    else {
+        warn!("No previous domain list output found, so there is no change report");
+        return None;
+    };
+    previous.sort();
+    let source_names: Vec<&str> = sources.iter().map(|val| val.name).collect();
+    let name = previous_output_path.display().to_string();
+
+    Some(diff_report(
+        format,
+        (&format!("{name} (previous)"), &previous),
+        (&name, domains),
+        Some((attribution, &source_names)),
+    ))
+}
+
+/// Fetches sources, parses them, filters the result and writes it to each output, for the whole
+/// config or for each of its `[[profiles]]`.
+pub struct Pipeline {
+    config: Config,
+    config_hash: String,
+    client: Client,
+    formats: Arc<FormatRegistry>,
+    concurrent_downloads: u32,
+    blocked_names_path: Option<PathBuf>,
+    force: bool,
+    change_report_format: Option<DiffFormat>,
+}
+
+/// Builder for a [`Pipeline`].  Start with [`Pipeline::builder`] or [`Pipeline::from_config`].
+#[must_use]
+pub struct PipelineBuilder {
+    config: Config,
+    config_hash: Option<String>,
+    client: Option<Client>,
+    formats: FormatRegistry,
+    concurrent_downloads: u32,
+    blocked_names_path: Option<PathBuf>,
+    force: bool,
+    change_report_format: Option<DiffFormat>,
+}
+
+impl PipelineBuilder {
+    /// Add a source at `url`.
+    pub fn source(self, url: impl Into<String>, format: SourceType) -> Self {
+        self.source_config(SourceConfig::new(url, format))
+    }
+
+    /// Add a source with a name, category or options.
+    pub fn source_config(mut self, source: SourceConfig) -> Self {
+        self.config
+            .sources
+            .get_or_insert_with(Vec::new)
+            .push(source);
+        self
+    }
+
+    /// Remove `filters.allowed_names` from, and add `filters.blocked_names` to, the result.
+    pub fn filters(mut self, filters: Filters) -> Self {
+        self.config.filters = Some(filters);
+        self
+    }
+
+    /// Write the result to `output`.  Without any outputs, the RPZ, Unbound and domain list files
+    /// are written to the working directory.
+    pub fn output(mut self, output: Output) -> Self {
+        self.config
+            .outputs
+            .get_or_insert_with(Vec::new)
+            .push(output);
+        self
+    }
+
+    /// Fetch sources with `client`, for example one with a larger size limit.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Parse sources and write outputs with the formats in `formats` (default: the built-in
+    /// formats).  Start from [`FormatRegistry::default`] to keep the built-in formats.
+    pub fn formats(mut self, formats: FormatRegistry) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    /// Most sources downloaded at once (default: 3).
+    pub fn max_concurrent_downloads(mut self, concurrent_downloads: u32) -> Self {
+        self.concurrent_downloads = concurrent_downloads;
+        self
+    }
+
+    /// Also block the names in the domain list file at `path`, if it exists.
+    pub fn blocked_names_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.blocked_names_path = Some(path.into());
+        self
+    }
+
+    /// Config hash for output headers (default: SHA-256 of the config, as JSON).
+    pub fn config_hash(mut self, config_hash: impl Into<String>) -> Self {
+        self.config_hash = Some(config_hash.into());
+        self
+    }
+
+    /// Write outputs even when they fail the `[guard]` checks.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Report domains added and removed since the previous output, in `format`.
+    pub fn change_report(mut self, format: DiffFormat) -> Self {
+        self.change_report_format = Some(format);
+        self
+    }
+
+    /// Pipeline with these settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no client was set, and the default one cannot start.
+    pub fn build(self) -> Result<Pipeline, Error> {
+        let PipelineBuilder {
+            mut config,
+            config_hash,
+            client,
+            formats,
+            concurrent_downloads,
+            blocked_names_path,
+            force,
+            change_report_format,
+        } = self;
+        let formats = Arc::new(formats);
+        let config_hash = config_hash
+            .unwrap_or_else(|| sha256_hex(&serde_json::to_vec(&config).unwrap_or_default()));
+        // profiles sharing a format would otherwise all write to its default path
+        for profile in config.profiles.iter_mut().flatten() {
+            for output in &mut profile.outputs {
+                if output.path.is_none() {
+                    output.path = Some(profile_file_path(&output.path(), Some(&profile.name)));
+                }
+            }
+        }
+
+        let client = match client {
+            Some(value) => value,
+            None => Client::new(None)?,
+        };
+
+        Ok(Pipeline {
+            config,
+            config_hash,
+            client: client.with_formats(Arc::clone(&formats)),
+            formats,
+            concurrent_downloads,
+            blocked_names_path,
+            force,
+            change_report_format,
+        })
+    }
+}
+
+impl Pipeline {
+    /// Builder for a pipeline with no sources or outputs yet.
+    pub fn builder() -> PipelineBuilder {
+        Pipeline::from_config(Config::default())
+    }
+
+    /// Builder for a pipeline running `config`, as read from a config file.
+    pub fn from_config(config: Config) -> PipelineBuilder {
+        PipelineBuilder {
+            config,
+            config_hash: None,
+            client: None,
+            formats: FormatRegistry::default(),
+            concurrent_downloads: DEFAULT_CONCURRENT_DOWNLOADS,
+            blocked_names_path: None,
+            force: false,
+            change_report_format: None,
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Filter and check the blocklist for `build`, and stage its outputs, without replacing any
+    /// yet.
+    async fn stage_build<'a>(
+        &self,
+        sources: &[Source<'_>],
+        build: Build<'a>,
+    ) -> Result<StagedBuild<'a>, Error> {
+        let Build {
+            profile,
+            filters,
+            outputs,
+            mut set,
+            attribution,
+            source_reports,
+        } = build;
+        let unfiltered_count = set.len();
+        for filters_value in &filters {
+            filter_blocklist(&mut set, filters_value);
+        }
+        let allowlist_removals = unfiltered_count - set.len();
+        for filters_value in &filters {
+            add_blocked_names(&mut set, filters_value);
+        }
+        if let Some(blocked_names_path) = &self.blocked_names_path {
+            get_custom_blocked_names(blocked_names_path, &mut set);
+        }
+
+        set.sort();
+
+        let guard = self.config.guard.as_ref();
+        let previous_output = guard
+            .and_then(|val| val.previous_output.as_deref())
+            .map(|val| profile_file_path(val, profile))
+            .or_else(|| plain_list_output_path(outputs));
+        let previous_domains = if guard.is_some() || self.change_report_format.is_some() {
+            previous_output.as_ref().and_then(read_domain_list_file)
+        } else {
+            None
+        };
+        if let Some(guard_value) = guard {
+            match check_guard(guard_value, &set, previous_domains.as_ref()) {
+                Ok(()) => {}
+                Err(error) if self.force => {
+                    warn!("Writing outputs anyway, as `force` is set: {error}");
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        let change_report = self
+            .change_report_format
+            .zip(attribution.as_ref())
+            .and_then(|(format, attribution_value)| {
+                change_report(
+                    format,
+                    previous_output.as_deref(),
+                    previous_domains,
+                    (attribution_value, sources),
+                    &set,
+                )
+            });
+        let metadata = GenerationMetadata::new(
+            self.config_hash.clone(),
+            profile.map(ToString::to_string),
+            source_reports,
+            set.len(),
+        );
+        let staged_outputs =
+            stage_output_files(&mut set, outputs, &self.config, &metadata, &self.formats).await?;
+
+        Ok(StagedBuild {
+            report: BuildReport {
+                profile: profile.map(ToString::to_string),
+                domain_count: set.len(),
+                domains: set,
+                allowlist_removals,
+                sources: metadata.sources,
+                output_paths: outputs.iter().map(Output::path).collect(),
+                written_paths: Vec::new(),
+                change_report,
+            },
+            outputs: staged_outputs,
+        })
+    }
+
+    /// Fetch every source, then build and write the blocklist, or one blocklist for each of the
+    /// config's `[[profiles]]`.  Sources which fail are skipped, or replaced by their cached copy
+    /// if set up to, and reported in the output headers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source cache cannot be used, if a blocklist fails a `[guard]`
+    /// check (unless [`PipelineBuilder::force`] is set), or if an output cannot be written.  Every
+    /// blocklist is checked, and its outputs staged, before any output is replaced, so if one
+    /// profile fails a check, no profile's outputs are written.
+    pub async fn run(&self) -> Result<RunReport, Error> {
+        let Config {
+            cache,
+            filters,
+            outputs,
+            profiles,
+            distribution,
+            ..
+        } = &self.config;
+        let mut sources = sources_from_config(&self.config);
+
+        let mut source_cache = match cache {
+            Some(value) => Some(SourceCache::load(&value.directory)?),
+            None => None,
+        };
+        let default_outputs = default_outputs();
+        let mut staged_builds: Vec<StagedBuild> = Vec::new();
+        if let Some(profiles_value) = profiles {
+            // fetch each source once, and only if some profile uses it
+            sources.retain(|source| {
+                profiles_value
+                    .iter()
+                    .any(|profile| profile_selects(profile, source))
+            });
+            let fetched_sets = self
+                .client
+                .fetch_sets(&sources, self.concurrent_downloads, source_cache.as_mut())
+                .await;
+            if let Some(source_cache_value) = &source_cache {
+                source_cache_value.save()?;
+            }
+
+            for profile in profiles_value {
+                let mut set = DomainSet::new();
+                let mut attribution = self
+                    .change_report_format
+                    .map(|_| SourceAttribution::default());
+                let mut source_reports: Vec<SourceReport> = Vec::new();
+                for (index, (report, domains)) in fetched_sets.iter().enumerate() {
+                    if profile_selects(profile, &sources[index]) {
+                        attribute(attribution.as_mut(), domains, index);
+                        set.extend(domains.iter());
+                        source_reports.push(report.clone());
+                    }
+                }
+                let build = Build {
+                    profile: Some(&profile.name),
+                    filters: filters.iter().chain(profile.filters.iter()).collect(),
+                    outputs: &profile.outputs,
+                    set,
+                    attribution,
+                    source_reports,
+                };
+                staged_builds.push(self.stage_build(&sources, build).await?);
+            }
+        } else {
+            let mut set = DomainSet::new();
+            let mut attribution = self
+                .change_report_format
+                .map(|_| SourceAttribution::default());
+            let source_reports = self
+                .client
+                .domainlists(
+                    &sources,
+                    self.concurrent_downloads,
+                    source_cache.as_mut(),
+                    attribution.as_mut(),
+                    &mut set,
+                )
+                .await;
+            if let Some(source_cache_value) = &source_cache {
+                source_cache_value.save()?;
+            }
+
+            let build = Build {
+                profile: None,
+                filters: filters.iter().collect(),
+                outputs: outputs.as_deref().unwrap_or(&default_outputs),
+                set,
+                attribution,
+                source_reports,
+            };
+            staged_builds.push(self.stage_build(&sources, build).await?);
+        }
+
+        // every blocklist passed its checks, so its outputs can replace the previous ones
+        let builds = commit_builds(staged_builds).await?;
+        let distribution_paths = match distribution {
+            Some(value) => {
+                let written_paths: Vec<PathBuf> = builds
+                    .iter()
+                    .flat_map(|val| val.written_paths.iter().cloned())
+                    .collect();
+                write_distribution_files(&written_paths, value)?
+            }
+            None => Vec::new(),
+        };
+
+        Ok(RunReport {
+            builds,
+            distribution_paths,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use crate::{
+        Error, Pipeline, SourceType,
+        file_system::{Config, Guard, Output, OutputFormat, Profile, SourceConfig},
+    };
+
+    fn profile(name: &str, outputs: Vec<Output>) -> Profile {
+        Profile {
+            name: String::from(name),
+            sources: None,
+            categories: None,
+            filters: None,
+            outputs,
+        }
+    }
+
+    #[test]
+    fn build_gives_each_profile_its_own_default_output_paths() {
+        // arrange
+        let config = Config {
+            profiles: Some(vec![
+                profile("kids", vec![Output::new(OutputFormat::DomainList)]),
+                profile(
+                    "work",
+                    vec![
+                        Output::new(OutputFormat::DomainList),
+                        Output {
+                            path: Some(PathBuf::from("./work.rpz")),
+                            ..Output::new(OutputFormat::Rpz)
+                        },
+                    ],
+                ),
+            ]),
+            ..Config::default()
+        };
+
+        // act
+        let pipeline = Pipeline::from_config(config).build().unwrap();
+
+        // assert
+        let outcome: Vec<Vec<PathBuf>> = pipeline
+            .config()
+            .profiles
+            .iter()
+            .flatten()
+            .map(|val| val.outputs.iter().map(Output::path).collect())
+            .collect();
+        assert_eq!(
+            outcome,
+            [
+                vec![PathBuf::from("./domain-blocklist-kids.txt")],
+                vec![
+                    PathBuf::from("./domain-blocklist-work.txt"),
+                    PathBuf::from("./work.rpz")
+                ],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_writes_no_profile_outputs_when_a_later_profile_fails_a_check() {
+        // arrange
+        let mock_remote_server = MockServer::start().await;
+        Mock::given(path("/safe"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ads.example.com\n"))
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/broken"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("login.example.net\n"))
+            .mount(&mock_remote_server)
+            .await;
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let previous_content = "previous.example.com\n";
+        for name in ["kids.txt", "work.txt"] {
+            std::fs::write(temp_dir.join(name), previous_content).unwrap();
+        }
+        let source = |name: &str| SourceConfig {
+            name: Some(String::from(name)),
+            ..SourceConfig::new(
+                format!("{}/{name}", mock_remote_server.uri()),
+                SourceType::DomainList,
+            )
+        };
+        let profile_output = |name: &str| Output {
+            path: Some(temp_dir.join(name)),
+            ..Output::new(OutputFormat::DomainList)
+        };
+        let config = Config {
+            sources: Some(vec![source("safe"), source("broken")]),
+            profiles: Some(vec![
+                Profile {
+                    sources: Some(vec![String::from("safe")]),
+                    ..profile("kids", vec![profile_output("kids.txt")])
+                },
+                Profile {
+                    sources: Some(vec![String::from("broken")]),
+                    ..profile("work", vec![profile_output("work.txt")])
+                },
+            ]),
+            guard: Some(Guard {
+                max_change_percent: None,
+                canary_names: Some(vec![String::from("login.example.net")]),
+                previous_output: None,
+            }),
+            ..Config::default()
+        };
+
+        // act
+        let outcome = Pipeline::from_config(config)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(outcome, Error::Guard(_)));
+        for name in ["kids.txt", "work.txt"] {
+            assert_eq!(
+                std::fs::read_to_string(temp_dir.join(name)).unwrap(),
+                previous_content
+            );
+        }
+        let outcome_files = std::fs::read_dir(&temp_dir).unwrap().count();
+        assert_eq!(outcome_files, 2);
+    }
+}
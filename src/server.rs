@@ -0,0 +1,720 @@
+//! HTTP server for `serve --listen`, publishing the outputs of the latest good run, with
+//! `/status`, `/check` and `/metrics` endpoints.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fs,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
+
+use blocklist_generator::{
+    BuildReport, RunReport,
+    domain_set::DomainSet,
+    metadata::sha256_hex,
+    metrics::{RunCounters, run_metrics},
+};
+use flate2::{Compression, write::GzEncoder};
+use http_body_util::Full;
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::Bytes,
+    header::{self, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use log::{debug, warn};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use url::{Host, form_urlencoded};
+
+/// Output file, ready to serve.
+struct PublishedFile {
+    content: Bytes,
+    etag: String,
+    content_type: &'static str,
+
+    /// Gzip copy and its `ETag`, for files which are not already compressed
+    gzip: Option<(Bytes, String)>,
+}
+
+impl PublishedFile {
+    fn new(name: &str, content: Vec<u8>) -> Self {
+        let content_type = match Path::new(name).extension().and_then(|val| val.to_str()) {
+            Some("gz") => "application/gzip",
+            Some("zst") => "application/zstd",
+            Some("sig") => "application/octet-stream",
+            _ => "text/plain; charset=utf-8",
+        };
+
+        PublishedFile {
+            etag: format!("\"{}\"", sha256_hex(&content)),
+            content: Bytes::from(content),
+            content_type,
+            gzip: None,
+        }
+    }
+}
+
+/// Outcome of fetching a source, as reported by `/status`.
+#[derive(Serialize)]
+struct SourceStatus {
+    name: String,
+    url: String,
+    entries: usize,
+    status: String,
+}
+
+/// Blocklist built by the latest good run, as reported by `/status`.
+#[derive(Serialize)]
+struct BuildStatus {
+    profile: Option<String>,
+    domain_count: usize,
+
+    /// URL paths of the files written
+    files: Vec<String>,
+    sources: Vec<SourceStatus>,
+}
+
+/// Everything published from the latest good run.
+struct Published {
+    finished_at: String,
+    builds: Vec<BuildStatus>,
+
+    /// Domains in each blocklist, by profile
+    domains: Vec<(Option<String>, DomainSet)>,
+
+    /// Files, by URL path
+    files: BTreeMap<String, PublishedFile>,
+
+    /// Gauges for the run, in the Prometheus text format
+    metrics: String,
+}
+
+#[derive(Clone, Serialize)]
+struct LastRun {
+    finished_at: String,
+    succeeded: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatusBody<'a> {
+    last_run: Option<&'a LastRun>,
+    last_success: Option<&'a str>,
+    builds: &'a [BuildStatus],
+}
+
+#[derive(Serialize)]
+struct BuildCheck<'a> {
+    profile: Option<&'a str>,
+    blocked: bool,
+
+    /// Entry which blocks the domain: the domain itself, or the closest listed parent domain
+    listed_as: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CheckBody<'a> {
+    domain: &'a str,
+    blocked: bool,
+    builds: Vec<BuildCheck<'a>>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+fn format_timestamp(timestamp: jiff::Timestamp) -> String {
+    timestamp.strftime("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// URL path a written file is served on.
+fn url_path(path: &Path) -> String {
+    format!(
+        "/{}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    )
+}
+
+/// Outputs and status shared between `serve` runs and the HTTP server.
+#[derive(Default)]
+pub struct ServerState {
+    last_run: RwLock<Option<LastRun>>,
+    published: RwLock<Option<Arc<Published>>>,
+    counters: Mutex<RunCounters>,
+}
+
+impl ServerState {
+    /// Serve the files written by `run_report`, in place of those from the previous run.
+    pub fn publish(&self, run_report: RunReport) {
+        let finished_at = jiff::Timestamp::now();
+        let metrics = run_metrics(&run_report, finished_at);
+        self.counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record_success(&run_report);
+        let finished_at = format_timestamp(finished_at);
+        let RunReport {
+            builds,
+            distribution_paths,
+        } = run_report;
+        let mut files: BTreeMap<String, PublishedFile> = BTreeMap::new();
+        let written_paths = builds
+            .iter()
+            .flat_map(|val| val.written_paths.iter())
+            .chain(distribution_paths.iter());
+        for path in written_paths {
+            let name = url_path(path);
+            if files.contains_key(&name) {
+                warn!(
+                    "Not serving `{}`, as another output is already served on `{name}`",
+                    path.display()
+                );
+                continue;
+            }
+            match fs::read(path) {
+                Ok(value) => {
+                    let file = PublishedFile::new(&name, value);
+                    files.insert(name, file);
+                }
+                Err(error) => warn!("Not serving `{}`: {error}", path.display()),
+            }
+        }
+        let gzip_copies: Vec<(String, (Bytes, String))> = files
+            .iter()
+            .filter(|(_, val)| val.content_type.starts_with("text/"))
+            .map(|(name, val)| {
+                // reuse the copy written by `compress = ["gzip"]`, if there is one
+                let gzip = files.get(&format!("{name}.gz")).map_or_else(
+                    || {
+                        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                        let _ = encoder.write_all(&val.content);
+                        Bytes::from(encoder.finish().unwrap_or_default())
+                    },
+                    |gzip_file| gzip_file.content.clone(),
+                );
+                let etag = format!("\"{}-gzip\"", val.etag.trim_matches('"'));
+                (name.clone(), (gzip, etag))
+            })
+            .collect();
+        for (name, gzip) in gzip_copies {
+            if let Some(value) = files.get_mut(&name) {
+                value.gzip = Some(gzip);
+            }
+        }
+
+        let mut build_statuses = Vec::with_capacity(builds.len());
+        let mut domains = Vec::with_capacity(builds.len());
+        for build in builds {
+            let BuildReport {
+                profile,
+                domain_count,
+                domains: build_domains,
+                sources,
+                written_paths,
+                ..
+            } = build;
+            build_statuses.push(BuildStatus {
+                profile: profile.clone(),
+                domain_count,
+                files: written_paths.iter().map(|val| url_path(val)).collect(),
+                sources: sources
+                    .into_iter()
+                    .map(|val| SourceStatus {
+                        name: val.name,
+                        url: val.url,
+                        entries: val.entries,
+                        status: val.status.to_string(),
+                    })
+                    .collect(),
+            });
+            domains.push((profile, build_domains));
+        }
+        *self
+            .published
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(Published {
+            finished_at: finished_at.clone(),
+            builds: build_statuses,
+            domains,
+            files,
+            metrics,
+        }));
+        *self
+            .last_run
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(LastRun {
+            finished_at,
+            succeeded: true,
+            error: None,
+        });
+    }
+
+    /// Record a failed run.  The files from the last good run are still served.
+    pub fn record_failure(&self, error: &dyn std::error::Error) {
+        self.counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record_failure();
+        *self
+            .last_run
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(LastRun {
+            finished_at: format_timestamp(jiff::Timestamp::now()),
+            succeeded: false,
+            error: Some(error.to_string()),
+        });
+    }
+
+    fn published(&self) -> Option<Arc<Published>> {
+        self.published
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn respond<B>(&self, request: &Request<B>) -> Response<Full<Bytes>> {
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            let mut result = error_response(StatusCode::METHOD_NOT_ALLOWED, "Use GET or HEAD");
+            result
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+            return result;
+        }
+        match request.uri().path() {
+            "/status" => self.status(),
+            "/check" => self.check(request.uri().query().unwrap_or_default()),
+            "/metrics" => self.metrics(),
+            path => self.file(path, request),
+        }
+    }
+
+    fn status(&self) -> Response<Full<Bytes>> {
+        let last_run = self
+            .last_run
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let published = self.published();
+        let body = StatusBody {
+            last_run: last_run.as_ref(),
+            last_success: published.as_deref().map(|val| val.finished_at.as_str()),
+            builds: published.as_deref().map_or(&[], |val| &val.builds),
+        };
+
+        json_response(StatusCode::OK, &body)
+    }
+
+    fn metrics(&self) -> Response<Full<Bytes>> {
+        let mut content = self
+            .published()
+            .map(|val| val.metrics.clone())
+            .unwrap_or_default();
+        content.push_str(
+            &self
+                .counters
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .render(),
+        );
+
+        Response::builder()
+            .header(
+                header::CONTENT_TYPE,
+                "text/plain; version=0.0.4; charset=utf-8",
+            )
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Full::new(Bytes::from(content)))
+            .unwrap()
+    }
+
+    fn check(&self, query: &str) -> Response<Full<Bytes>> {
+        let Some(domain) = form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "domain")
+            .map(|(_, val)| val.trim().trim_end_matches('.').to_ascii_lowercase())
+        else {
+            return error_response(StatusCode::BAD_REQUEST, "Add a `domain` query parameter");
+        };
+        let Ok(host) = Host::parse(&domain) else {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("`{domain}` is not a valid domain name or IP address"),
+            );
+        };
+        let Some(published) = self.published() else {
+            return error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No blocklist has been built yet",
+            );
+        };
+        let builds: Vec<BuildCheck> = published
+            .domains
+            .iter()
+            .map(|(profile, domains)| {
+                let listed_as = if domains.contains(&host) {
+                    Some(host.to_string())
+                } else {
+                    domains.listed_parent(&host).map(|val| val.to_string())
+                };
+                BuildCheck {
+                    profile: profile.as_deref(),
+                    blocked: listed_as.is_some(),
+                    listed_as,
+                }
+            })
+            .collect();
+        let domain = host.to_string();
+        let body = CheckBody {
+            domain: &domain,
+            blocked: builds.iter().any(|val| val.blocked),
+            builds,
+        };
+
+        json_response(StatusCode::OK, &body)
+    }
+
+    fn file<B>(&self, path: &str, request: &Request<B>) -> Response<Full<Bytes>> {
+        let published = self.published();
+        let Some(file) = published.as_deref().and_then(|val| val.files.get(path)) else {
+            return error_response(StatusCode::NOT_FOUND, "No such file");
+        };
+        let (content, etag, gzip) = match &file.gzip {
+            Some((content, etag)) if accepts_gzip(request) => (content, etag, true),
+            _ => (&file.content, &file.etag, false),
+        };
+
+        let mut builder = Response::builder()
+            .header(header::ETAG, etag.as_str())
+            .header(header::CACHE_CONTROL, "no-cache");
+        if file.gzip.is_some() {
+            builder = builder.header(header::VARY, "accept-encoding");
+        }
+        if etag_matches(request, etag) {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Full::default())
+                .unwrap();
+        }
+        if gzip {
+            builder = builder.header(header::CONTENT_ENCODING, "gzip");
+        }
+
+        builder
+            .header(header::CONTENT_TYPE, file.content_type)
+            .body(Full::new(content.clone()))
+            .unwrap()
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Full<Bytes>> {
+    let mut content = serde_json::to_vec_pretty(body).unwrap_or_default();
+    content.push(b'\n');
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(content)))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    json_response(status, &ErrorBody { error: message })
+}
+
+/// Values of each `name` header, split at commas.
+fn header_list<'a, B>(request: &'a Request<B>, name: &header::HeaderName) -> Vec<&'a str> {
+    request
+        .headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .map(str::trim)
+        .collect()
+}
+
+/// Whether the client accepts gzip, with a non-zero quality value, or `*`.
+fn accepts_gzip<B>(request: &Request<B>) -> bool {
+    header_list(request, &header::ACCEPT_ENCODING)
+        .into_iter()
+        .any(|val| {
+            let mut parts = val.split(';').map(str::trim);
+            let coding = parts.next().unwrap_or_default();
+            let quality = parts
+                .find_map(|part| part.strip_prefix("q="))
+                .and_then(|part| part.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (coding.eq_ignore_ascii_case("gzip") || coding == "*") && quality > 0.0
+        })
+}
+
+fn etag_matches<B>(request: &Request<B>, etag: &str) -> bool {
+    header_list(request, &header::IF_NONE_MATCH)
+        .into_iter()
+        .any(|val| val == "*" || val.trim_start_matches("W/") == etag)
+}
+
+/// Serve HTTP requests on `listener` from `state`, until the task is dropped.
+pub async fn listen(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((value, _)) => value,
+            Err(error) => {
+                warn!("Failed to accept HTTP connection: {error}");
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = state.respond(&request);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            if let Err(error) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("HTTP connection closed: {error}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Read, sync::Arc, time::Duration};
+
+    use blocklist_generator::{
+        BuildReport, Pipeline, RunReport, SourceType,
+        domain_set::DomainSet,
+        fetch::{FetchStatus, SourceReport},
+        file_system::{Blocklists, Config, Output, OutputFormat, SourceConfig},
+    };
+    use flate2::read::GzDecoder;
+    use tokio::net::TcpListener;
+    use url::Host;
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+    use crate::server::{ServerState, listen};
+
+    /// Server on a local port, publishing a run which wrote `domain-blocklist.txt`, returning
+    /// its base URL.
+    async fn start_server(temp_dir: &assert_fs::TempDir) -> String {
+        let output_path = temp_dir.join("domain-blocklist.txt");
+        std::fs::write(&output_path, "ads.example.com\nexample.net\n").unwrap();
+        let domains: DomainSet = ["ads.example.com", "example.net"]
+            .iter()
+            .map(|val| Host::parse(val).unwrap())
+            .collect();
+        let state = Arc::new(ServerState::default());
+        state.publish(RunReport {
+            builds: vec![BuildReport {
+                profile: None,
+                domain_count: domains.len(),
+                domains,
+                allowlist_removals: 0,
+                sources: vec![SourceReport {
+                    name: String::from("Example"),
+                    url: String::from("https://example.com/domains.txt"),
+                    entries: 2,
+                    rejected: 0,
+                    bytes: 0,
+                    duration: Duration::ZERO,
+                    status: FetchStatus::Ok,
+                }],
+                output_paths: vec![output_path.clone()],
+                written_paths: vec![output_path],
+                change_report: None,
+            }],
+            distribution_paths: Vec::new(),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(listen(listener, state));
+
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn listen_serves_outputs_with_etags_and_gzip() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let base_url = start_server(&temp_dir).await;
+        let client = reqwest::Client::builder().no_gzip().build().unwrap();
+        let url = format!("{base_url}/domain-blocklist.txt");
+
+        // act
+        let outcome = client.get(&url).send().await.unwrap();
+        let etag = outcome.headers()["etag"].to_str().unwrap().to_string();
+        let outcome_body = outcome.text().await.unwrap();
+        let outcome_cached = client
+            .get(&url)
+            .header("if-none-match", &etag)
+            .send()
+            .await
+            .unwrap();
+        let outcome_gzip = client
+            .get(&url)
+            .header("accept-encoding", "br;q=1.0, gzip;q=0.8")
+            .send()
+            .await
+            .unwrap();
+        let outcome_gzip_encoding = outcome_gzip.headers()["content-encoding"].clone();
+        let outcome_gzip_etag = outcome_gzip.headers()["etag"].clone();
+        let mut outcome_gzip_body = String::new();
+        GzDecoder::new(&outcome_gzip.bytes().await.unwrap()[..])
+            .read_to_string(&mut outcome_gzip_body)
+            .unwrap();
+        let outcome_missing = client
+            .get(format!("{base_url}/missing.txt"))
+            .send()
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(outcome_body, "ads.example.com\nexample.net\n");
+        assert_eq!(outcome_cached.status(), 304);
+        assert_eq!(outcome_gzip_encoding, "gzip");
+        assert_ne!(outcome_gzip_etag, etag.as_str());
+        assert_eq!(outcome_gzip_body, "ads.example.com\nexample.net\n");
+        assert_eq!(outcome_missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn listen_reports_status_metrics_and_checks_domains() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let base_url = start_server(&temp_dir).await;
+        let client = reqwest::Client::new();
+        let get_json = async |path: &str| {
+            let response = client
+                .get(format!("{base_url}{path}"))
+                .send()
+                .await
+                .unwrap();
+            let status = response.status();
+            let body: serde_json::Value =
+                serde_json::from_str(&response.text().await.unwrap()).unwrap();
+            (status, body)
+        };
+
+        // act
+        let (_, outcome_status) = get_json("/status").await;
+        let (_, outcome_blocked) = get_json("/check?domain=Tracker.Ads.Example.com.").await;
+        let (_, outcome_allowed) = get_json("/check?domain=example.com").await;
+        let (outcome_missing_status, _) = get_json("/check").await;
+        let outcome_metrics = client
+            .get(format!("{base_url}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(outcome_status["last_run"]["succeeded"], true);
+        assert_eq!(outcome_status["builds"][0]["domain_count"], 2);
+        assert_eq!(
+            outcome_status["builds"][0]["files"][0],
+            "/domain-blocklist.txt"
+        );
+        assert_eq!(outcome_status["builds"][0]["sources"][0]["status"], "ok");
+        assert_eq!(outcome_blocked["domain"], "tracker.ads.example.com");
+        assert_eq!(outcome_blocked["blocked"], true);
+        assert_eq!(outcome_blocked["builds"][0]["listed_as"], "ads.example.com");
+        assert_eq!(outcome_allowed["blocked"], false);
+        assert_eq!(outcome_missing_status, 400);
+        assert!(
+            outcome_metrics.contains("blocklist_generator_source_entries{source=\"Example\"} 2\n")
+        );
+        assert!(
+            outcome_metrics.contains("blocklist_generator_runs_total{outcome=\"success\"} 1\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_in_source_urls_are_not_published() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let mock_remote_server = MockServer::start().await;
+        Mock::given(path("/hosts"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("0.0.0.0 ads.example.com\n"))
+            .mount(&mock_remote_server)
+            .await;
+        Mock::given(path("/domains"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("example.net\n"))
+            .mount(&mock_remote_server)
+            .await;
+        let base_uri = mock_remote_server.uri();
+        // as if interpolated from `${LIST_TOKEN}` and `${LIST_KEY}` in the config file
+        let hosts_url = format!("{base_uri}/hosts?token=s3cret");
+        let domains_url = format!("{base_uri}/domains?key=s3cret");
+        let output_path = temp_dir.join("blocklist.rpz");
+        let config = Config {
+            blocklists: Blocklists {
+                hosts_file_blocklist_urls: Vec::new(),
+                domain_blocklist_urls: vec![domains_url.clone()],
+            },
+            sources: Some(vec![SourceConfig::new(
+                hosts_url.clone(),
+                SourceType::HostsFile,
+            )]),
+            outputs: Some(vec![Output {
+                path: Some(output_path.clone()),
+                header: true,
+                ..Output::new(OutputFormat::Rpz)
+            }]),
+            url_templates: BTreeMap::from([
+                (hosts_url, format!("{base_uri}/hosts?token=${{LIST_TOKEN}}")),
+                (domains_url, format!("{base_uri}/domains?key=${{LIST_KEY}}")),
+            ]),
+            ..Config::default()
+        };
+        let run_report = Pipeline::from_config(config)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+        let state = Arc::new(ServerState::default());
+        state.publish(run_report);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(listen(listener, state));
+        let client = reqwest::Client::new();
+
+        // act
+        let outcome_output = std::fs::read_to_string(&output_path).unwrap();
+        let outcome_status = client
+            .get(format!("http://{address}/status"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let outcome_metrics = client
+            .get(format!("http://{address}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        // assert
+        for outcome in [&outcome_output, &outcome_status, &outcome_metrics] {
+            assert!(!outcome.contains("s3cret"));
+            assert!(outcome.contains("/hosts?token=${LIST_TOKEN}"));
+            assert!(outcome.contains("/domains?key=${LIST_KEY}"));
+        }
+        assert!(outcome_output.contains("ads.example.com"));
+    }
+}
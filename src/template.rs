@@ -0,0 +1,327 @@
+use minijinja::{Environment, Error as TemplateError, ErrorKind, UndefinedBehavior};
+use serde::Serialize;
+
+use crate::{
+    SourceType,
+    domain_set::DomainSet,
+    file_system::{Config, Filters, Guard, Schedule},
+};
+
+#[derive(Debug, Serialize)]
+pub struct TemplateSource<'a> {
+    pub name: &'a str,
+    pub url: &'a str,
+    pub format: &'a str,
+    pub category: Option<&'a str>,
+}
+
+/// `[[sources]]` entry, as templates see it.
+#[derive(Debug, Serialize)]
+pub struct TemplateSourceConfig<'a> {
+    pub name: Option<&'a str>,
+    pub url: &'a str,
+    pub format: &'a str,
+    pub category: Option<&'a str>,
+    pub enabled: bool,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateBlocklists<'a> {
+    pub hosts_file_blocklist_urls: Vec<&'a str>,
+    pub domain_blocklist_urls: Vec<&'a str>,
+}
+
+/// Config values available to templates.  Source URLs are as written, with any `${NAME}`
+/// references left in place, and source headers are left out, since outputs are published.
+#[derive(Debug, Serialize)]
+pub struct TemplateConfig<'a> {
+    pub blocklists: TemplateBlocklists<'a>,
+    pub sources: Vec<TemplateSourceConfig<'a>>,
+    pub filters: Option<&'a Filters>,
+    pub guard: Option<&'a Guard>,
+    pub schedule: Option<&'a Schedule>,
+}
+
+impl<'a> TemplateConfig<'a> {
+    fn new(config: &'a Config) -> Self {
+        let display_urls = |urls: &'a [String]| -> Vec<&'a str> {
+            urls.iter().map(|val| config.display_url(val)).collect()
+        };
+
+        TemplateConfig {
+            blocklists: TemplateBlocklists {
+                hosts_file_blocklist_urls: display_urls(
+                    &config.blocklists.hosts_file_blocklist_urls,
+                ),
+                domain_blocklist_urls: display_urls(&config.blocklists.domain_blocklist_urls),
+            },
+            sources: config
+                .sources
+                .iter()
+                .flatten()
+                .map(|val| TemplateSourceConfig {
+                    name: val.name.as_deref(),
+                    url: config.display_url(&val.url),
+                    format: val.format.config_name(),
+                    category: val.category.as_deref(),
+                    enabled: val.enabled,
+                    timeout: val.timeout,
+                })
+                .collect(),
+            filters: config.filters.as_ref(),
+            guard: config.guard.as_ref(),
+            schedule: config.schedule.as_ref(),
+        }
+    }
+}
+
+/// Values available to user-supplied output templates.
+#[derive(Debug, Serialize)]
+pub struct TemplateContext<'a> {
+    pub domains: Vec<String>,
+    pub domain_count: usize,
+
+    /// UTC timestamp in RFC 3339 format
+    pub generated_at: String,
+    pub sources: Vec<TemplateSource<'a>>,
+    pub config: TemplateConfig<'a>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(blocklist_domains: &DomainSet, config: &'a Config, generated_at: String) -> Self {
+        let domains: Vec<String> = blocklist_domains
+            .iter()
+            .map(|val| val.to_string())
+            .collect();
+        let named_sources = config
+            .sources
+            .iter()
+            .flatten()
+            .filter(|val| val.enabled)
+            .map(|val| TemplateSource {
+                name: config.source_name(val),
+                url: config.display_url(&val.url),
+                format: val.format.config_name(),
+                category: val.category.as_deref(),
+            });
+        let hosts_file_sources = config
+            .blocklists
+            .hosts_file_blocklist_urls
+            .iter()
+            .map(|val| TemplateSource {
+                name: config.display_url(val),
+                url: config.display_url(val),
+                format: SourceType::HostsFile.config_name(),
+                category: None,
+            });
+        let domain_list_sources =
+            config
+                .blocklists
+                .domain_blocklist_urls
+                .iter()
+                .map(|val| TemplateSource {
+                    name: config.display_url(val),
+                    url: config.display_url(val),
+                    format: SourceType::DomainList.config_name(),
+                    category: None,
+                });
+
+        TemplateContext {
+            domain_count: domains.len(),
+            domains,
+            generated_at,
+            sources: named_sources
+                .chain(hosts_file_sources)
+                .chain(domain_list_sources)
+                .collect(),
+            config: TemplateConfig::new(config),
+        }
+    }
+}
+
+/// `ads.example.com` becomes `com.example.ads`
+fn reversed_filter(domain: &str) -> String {
+    domain.rsplit('.').collect::<Vec<&str>>().join(".")
+}
+
+fn punycode_filter(domain: &str) -> Result<String, TemplateError> {
+    idna::domain_to_ascii(domain).map_err(|_| {
+        TemplateError::new(
+            ErrorKind::InvalidOperation,
+            format!("`{domain}` cannot be converted to punycode"),
+        )
+    })
+}
+
+fn unicode_filter(domain: &str) -> Result<String, TemplateError> {
+    let (result, outcome) = idna::domain_to_unicode(domain);
+    match outcome {
+        Ok(()) => Ok(result),
+        Err(_) => Err(TemplateError::new(
+            ErrorKind::InvalidOperation,
+            format!("`{domain}` cannot be converted to Unicode"),
+        )),
+    }
+}
+
+fn wildcard_filter(domain: &str) -> String {
+    format!("*.{domain}")
+}
+
+fn environment() -> Environment<'static> {
+    let mut result = Environment::new();
+    result.set_undefined_behavior(UndefinedBehavior::Strict);
+    result.set_keep_trailing_newline(true);
+    result.add_filter("reversed", reversed_filter);
+    result.add_filter("punycode", punycode_filter);
+    result.add_filter("unicode", unicode_filter);
+    result.add_filter("wildcard", wildcard_filter);
+
+    result
+}
+
+/// Render the Jinja-style `template_source` at runtime.  `name` only appears in error messages.
+pub fn render(
+    name: &str,
+    template_source: &str,
+    context: &TemplateContext,
+) -> Result<String, TemplateError> {
+    let environment = environment();
+    let template = environment.template_from_named_str(name, template_source)?;
+    template.render(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use url::Host;
+
+    use crate::{
+        SourceType,
+        domain_set::DomainSet,
+        file_system::{Blocklists, Config, SourceConfig},
+        template::{TemplateContext, render, reversed_filter, unicode_filter},
+    };
+
+    fn sample_config() -> Config {
+        Config {
+            blocklists: Blocklists {
+                hosts_file_blocklist_urls: vec![String::from("https://example.com/hosts")],
+                domain_blocklist_urls: vec![String::from("https://example.com/domains.txt")],
+            },
+            sources: None,
+            source_options: None,
+            cache: None,
+            filters: None,
+            outputs: None,
+            profiles: None,
+            guard: None,
+            distribution: None,
+            schedule: None,
+            url_templates: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn reversed_filter_reverses_domain_labels() {
+        // act
+        let outcome = reversed_filter("ads.example.com");
+
+        // assert
+        assert_eq!(outcome, "com.example.ads");
+    }
+
+    #[test]
+    fn unicode_filter_decodes_punycode_domain() {
+        // act
+        let outcome = unicode_filter("xn--bcher-kva.example").unwrap();
+
+        // assert
+        assert_eq!(outcome, "bücher.example");
+    }
+
+    #[test]
+    fn render_provides_expected_variables_and_filters() {
+        // arrange
+        let config = sample_config();
+        let domains: DomainSet = [
+            Host::parse("ads.example.com").unwrap(),
+            Host::parse("bücher.example").unwrap(),
+        ]
+        .into_iter()
+        .collect();
+        let context = TemplateContext::new(&domains, &config, String::from("2026-01-01T00:00:00Z"));
+        let template_source = r"# {{ domain_count }} domains, generated {{ generated_at }}
+{% for source in sources -%}
+# {{ source.format }}: {{ source.url }}
+{% endfor -%}
+{% for domain in domains -%}
+{{ domain }} {{ domain | reversed }} {{ domain | wildcard }} {{ domain | unicode }}
+{% endfor -%}
+";
+
+        // act
+        let outcome = render("test", template_source, &context).unwrap();
+
+        // assert
+        insta::assert_snapshot!(outcome);
+    }
+
+    #[test]
+    fn render_returns_error_for_undefined_variable() {
+        // arrange
+        let config = sample_config();
+        let context = TemplateContext::new(
+            &DomainSet::new(),
+            &config,
+            String::from("2026-01-01T00:00:00Z"),
+        );
+
+        // act
+        let outcome = render("test", "{{ not_a_variable }}", &context).unwrap_err();
+
+        // assert
+        assert_eq!(outcome.kind(), minijinja::ErrorKind::UndefinedError);
+    }
+
+    #[test]
+    fn render_does_not_provide_source_headers_or_secrets() {
+        // arrange
+        let resolved_url = "https://lists.example.com/hosts?token=s3cret";
+        let config = Config {
+            sources: Some(vec![SourceConfig {
+                headers: Some(BTreeMap::from([(
+                    String::from("Authorization"),
+                    String::from("Bearer s3cret"),
+                )])),
+                ..SourceConfig::new(resolved_url, SourceType::HostsFile)
+            }]),
+            url_templates: BTreeMap::from([(
+                String::from(resolved_url),
+                String::from("https://lists.example.com/hosts?token=${LIST_TOKEN}"),
+            )]),
+            ..sample_config()
+        };
+        let context = TemplateContext::new(
+            &DomainSet::new(),
+            &config,
+            String::from("2026-01-01T00:00:00Z"),
+        );
+
+        // act
+        let outcome = render("test", "{{ config.sources[0].url }}", &context).unwrap();
+        let outcome_headers =
+            render("test", "{{ config.sources[0].headers }}", &context).unwrap_err();
+        let outcome_config = render("test", "{{ config }}", &context).unwrap();
+
+        // assert
+        assert_eq!(
+            outcome,
+            "https://lists.example.com/hosts?token=${LIST_TOKEN}"
+        );
+        assert_eq!(outcome_headers.kind(), minijinja::ErrorKind::UndefinedError);
+        assert!(!outcome_config.contains("s3cret"));
+    }
+}
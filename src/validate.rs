@@ -0,0 +1,498 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    path::Path,
+};
+
+use miette::Diagnostic;
+use url::{Host, Url};
+
+use crate::{
+    file_system::{
+        Config, Filters, HookCheck, Output, OutputFormat, default_outputs, plain_list_output_path,
+    },
+    format::FormatRegistry,
+    profile_file_path,
+};
+
+/// Problem found in a config which parsed, but would not work as intended.
+#[derive(Debug, Diagnostic, thiserror::Error)]
+#[error("`{key}`: {message}")]
+pub struct ConfigIssue {
+    key: String,
+    message: String,
+}
+
+impl ConfigIssue {
+    fn new(key: String, message: impl Into<String>) -> Self {
+        ConfigIssue {
+            key,
+            message: message.into(),
+        }
+    }
+}
+
+/// Every problem found in a config file.
+#[derive(Debug, Diagnostic, thiserror::Error)]
+#[error("Config file `{path}` is not valid ({} problems found)", issues.len())]
+#[diagnostic(code(config::validate))]
+pub struct ConfigValidationError {
+    path: String,
+    #[related]
+    issues: Vec<ConfigIssue>,
+}
+
+/// Source URLs, with the config key which sets each one.
+fn source_urls(config: &Config) -> Vec<(String, &str)> {
+    let Config {
+        blocklists,
+        sources,
+        ..
+    } = config;
+    let source_entries = sources
+        .iter()
+        .flatten()
+        .enumerate()
+        .flat_map(|(index, val)| {
+            let signature_url = val
+                .options
+                .minisign_signature_url
+                .as_deref()
+                .map(|url| (format!("sources[{index}].minisign_signature_url"), url));
+            [(format!("sources[{index}].url"), val.url.as_str())]
+                .into_iter()
+                .chain(signature_url)
+        });
+    let hosts_file_entries =
+        blocklists
+            .hosts_file_blocklist_urls
+            .iter()
+            .enumerate()
+            .map(|(index, val)| {
+                (
+                    format!("blocklists.hosts_file_blocklist_urls[{index}]"),
+                    val.as_str(),
+                )
+            });
+    let domain_list_entries =
+        blocklists
+            .domain_blocklist_urls
+            .iter()
+            .enumerate()
+            .map(|(index, val)| {
+                (
+                    format!("blocklists.domain_blocklist_urls[{index}]"),
+                    val.as_str(),
+                )
+            });
+
+    source_entries
+        .chain(hosts_file_entries)
+        .chain(domain_list_entries)
+        .collect()
+}
+
+fn check_sources(config: &Config, formats: &FormatRegistry, issues: &mut Vec<ConfigIssue>) {
+    let mut first_keys: HashMap<&str, String> = HashMap::new();
+    for (key, url) in source_urls(config) {
+        match Url::parse(url) {
+            Ok(value) if matches!(value.scheme(), "http" | "https") => {}
+            Ok(value) => issues.push(ConfigIssue::new(
+                key.clone(),
+                format!(
+                    "`{url}` uses `{}`, but only HTTP(S) URLs are fetched",
+                    value.scheme()
+                ),
+            )),
+            Err(error) => {
+                issues.push(ConfigIssue::new(
+                    key.clone(),
+                    format!("`{url}` is not a valid URL ({error})"),
+                ));
+            }
+        }
+        if key.ends_with("minisign_signature_url") {
+            continue;
+        }
+        if let Some(first_key) = first_keys.get(url) {
+            issues.push(ConfigIssue::new(
+                key,
+                format!("`{url}` is already listed as a source, in `{first_key}`"),
+            ));
+        } else {
+            first_keys.insert(url, key);
+        }
+    }
+
+    let mut first_indices: HashMap<&str, usize> = HashMap::new();
+    for (index, source) in config.sources.iter().flatten().enumerate() {
+        let format = source.format.config_name();
+        if formats.parser(format).is_none() {
+            issues.push(ConfigIssue::new(
+                format!("sources[{index}].format"),
+                format!("there is no parser for the `{format}` format"),
+            ));
+        }
+        let Some(name) = source.name.as_deref() else {
+            continue;
+        };
+        if let Some(first_index) = first_indices.get(name) {
+            issues.push(ConfigIssue::new(
+                format!("sources[{index}].name"),
+                format!("`{name}` is already the name of `sources[{first_index}]`"),
+            ));
+        } else {
+            first_indices.insert(name, index);
+        }
+    }
+}
+
+fn check_allowed_names(filters: Option<&Filters>, key: &str, issues: &mut Vec<ConfigIssue>) {
+    let allowed_names = filters.and_then(|val| val.allowed_names.as_ref());
+    for (index, name) in allowed_names.into_iter().flatten().enumerate() {
+        if Host::parse(name).is_err() {
+            issues.push(ConfigIssue::new(
+                format!("{key}.allowed_names[{index}]"),
+                format!("`{name}` is not a valid domain name or IP address"),
+            ));
+        }
+    }
+}
+
+/// Reason `path` cannot be written, if any.  Existing files are opened for appending, which
+/// leaves them unchanged.
+fn unwritable_reason(path: &Path) -> Option<String> {
+    match fs::metadata(path) {
+        Ok(value) if value.is_dir() => return Some(String::from("is a directory")),
+        Ok(_) => {
+            return OpenOptions::new()
+                .append(true)
+                .open(path)
+                .err()
+                .map(|error| format!("cannot be opened for writing ({error})"));
+        }
+        Err(_) => {}
+    }
+    let directory = match path.parent() {
+        Some(value) if !value.as_os_str().is_empty() => value,
+        _ => Path::new("."),
+    };
+    match fs::metadata(directory) {
+        Ok(value) if !value.is_dir() => Some(format!(
+            "is in `{}`, which is not a directory",
+            directory.display()
+        )),
+        Ok(value) if value.permissions().readonly() => Some(format!(
+            "is in `{}`, which is read-only",
+            directory.display()
+        )),
+        Ok(_) => None,
+        Err(_) => Some(format!(
+            "is in `{}`, which does not exist",
+            directory.display()
+        )),
+    }
+}
+
+fn check_outputs(
+    outputs: &[Output],
+    key: &str,
+    formats: &FormatRegistry,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    for (index, output) in outputs.iter().enumerate() {
+        let format = output.format.config_name();
+        if formats.writer(format).is_none() {
+            issues.push(ConfigIssue::new(
+                format!("{key}[{index}].format"),
+                format!("there is no writer for the `{format}` format"),
+            ));
+        }
+        let path = output.path();
+        if let Some(reason) = unwritable_reason(&path) {
+            issues.push(ConfigIssue::new(
+                format!("{key}[{index}].path"),
+                format!("`{}` {reason}", path.display()),
+            ));
+        }
+        check_hooks(output, &format!("{key}[{index}].hooks"), issues);
+    }
+}
+
+fn check_hooks(output: &Output, key: &str, issues: &mut Vec<ConfigIssue>) {
+    for (index, hook) in output.hooks.iter().enumerate() {
+        let message = match (&hook.command, hook.check) {
+            (Some(_), Some(_)) | (None, None) => Some("set one of `command` or `check`"),
+            (Some(command), None) if command.is_empty() => Some("`command` names no program"),
+            (None, Some(HookCheck::UnboundCheckconf)) if output.format != OutputFormat::Unbound => {
+                Some("`unbound-checkconf` only checks `unbound` outputs")
+            }
+            (None, Some(HookCheck::NamedCheckzone)) if output.format != OutputFormat::Rpz => {
+                Some("`named-checkzone` only checks `rpz` outputs")
+            }
+            _ if hook.timeout == Some(0) => Some("`timeout` must be at least 1 second"),
+            _ => None,
+        };
+        if let Some(value) = message {
+            issues.push(ConfigIssue::new(format!("{key}[{index}]"), value));
+        }
+    }
+}
+
+/// Flag a `max_change_percent` check with no previous output to read, so it would never run.
+/// `output_sets` are the outputs written, with the profile writing them, if any.
+fn check_guard(
+    config: &Config,
+    output_sets: &[(Option<&str>, &[Output])],
+    issues: &mut Vec<ConfigIssue>,
+) {
+    let Some(guard) = &config.guard else {
+        return;
+    };
+    if guard.max_change_percent.is_none() {
+        return;
+    }
+    for (profile, outputs) in output_sets {
+        let profile_note = profile.map_or_else(String::new, |val| format!(" for profile `{val}`"));
+        let Some(previous_output) = &guard.previous_output else {
+            if plain_list_output_path(outputs).is_none() {
+                issues.push(ConfigIssue::new(
+                    String::from("guard.max_change_percent"),
+                    format!(
+                        "there is no `previous_output`, or `domain-list`, `pihole` or `blocky` \
+                        output{profile_note}, to compare against, so the check never runs"
+                    ),
+                ));
+            }
+            continue;
+        };
+        let path = profile_file_path(previous_output, *profile);
+        // an output of this run is read back on the next, so need not exist yet
+        let is_output = outputs.iter().any(|val| match (&val.path, profile) {
+            (None, Some(_)) => profile_file_path(&val.path(), *profile) == path,
+            _ => val.path() == path,
+        });
+        if !is_output && fs::File::open(&path).is_err() {
+            issues.push(ConfigIssue::new(
+                String::from("guard.previous_output"),
+                format!(
+                    "`{}`{profile_note} cannot be read, so the `max_change_percent` check never \
+                    runs",
+                    path.display()
+                ),
+            ));
+        }
+    }
+}
+
+/// Problems in `config` which parsing does not catch: invalid source URLs, sources listed more
+/// than once, formats missing from `formats`, invalid allowed names, output paths which cannot
+/// be written, hooks which would not run, a change check with no previous output and a zero
+/// `serve` interval.
+#[must_use]
+pub fn config_issues(config: &Config, formats: &FormatRegistry) -> Vec<ConfigIssue> {
+    let mut result = Vec::new();
+    check_sources(config, formats, &mut result);
+    check_allowed_names(config.filters.as_ref(), "filters", &mut result);
+    let default_outputs = default_outputs();
+    let mut output_sets: Vec<(Option<&str>, &[Output])> = Vec::new();
+    if let Some(profiles_value) = &config.profiles {
+        for (index, profile) in profiles_value.iter().enumerate() {
+            check_allowed_names(
+                profile.filters.as_ref(),
+                &format!("profiles[{index}].filters"),
+                &mut result,
+            );
+            check_outputs(
+                &profile.outputs,
+                &format!("profiles[{index}].outputs"),
+                formats,
+                &mut result,
+            );
+            output_sets.push((Some(&profile.name), &profile.outputs));
+        }
+    } else {
+        let (key, outputs) = match &config.outputs {
+            Some(value) => ("outputs", value.as_slice()),
+            None => ("outputs (default)", default_outputs.as_slice()),
+        };
+        check_outputs(outputs, key, formats, &mut result);
+        output_sets.push((None, outputs));
+    }
+    check_guard(config, &output_sets, &mut result);
+    if config
+        .schedule
+        .as_ref()
+        .is_some_and(|val| val.interval == Some(0))
+    {
+        result.push(ConfigIssue::new(
+            String::from("schedule.interval"),
+            "must be at least 1 second",
+        ));
+    }
+
+    result
+}
+
+/// Check `config`, loaded from `config_path` for use with `formats`, reporting every problem
+/// found.
+///
+/// # Errors
+///
+/// Returns an error listing each problem, if [`config_issues`] finds any.
+pub fn check<P: AsRef<Path>>(
+    config_path: P,
+    config: &Config,
+    formats: &FormatRegistry,
+) -> Result<(), ConfigValidationError> {
+    let issues = config_issues(config, formats);
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    Err(ConfigValidationError {
+        path: config_path.as_ref().display().to_string(),
+        issues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{
+        file_system::get_config_from_file, format::FormatRegistry, validate::config_issues,
+    };
+
+    #[test]
+    fn config_issues_reports_each_problem() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let output_path = temp_dir.join("missing-directory/domain-blocklist.txt");
+        let config_content = format!(
+            r#"[blocklists]
+domain_blocklist_urls = ["https://example.com/domains.txt", "not a url"]
+
+[[sources]]
+name = "Example"
+url = "https://example.com/domains.txt"
+format = "domain-list"
+
+[[sources]]
+name = "Example"
+url = "ftp://example.com/hosts"
+format = "hosts-file"
+
+[filters]
+allowed_names = ["example.com", "bad name.example.com"]
+
+[[outputs]]
+format = "domain-list"
+path = "{}"
+
+[[outputs]]
+format = "dnsmasq"
+path = "{}"
+
+[[outputs.hooks]]
+check = "named-checkzone"
+
+[[outputs.hooks]]
+command = ["unbound-control", "reload"]
+check = "unbound-checkconf"
+
+[schedule]
+interval = 0
+"#,
+            output_path.display(),
+            temp_dir.join("dnsmasq.conf").display()
+        );
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str(&config_content);
+        let config = get_config_from_file(temp_dir.join("blocklist-generator.toml")).unwrap();
+
+        // act
+        let outcome: Vec<String> = config_issues(&config, &FormatRegistry::default())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        // assert
+        assert_eq!(
+            outcome,
+            vec![
+                String::from(
+                    "`sources[1].url`: `ftp://example.com/hosts` uses `ftp`, but only HTTP(S) URLs are fetched"
+                ),
+                String::from(
+                    "`blocklists.domain_blocklist_urls[0]`: `https://example.com/domains.txt` is already listed as a source, in `sources[0].url`"
+                ),
+                String::from(
+                    "`blocklists.domain_blocklist_urls[1]`: `not a url` is not a valid URL (relative URL without a base)"
+                ),
+                String::from("`sources[1].name`: `Example` is already the name of `sources[0]`"),
+                String::from(
+                    "`filters.allowed_names[1]`: `bad name.example.com` is not a valid domain name or IP address"
+                ),
+                format!(
+                    "`outputs[0].path`: `{}` is in `{}`, which does not exist",
+                    output_path.display(),
+                    temp_dir.join("missing-directory").display()
+                ),
+                String::from("`outputs[1].format`: there is no writer for the `dnsmasq` format"),
+                String::from("`outputs[1].hooks[0]`: `named-checkzone` only checks `rpz` outputs"),
+                String::from("`outputs[1].hooks[1]`: set one of `command` or `check`"),
+                String::from("`schedule.interval`: must be at least 1 second"),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_issues_reports_change_check_with_no_previous_output() {
+        // arrange
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let config_content = format!(
+            r#"[blocklists]
+domain_blocklist_urls = ["https://example.com/domains.txt"]
+
+[guard]
+max_change_percent = 20
+
+[[profiles]]
+name = "family"
+
+[[profiles.outputs]]
+format = "rpz"
+path = "{}"
+
+[[profiles]]
+name = "kids"
+
+[[profiles.outputs]]
+format = "domain-list"
+"#,
+            temp_dir.join("blocklist.rpz").display()
+        );
+        let _ = temp_dir
+            .child("blocklist-generator.toml")
+            .write_str(&config_content);
+        let config = get_config_from_file(temp_dir.join("blocklist-generator.toml")).unwrap();
+
+        // act
+        let outcome: Vec<String> = config_issues(&config, &FormatRegistry::default())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        // assert
+        assert_eq!(
+            outcome,
+            vec![String::from(
+                "`guard.max_change_percent`: there is no `previous_output`, or `domain-list`, \
+                `pihole` or `blocky` output for profile `family`, to compare against, so the \
+                check never runs"
+            )]
+        );
+    }
+}
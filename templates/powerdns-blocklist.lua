@@ -0,0 +1,11 @@
+-- PowerDNS Recursor blocklist.  Load with the `lua-dns-script` setting.
+blocklist = newDS()
+
+{{ domain_chunks }}
+function preresolve(dq)
+  if blocklist:check(dq.qname) then
+    dq.rcode = pdns.NXDOMAIN
+    return true
+  end
+  return false
+end